@@ -1,3 +1,6 @@
 fn main() {
-    embuild::espidf::sysenv::output();
+    // The simulator builds for the host, where there's no ESP-IDF to link against.
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("espidf") {
+        embuild::espidf::sysenv::output();
+    }
 }
@@ -0,0 +1,316 @@
+//! IMA ADPCM, four bits a sample, laid out in blocks as WAV files have it.
+//!
+//! Each block starts with a header holding its first sample and the step index to carry on
+//! from, so decoding can start at any block and errors never last past one. The rest of the
+//! block is two samples a byte, low nibble first. Only mono is supported, which is all the
+//! speaker mixes. Shared with `util/sound-pack`, which encodes the sounds.
+
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// 505 samples a block, about 32 ms at 16 kHz, the usual size for mono.
+pub const BLOCK_ALIGN: usize = 256;
+const HEADER_BYTES: usize = 4;
+
+const STEPS: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408,
+    449, 494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066,
+    2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630,
+    9493, 10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794,
+    32767,
+];
+
+/// How far each nibble moves the step index, by its magnitude bits.
+const INDEX_STEPS: [i32; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+
+/// The predictor and step index that both ends keep in step.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct State {
+    predictor: i32,
+    index: usize,
+}
+
+impl State {
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let step = STEPS[self.index];
+        let mut difference = step >> 3;
+        if nibble & 1 != 0 {
+            difference += step >> 2;
+        }
+        if nibble & 2 != 0 {
+            difference += step >> 1;
+        }
+        if nibble & 4 != 0 {
+            difference += step;
+        }
+        if nibble & 8 != 0 {
+            difference = -difference;
+        }
+        self.predictor = (self.predictor + difference).clamp(i16::MIN as i32, i16::MAX as i32);
+        self.index = (self.index as i32 + INDEX_STEPS[(nibble & 7) as usize]).clamp(0, 88) as usize;
+        self.predictor as i16
+    }
+
+    fn encode(&mut self, sample: i16) -> u8 {
+        let mut difference = sample as i32 - self.predictor;
+        let mut nibble = 0;
+        if difference < 0 {
+            nibble = 8;
+            difference = -difference;
+        }
+        let mut step = STEPS[self.index];
+        for bit in [4, 2, 1] {
+            if difference >= step {
+                nibble |= bit;
+                difference -= step;
+            }
+            step >>= 1;
+        }
+        // Decode what was just encoded, so the predictor follows the decoder's rather than
+        // the input's.
+        self.decode(nibble);
+        nibble
+    }
+}
+
+/// Where decoding has got to in an `Adpcm` clip. It's kept apart from the clip so that a
+/// sound can own both.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Position {
+    sample: usize,
+    state: State,
+}
+
+/// The encoded blocks. Sounds built into the firmware borrow theirs from flash, the rest are
+/// shared between everything playing them.
+#[derive(Clone, Debug)]
+pub enum Data {
+    Static(&'static [u8]),
+    Shared(Arc<[u8]>),
+}
+
+impl Deref for Data {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Data::Static(data) => data,
+            Data::Shared(data) => data,
+        }
+    }
+}
+
+/// Mono IMA ADPCM audio.
+#[derive(Clone, Debug)]
+pub struct Adpcm {
+    pub sample_rate: u32,
+    pub block_align: usize,
+    /// How many samples the blocks hold. The last block is usually only partly used.
+    pub samples: usize,
+    pub data: Data,
+}
+
+impl Adpcm {
+    pub fn encode(samples: &[i16], sample_rate: u32, block_align: usize) -> Self {
+        let samples_per_block = samples_per_block(block_align);
+        let mut data = Vec::with_capacity(samples.len() / 2 + block_align);
+        let mut state = State {
+            predictor: 0,
+            index: first_index(&samples[..samples.len().min(samples_per_block)]),
+        };
+        for block in samples.chunks(samples_per_block) {
+            // The header's sample is stored whole, and the step index carries on.
+            state.predictor = block[0] as i32;
+            data.extend_from_slice(&block[0].to_le_bytes());
+            data.push(state.index as u8);
+            data.push(0);
+            for pair in block[1..].chunks(2) {
+                let low = state.encode(pair[0]);
+                let high = pair.get(1).map_or(0, |sample| state.encode(*sample));
+                data.push(low | (high << 4));
+            }
+        }
+        Self {
+            sample_rate,
+            block_align,
+            samples: samples.len(),
+            data: Data::Shared(data.into()),
+        }
+    }
+
+    pub fn samples_per_block(&self) -> usize {
+        samples_per_block(self.block_align)
+    }
+
+    /// The next sample from `position`, or `None` at the end of the clip.
+    pub fn next_sample(&self, position: &mut Position) -> Option<i16> {
+        if position.sample >= self.samples {
+            return None;
+        }
+        let samples_per_block = self.samples_per_block();
+        let block = &self.data[position.sample / samples_per_block * self.block_align..];
+        let sample = match position.sample % samples_per_block {
+            0 => {
+                let first = i16::from_le_bytes([block[0], block[1]]);
+                position.state = State {
+                    predictor: first as i32,
+                    index: (block[2] as usize).min(STEPS.len() - 1),
+                };
+                first
+            },
+            in_block => {
+                let byte = block[HEADER_BYTES + (in_block - 1) / 2];
+                let nibble = if in_block % 2 == 1 { byte & 0x0F } else { byte >> 4 };
+                position.state.decode(nibble)
+            },
+        };
+        position.sample += 1;
+        Some(sample)
+    }
+
+    /// The whole clip as PCM.
+    pub fn decode(&self) -> Vec<i16> {
+        let mut position = Position::default();
+        std::iter::from_fn(|| self.next_sample(&mut position)).collect()
+    }
+}
+
+/// The step index to start encoding `block` from that follows it most closely. Working up from
+/// the smallest step would smear the start of the sound, where it's usually loudest.
+fn first_index(block: &[i16]) -> usize {
+    let Some((first, rest)) = block.split_first() else {
+        return 0;
+    };
+    let error = |index| {
+        let mut state = State { predictor: *first as i32, index };
+        rest.iter()
+            .map(|sample| {
+                state.encode(*sample);
+                (*sample as i64 - state.predictor as i64).pow(2)
+            })
+            .sum::<i64>()
+    };
+    (0..STEPS.len()).min_by_key(|index| error(*index)).unwrap_or(0)
+}
+
+pub fn samples_per_block(block_align: usize) -> usize {
+    (block_align - HEADER_BYTES) * 2 + 1
+}
+
+/// How many samples `bytes` of blocks can hold, counting a partial last block.
+pub fn samples_in(bytes: usize, block_align: usize) -> usize {
+    let partial = bytes % block_align;
+    let partial_samples = if partial >= HEADER_BYTES { (partial - HEADER_BYTES) * 2 + 1 } else { 0 };
+    bytes / block_align * samples_per_block(block_align) + partial_samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(samples: usize, amplitude: f64) -> Vec<i16> {
+        (0..samples)
+            .map(|i| (amplitude * (i as f64 * 2.0 * std::f64::consts::PI * 440.0 / 16000.0).sin()) as i16)
+            .collect()
+    }
+
+    /// How far above the compression noise `original` is, in decibels.
+    fn snr(original: &[i16], decoded: &[i16]) -> f64 {
+        let (signal, noise) = original.iter().zip(decoded).fold((0.0, 0.0), |(signal, noise), (a, b)| {
+            let (a, b) = (*a as f64, *b as f64);
+            (signal + a * a, noise + (a - b) * (a - b))
+        });
+        10.0 * (signal / noise.max(1.0)).log10()
+    }
+
+    #[test]
+    fn matches_the_reference_codec() {
+        // From Python's audioop.lin2adpcm and adpcm2lin, the IMA/DVI reference algorithm.
+        let input = [
+            0, 1000, 3000, 6000, 8000, 6000, 2000, -2000, -8000, -16000, -12000, -4000, 0, 100, -100, 32767,
+            -32768, 0,
+        ];
+        let nibbles = [0, 7, 7, 7, 7, 7, 7, 15, 15, 15, 9, 4, 2, 8, 0, 7, 15, 1];
+        let decoded = [
+            0, 11, 41, 104, 240, 533, 1164, -193, -3103, -9339, -12013, -4719, 183, -708, 102, 11152, -12537,
+            -2381,
+        ];
+
+        let mut encoder = State::default();
+        let encoded: Vec<u8> = input.iter().map(|sample| encoder.encode(*sample)).collect();
+        assert_eq!(encoded, nibbles);
+        assert_eq!(encoder, State { predictor: -2381, index: 85 });
+
+        let mut decoder = State::default();
+        let samples: Vec<i16> = nibbles.iter().map(|nibble| decoder.decode(*nibble)).collect();
+        assert_eq!(samples, decoded);
+        assert_eq!(decoder, encoder);
+    }
+
+    #[test]
+    fn round_trips_closely() {
+        let samples = sine(505 * 8, 12000.0);
+        let clip = Adpcm::encode(&samples, 16000, BLOCK_ALIGN);
+        assert_eq!(clip.samples, samples.len());
+        assert_eq!(clip.data.len(), 8 * BLOCK_ALIGN);
+        let decoded = clip.decode();
+        assert_eq!(decoded.len(), samples.len());
+        assert!(snr(&samples, &decoded) > 30.0, "{:.1} dB", snr(&samples, &decoded));
+        // Each block starts from a sample stored whole.
+        for start in (0..samples.len()).step_by(clip.samples_per_block()) {
+            assert_eq!(decoded[start], samples[start]);
+        }
+
+        // A quiet sound starts at a small step rather than working down to it.
+        let quiet = sine(505, 50.0);
+        let decoded = Adpcm::encode(&quiet, 16000, BLOCK_ALIGN).decode();
+        assert!(decoded.iter().zip(&quiet).all(|(a, b)| (a - b).abs() < 20));
+    }
+
+    #[test]
+    fn the_last_block_can_be_partly_used() {
+        assert_eq!(samples_per_block(BLOCK_ALIGN), 505);
+        let samples = sine(505 * 2 + 10, 8000.0);
+        let clip = Adpcm::encode(&samples, 16000, BLOCK_ALIGN);
+        // A header and nine samples two to a byte, the last nibble spare.
+        assert_eq!(clip.data.len(), 2 * BLOCK_ALIGN + 4 + 5);
+        assert_eq!(samples_in(clip.data.len(), BLOCK_ALIGN), 505 * 2 + 11);
+
+        let mut position = Position::default();
+        let decoded: Vec<i16> = std::iter::from_fn(|| clip.next_sample(&mut position)).collect();
+        assert_eq!(decoded.len(), samples.len());
+        assert_eq!(decoded[1010], samples[1010]);
+        assert_eq!(clip.next_sample(&mut position), None);
+    }
+
+    #[test]
+    fn samples_in_counts_whole_and_partial_blocks() {
+        assert_eq!(samples_in(0, 256), 0);
+        assert_eq!(samples_in(3, 256), 0);
+        assert_eq!(samples_in(4, 256), 1);
+        assert_eq!(samples_in(5, 256), 3);
+        assert_eq!(samples_in(256, 256), 505);
+        assert_eq!(samples_in(3 * 256 + 2, 256), 3 * 505);
+        assert_eq!(samples_in(1024, 1024), 2041);
+    }
+
+    #[test]
+    fn short_and_empty_clips() {
+        let clip = Adpcm::encode(&[1234], 16000, BLOCK_ALIGN);
+        assert_eq!(clip.decode(), [1234]);
+        let clip = Adpcm::encode(&[], 16000, BLOCK_ALIGN);
+        assert!(clip.data.is_empty());
+        assert!(clip.decode().is_empty());
+    }
+
+    #[test]
+    fn a_corrupt_step_index_is_clamped() {
+        let data: &'static [u8] = &[0x10, 0x00, 200, 0, 0x77, 0x77];
+        let clip = Adpcm { sample_rate: 16000, block_align: 6, samples: 5, data: Data::Static(data) };
+        let decoded = clip.decode();
+        assert_eq!(decoded.len(), 5);
+        assert_eq!(decoded[0], 16);
+        assert_eq!(decoded[4], i16::MAX);
+    }
+}
@@ -0,0 +1,402 @@
+//! Frame based display animations.
+//!
+//! An `Animation` is a pure function of how long it's been playing. `Animator` plays one at
+//! a time and is ticked from the app's polling loops, writing to the display only when the
+//! frame actually changes, so animating costs nothing between frames and never blocks.
+
+use anyhow::Result;
+use crate::font::{Marquee, POINT};
+use crate::hal::{Display, MAX_BRIGHTNESS};
+
+/// How long each step of a scrolling message stays on the display.
+pub const MARQUEE_STEP_MS: u64 = 300;
+const BLINK_PERIOD_MS: u64 = 1000;
+const SPINNER_STEP_MS: u64 = 100;
+/// How long each brightness level lasts while fading.
+const FADE_STEP_MS: u64 = 60;
+const FADE_MS: u64 = FADE_STEP_MS * (MAX_BRIGHTNESS as u64 + 1);
+
+/// The outer segments of a four digit display, clockwise from the top left, as a digit and
+/// its segment bit.
+const SPINNER: [(usize, u8); 12] = [
+    (0, 0b00000001),
+    (1, 0b00000001),
+    (2, 0b00000001),
+    (3, 0b00000001),
+    (3, 0b00000010),
+    (3, 0b00000100),
+    (3, 0b00001000),
+    (2, 0b00001000),
+    (1, 0b00001000),
+    (0, 0b00001000),
+    (0, 0b00010000),
+    (0, 0b00100000),
+];
+const SPINNER_MS: u64 = SPINNER_STEP_MS * SPINNER.len() as u64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    pub segments: [u8; 4],
+    /// 0-7, see `Display::set_brightness`.
+    pub brightness: u8,
+}
+
+impl Frame {
+    pub fn new(segments: [u8; 4]) -> Self {
+        Self { segments, brightness: MAX_BRIGHTNESS }
+    }
+}
+
+pub enum Animation {
+    Still([u8; 4]),
+    /// Like `Still`, but only finishes after `ms`, for a message that should stay up a while
+    /// before the loop showing it goes back to what it was showing.
+    Message{segments: [u8; 4], ms: u64},
+    /// Lights the colon for the first half of every second, like a clock.
+    BlinkColon([u8; 4]),
+    /// Flashes the whole display, on for the first half of every second.
+    Blink([u8; 4]),
+    /// A single segment running round the outside of the display, while waiting on
+    /// something.
+    Spinner,
+    /// Scrolls a message through once, or over and over if `looping`.
+    Scroll{marquee: Marquee<4>, looping: bool},
+    /// Brightens from the lowest brightness.
+    FadeIn([u8; 4]),
+    /// Dims to the lowest brightness and then blanks the display.
+    FadeOut([u8; 4]),
+    /// Every segment fading in, then a lap of the spinner.
+    Boot,
+}
+
+impl Animation {
+    /// What to show once the animation has been playing for `elapsed_ms`.
+    pub fn frame(&self, elapsed_ms: u64) -> Frame {
+        let first_half = elapsed_ms % BLINK_PERIOD_MS < BLINK_PERIOD_MS / 2;
+        match self {
+            Animation::Still(segments) | Animation::Message{segments, ..} => Frame::new(*segments),
+            Animation::BlinkColon(segments) if first_half => Frame::new(*segments),
+            Animation::BlinkColon(segments) => {
+                let mut segments = *segments;
+                segments[1] &= !POINT;
+                Frame::new(segments)
+            },
+            Animation::Blink(segments) if first_half => Frame::new(*segments),
+            Animation::Blink(_) => Frame::new([0; 4]),
+            Animation::Spinner => spinner(elapsed_ms),
+            Animation::Scroll{marquee, looping} => {
+                let index = (elapsed_ms / MARQUEE_STEP_MS) as usize;
+                if !looping && index >= marquee.frames() {
+                    return Frame::new([0; 4]);
+                }
+                Frame::new(marquee.frame(index))
+            },
+            Animation::FadeIn(segments) => Frame {
+                segments: *segments,
+                brightness: fade_level(elapsed_ms),
+            },
+            Animation::FadeOut(_) if elapsed_ms >= FADE_MS => Frame {
+                segments: [0; 4],
+                brightness: 0,
+            },
+            Animation::FadeOut(segments) => Frame {
+                segments: *segments,
+                brightness: MAX_BRIGHTNESS - fade_level(elapsed_ms),
+            },
+            Animation::Boot if elapsed_ms < FADE_MS => Frame {
+                segments: [0xFF; 4],
+                brightness: fade_level(elapsed_ms),
+            },
+            Animation::Boot if elapsed_ms < FADE_MS + SPINNER_MS => spinner(elapsed_ms - FADE_MS),
+            Animation::Boot => Frame::new([0; 4]),
+        }
+    }
+
+    /// How long the animation runs before settling on its last frame, or `None` if it
+    /// carries on until something else is played.
+    pub fn duration_ms(&self) -> Option<u64> {
+        match self {
+            Animation::Scroll{marquee, looping: false} => {
+                Some(marquee.frames() as u64 * MARQUEE_STEP_MS)
+            },
+            Animation::Still(_) => Some(0),
+            Animation::Message{ms, ..} => Some(*ms),
+            Animation::FadeIn(_) | Animation::FadeOut(_) => Some(FADE_MS),
+            Animation::Boot => Some(FADE_MS + SPINNER_MS),
+            _ => None,
+        }
+    }
+}
+
+fn spinner(elapsed_ms: u64) -> Frame {
+    let (digit, segment) = SPINNER[(elapsed_ms / SPINNER_STEP_MS) as usize % SPINNER.len()];
+    let mut segments = [0; 4];
+    segments[digit] = segment;
+    Frame::new(segments)
+}
+
+fn fade_level(elapsed_ms: u64) -> u8 {
+    (elapsed_ms / FADE_STEP_MS).min(MAX_BRIGHTNESS as u64) as u8
+}
+
+/// Plays one animation at a time on a display.
+pub struct Animator {
+    animation: Animation,
+    started_ms: u64,
+    /// The segments last written, or `None` if the display may have been written behind the
+    /// animator's back since.
+    shown: Option<[u8; 4]>,
+    /// Only the animator changes the brightness, so this stays right across animations.
+    brightness: Option<u8>,
+}
+
+impl Animator {
+    pub fn new() -> Self {
+        Self {
+            animation: Animation::Still([0; 4]),
+            started_ms: 0,
+            shown: None,
+            brightness: None,
+        }
+    }
+
+    /// Starts `animation` from its first frame, replacing whatever was playing.
+    pub fn play(&mut self, animation: Animation, now_ms: u64) {
+        self.animation = animation;
+        self.started_ms = now_ms;
+        self.shown = None;
+    }
+
+    pub fn is_finished(&self, now_ms: u64) -> bool {
+        self.animation.duration_ms()
+            .is_some_and(|duration| now_ms - self.started_ms >= duration)
+    }
+
+    /// Whether a message or fade is still playing. Loops that redraw the display on their own
+    /// leave it alone until it's done.
+    pub fn is_holding(&self, now_ms: u64) -> bool {
+        matches!(self.animation, Animation::Message{..} | Animation::FadeIn(_)) && !self.is_finished(now_ms)
+    }
+
+    /// The segments last written, for animations that start from what's on the display.
+    pub fn segments(&self) -> [u8; 4] {
+        self.shown.unwrap_or([0; 4])
+    }
+
+    /// Writes the frame for `now_ms` to `display`, if it's changed since the last one.
+    pub fn tick(&mut self, now_ms: u64, display: &mut dyn Display) -> Result<()> {
+        let frame = self.animation.frame(now_ms - self.started_ms);
+        let brightness = Some(frame.brightness).filter(|brightness| self.brightness != Some(*brightness));
+        // Dim before drawing the new segments and brighten after, so neither the old nor the
+        // new frame flashes at the wrong level.
+        let dimming = self.brightness.map_or(true, |shown| frame.brightness < shown);
+        if let Some(brightness) = brightness.filter(|_| dimming) {
+            display.set_brightness(brightness)?;
+        }
+        if self.shown != Some(frame.segments) {
+            display.set_segments(frame.segments)?;
+            self.shown = Some(frame.segments);
+        }
+        if let Some(brightness) = brightness.filter(|_| !dimming) {
+            display.set_brightness(brightness)?;
+        }
+        self.brightness = Some(frame.brightness);
+        Ok(())
+    }
+}
+
+impl Default for Animator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::{self, Align};
+
+    const TEXT: [u8; 4] = [0b00111111, 0b00000110 | POINT, 0b01011011, 0b01001111];
+
+    /// Remembers every write, to check the animator only writes what changed.
+    #[derive(Default)]
+    struct Recorder {
+        segments: Vec<[u8; 4]>,
+        brightness: Vec<u8>,
+    }
+
+    impl Display for Recorder {
+        fn set_segments(&mut self, segments: [u8; 4]) -> Result<()> {
+            self.segments.push(segments);
+            Ok(())
+        }
+
+        fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+            self.brightness.push(brightness);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn still_shows_its_segments_and_is_done() {
+        let animation = Animation::Still(TEXT);
+        assert_eq!(animation.frame(0), Frame::new(TEXT));
+        assert_eq!(animation.frame(5000), Frame::new(TEXT));
+        assert_eq!(animation.duration_ms(), Some(0));
+    }
+
+    #[test]
+    fn blinks_repeat_every_second() {
+        let mut no_colon = TEXT;
+        no_colon[1] &= !POINT;
+        let colon = Animation::BlinkColon(TEXT);
+        let blink = Animation::Blink(TEXT);
+        for (elapsed_ms, colon_frame, blink_frame) in [
+            (0, TEXT, TEXT),
+            (499, TEXT, TEXT),
+            (500, no_colon, [0; 4]),
+            (999, no_colon, [0; 4]),
+            (1000, TEXT, TEXT),
+            (2700, no_colon, [0; 4]),
+        ] {
+            assert_eq!(colon.frame(elapsed_ms), Frame::new(colon_frame), "{} ms", elapsed_ms);
+            assert_eq!(blink.frame(elapsed_ms), Frame::new(blink_frame), "{} ms", elapsed_ms);
+        }
+        assert_eq!(colon.duration_ms(), None);
+        assert_eq!(blink.duration_ms(), None);
+    }
+
+    #[test]
+    fn scrolls_step_through_the_marquee() {
+        let marquee = || Marquee::<4>::new("Hi");
+        let frames = marquee().frames();
+        let once = Animation::Scroll{marquee: marquee(), looping: false};
+        for index in 0..frames {
+            let elapsed_ms = index as u64 * MARQUEE_STEP_MS;
+            assert_eq!(once.frame(elapsed_ms), Frame::new(marquee().frame(index)));
+            assert_eq!(once.frame(elapsed_ms + MARQUEE_STEP_MS - 1), Frame::new(marquee().frame(index)));
+        }
+        assert_eq!(once.duration_ms(), Some(frames as u64 * MARQUEE_STEP_MS));
+        assert_eq!(once.frame(frames as u64 * MARQUEE_STEP_MS), Frame::new([0; 4]));
+
+        let looping = Animation::Scroll{marquee: marquee(), looping: true};
+        assert_eq!(looping.frame(frames as u64 * MARQUEE_STEP_MS), Frame::new(marquee().frame(0)));
+        assert_eq!(looping.duration_ms(), None);
+    }
+
+    #[test]
+    fn a_message_stays_up_for_its_time() {
+        let animation = Animation::Message{segments: TEXT, ms: 1000};
+        assert_eq!(animation.frame(0), Frame::new(TEXT));
+        assert_eq!(animation.frame(1500), Frame::new(TEXT));
+        assert_eq!(animation.duration_ms(), Some(1000));
+
+        let mut animator = Animator::new();
+        animator.play(animation, 2000);
+        assert!(!animator.is_finished(2999));
+        assert!(animator.is_holding(2999));
+        assert!(animator.is_finished(3000));
+        assert!(!animator.is_holding(3000));
+
+        // Animations that never finish don't hold the display.
+        animator.play(Animation::BlinkColon(TEXT), 3000);
+        assert!(!animator.is_holding(3000));
+    }
+
+    #[test]
+    fn the_spinner_goes_round_the_outside_until_replaced() {
+        let animation = Animation::Spinner;
+        let lap: Vec<[u8; 4]> = (0..SPINNER.len() as u64)
+            .map(|step| animation.frame(step * SPINNER_STEP_MS).segments)
+            .collect();
+        assert_eq!(lap[0], [0b00000001, 0, 0, 0]);
+        assert_eq!(lap[4], [0, 0, 0, 0b00000010]);
+        assert_eq!(lap[11], [0b00100000, 0, 0, 0]);
+        // Exactly one segment lit at a time, each a different one.
+        for (i, segments) in lap.iter().enumerate() {
+            assert_eq!(segments.iter().map(|digit| digit.count_ones()).sum::<u32>(), 1);
+            assert!(!lap[..i].contains(segments));
+        }
+        assert_eq!(animation.frame(SPINNER_MS + SPINNER_STEP_MS - 1).segments, lap[0]);
+        assert_eq!(animation.frame(SPINNER_MS).brightness, MAX_BRIGHTNESS);
+        assert_eq!(animation.duration_ms(), None);
+    }
+
+    #[test]
+    fn fade_in_brightens_a_level_a_step() {
+        let animation = Animation::FadeIn(TEXT);
+        let levels: Vec<u8> = (0..=MAX_BRIGHTNESS as u64)
+            .map(|step| animation.frame(step * FADE_STEP_MS).brightness)
+            .collect();
+        assert_eq!(levels, [0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(animation.frame(0).segments, TEXT);
+        assert_eq!(animation.frame(FADE_MS * 2), Frame::new(TEXT));
+        assert_eq!(animation.duration_ms(), Some(FADE_MS));
+    }
+
+    #[test]
+    fn fade_out_dims_a_level_a_step_then_blanks() {
+        let animation = Animation::FadeOut(TEXT);
+        let levels: Vec<u8> = (0..=MAX_BRIGHTNESS as u64)
+            .map(|step| animation.frame(step * FADE_STEP_MS).brightness)
+            .collect();
+        assert_eq!(levels, [7, 6, 5, 4, 3, 2, 1, 0]);
+        assert_eq!(animation.frame(FADE_MS - 1).segments, TEXT);
+        assert_eq!(animation.frame(FADE_MS), Frame { segments: [0; 4], brightness: 0 });
+        assert_eq!(animation.duration_ms(), Some(FADE_MS));
+    }
+
+    #[test]
+    fn boot_fades_in_then_spins_once_round() {
+        let animation = Animation::Boot;
+        assert_eq!(animation.frame(0), Frame { segments: [0xFF; 4], brightness: 0 });
+        assert_eq!(animation.frame(FADE_MS - 1), Frame { segments: [0xFF; 4], brightness: MAX_BRIGHTNESS });
+        for (step, (digit, segment)) in SPINNER.iter().enumerate() {
+            let frame = animation.frame(FADE_MS + step as u64 * SPINNER_STEP_MS);
+            let mut segments = [0; 4];
+            segments[*digit] = *segment;
+            assert_eq!(frame, Frame::new(segments));
+        }
+        assert_eq!(animation.frame(FADE_MS + SPINNER_MS), Frame::new([0; 4]));
+        assert_eq!(animation.duration_ms(), Some(FADE_MS + SPINNER_MS));
+    }
+
+    #[test]
+    fn the_animator_only_writes_changes() {
+        let mut animator = Animator::new();
+        let mut display = Recorder::default();
+        animator.play(Animation::BlinkColon(TEXT), 1000);
+        for now_ms in (1000..3000).step_by(50) {
+            animator.tick(now_ms, &mut display).unwrap();
+        }
+        assert_eq!(display.segments.len(), 4);
+        assert_eq!(display.brightness, [MAX_BRIGHTNESS]);
+        assert!(!animator.is_finished(3000));
+
+        // Playing again always writes, as the display may have been changed since.
+        animator.play(Animation::Still(TEXT), 3000);
+        animator.tick(3000, &mut display).unwrap();
+        assert_eq!(display.segments.len(), 5);
+        assert!(animator.is_finished(3000));
+        assert_eq!(animator.segments(), TEXT);
+    }
+
+    #[test]
+    fn the_animator_steps_the_brightness_down_and_back_up() {
+        let mut animator = Animator::new();
+        let mut display = Recorder::default();
+        let text = font::text("LOC", Align::Left);
+        animator.play(Animation::FadeOut(text), 0);
+        for now_ms in (0..=FADE_MS).step_by(10) {
+            animator.tick(now_ms, &mut display).unwrap();
+        }
+        assert_eq!(display.brightness, [7, 6, 5, 4, 3, 2, 1, 0]);
+        assert_eq!(display.segments, [text, [0; 4]]);
+        assert!(animator.is_finished(FADE_MS));
+
+        animator.play(Animation::Still(text), FADE_MS);
+        animator.tick(FADE_MS, &mut display).unwrap();
+        assert_eq!(display.brightness.last(), Some(&MAX_BRIGHTNESS));
+        assert_eq!(display.segments.last(), Some(&text));
+    }
+}
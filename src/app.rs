@@ -1,202 +1,641 @@
-use anyhow::Result;
-use esp_idf_svc::hal::{
-    peripherals::Peripherals,
-    timer::*,
-    gpio::{
-        AnyInputPin,
-        Input,
-        PinDriver,
-        Level, AnyIOPin,
-    },
-    timer::config::Config,
-    delay::FreeRtos, peripheral::Peripheral,
+use anyhow::{anyhow, bail, Result};
+use crate::hal::{
+    AudioOut,
+    Board,
+    Clock,
+    Display,
+    Files,
+    InputLine,
+    KeyInput,
+    Light,
+    LightCode,
+    Power,
+    Settings,
+    WakeReason,
+    WallClock,
 };
-use esp_idf_svc::sys::{
-    esp_deep_sleep_start,
-};
-use crate::seven_segment::SevenSegment;
-use crate::keypad::Keypad;
-use crate::speaker::Speaker;
-use crate::remote::Remote;
 use awedio::{
-    sounds::MemorySound,
+    sounds::wrappers::{AdjustableVolume, Controller},
     Sound,
 };
+use crate::adpcm::{self, Adpcm};
+use crate::animation::{Animation, Animator};
+use crate::clip_sound::ClipSound;
+use crate::defrost::{self, Food};
+use crate::font::{self, Align, Marquee, DISPLAY_DIGITS};
+use crate::key_events::{KeyEvent, KeyEvents, Timings};
+use crate::keys::Key;
+use crate::manifest::{self, Manifest, SoundEvent, MANIFEST_FILE};
+use crate::preset::{Dish, Preset, PresetSound};
+use crate::program::Stage;
+use crate::wav::{Audio, Wav};
+use std::collections::VecDeque;
 use std::sync::Arc;
 
+/// The built-in sounds, for anything the sound pack on flash doesn't replace.
 const MICROWAVE_BEEP_WAV: &[u8] = include_bytes!("./assets/beep.wav");
 const MICROWAVE_START_WAV: &[u8] = include_bytes!("./assets/start.wav");
 const MICROWAVE_RUNNING_WAV: &[u8] = include_bytes!("./assets/microwave.wav");
 
-const DISPLAY_DIGITS: [u8;11] = [
-    0b00111111,
-    0b00000110,
-    0b01011011,
-    0b01001111,
-    0b01100110,
-    0b01101101,
-    0b01111101,
-    0b00000111,
-    0b01111111,
-    0b01101111,
-    0b00000000,
-];
+const SETTING_TWELVE_HOUR: &str = "clock_12h";
+const SETTING_CHILD_LOCK: &str = "child_lock";
+const SETTING_VOLUME: &str = "volume";
+const SETTING_QUIET_HOURS: &str = "quiet_hours";
+
+/// Volume levels run from 0, muted, up to this, the sounds at their own level.
+const MAX_VOLUME: u8 = 9;
+/// During quiet hours the volume is turned down to at most this.
+const QUIET_VOLUME: u8 = 3;
+/// Quiet hours run overnight, from the start of one hour to the start of the other.
+const QUIET_FROM_HOUR: u32 = 22;
+const QUIET_UNTIL_HOUR: u32 = 7;
+
+/// Gains that even out the levels the sounds were recorded at. The hum sits under the beeps
+/// so they can still be heard over it.
+const BEEP_GAIN: f32 = 1.0;
+const START_GAIN: f32 = 1.0;
+const RUNNING_GAIN: f32 = 0.7;
+
+/// How long stop has to be held to lock or unlock the panel.
+const LOCK_HOLD_MS: u64 = 3000;
+/// How long a message like `LOC` or a dish's name stays up.
+const MESSAGE_MS: u64 = 1000;
+
+/// While cooking, every fifth second shows the kitchen timer instead of the cook time.
+const KITCHEN_TIMER_SHOW_EVERY: u64 = 5;
+
+const MAX_POWER: u8 = 10;
+/// Like a real magnetron, lower power levels cycle on for `power` seconds out of every ten.
+const POWER_CYCLE_SECONDS: u64 = 10;
+/// The fan keeps running while the magnetron is off, so the hum drops rather than stopping.
+const MAGNETRON_OFF_VOLUME: f32 = 0.35;
 
+const DOOR_OPEN_MESSAGE: &str = "door OPEn";
+const DONE_MESSAGE: &str = "Enjoy your meal";
+
+/// The most stages that can be entered for one program from the keypad.
+const MAX_STAGES: usize = 4;
+
+/// Each bare press of start adds this much time.
+const EXPRESS_SECONDS: u16 = 30;
+const MAX_SECONDS: u16 = 99 * 60 + 59;
+
+/// Menu entries that need another key after them.
 #[derive(Clone, Copy)]
-enum Mode {
+enum MenuChoice {
+    EditPreset,
+    Learn,
+    Volume,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// The power-on animation, before going idle.
+    Boot,
     Idle,
-    UserInput,
-    Running{seconds: u8, minutes: u8},
+    UserInput{first_key: Option<Key>},
+    Running{seconds: u8, minutes: u8, power: u8},
     Done,
-    Paused{seconds: u8, minutes: u8},
+    Paused{seconds: u8, minutes: u8, power: u8},
     Sleep,
+    Learn{code: LightCode},
+    TurnOver,
+    SetClock,
+    KitchenTimer,
+    Preset{dish: Dish},
+    EditPreset{dish: Dish},
 }
 
+/// A sound and how loud to play it, relative to the rest of the pack.
+struct PackSound {
+    audio: Audio,
+    gain: f32,
+}
+
+/// The sounds the app plays. Any the sound pack on flash provides replace the built-in ones.
 struct SoundPack {
-    beep: Arc<Vec<i16>>,
-    start: Arc<Vec<i16>>,
-    running: Arc<Vec<i16>>,
+    beep: PackSound,
+    start: PackSound,
+    running: PackSound,
+    /// `None` without a pack sound, in which case cooking ends with a row of beeps.
+    done: Option<PackSound>,
+    door: PackSound,
+    chime: PackSound,
 }
 
 impl SoundPack {
-    fn new() -> Self {
-        Self {
-            beep: Self::convert_wav_to_samples(&MICROWAVE_BEEP_WAV),
-            start: Self::convert_wav_to_samples(&MICROWAVE_START_WAV),
-            running: Self::convert_wav_to_samples(&MICROWAVE_RUNNING_WAV),
+    /// Loads the pack from `files`, falling back to the built-in sound for any event it
+    /// doesn't have or has a file that can't be played.
+    fn load(files: &dyn Files) -> Result<Self> {
+        let manifest = Self::manifest(files);
+        let load = |event| Self::pack_sound(files, &manifest, event);
+        let beep = match load(SoundEvent::Beep) {
+            Some(beep) => beep,
+            None => PackSound {audio: Audio::parse_static(MICROWAVE_BEEP_WAV)?, gain: BEEP_GAIN},
+        };
+        let start = match load(SoundEvent::Start) {
+            Some(start) => start,
+            None => PackSound {audio: Audio::parse_static(MICROWAVE_START_WAV)?, gain: START_GAIN},
+        };
+        let running = match load(SoundEvent::Running) {
+            Some(running) => running,
+            None => PackSound {audio: Audio::parse_static(MICROWAVE_RUNNING_WAV)?, gain: RUNNING_GAIN},
+        };
+        let pip = Self::pip(&beep.audio);
+        let door = load(SoundEvent::Door)
+            .unwrap_or_else(|| PackSound {audio: Self::door(&pip, &beep.audio), gain: beep.gain});
+        Ok(Self {
+            done: load(SoundEvent::Done),
+            chime: PackSound {audio: Self::chime(&pip, &beep.audio), gain: beep.gain},
+            beep,
+            start,
+            running,
+            door,
+        })
+    }
+
+    /// The pack's manifest, or an empty one if there's no pack or the manifest is broken.
+    fn manifest(files: &dyn Files) -> Manifest {
+        let manifest = files.read(MANIFEST_FILE).and_then(|text| match text {
+            Some(text) => Ok(Manifest::parse(std::str::from_utf8(&text)?)?),
+            None => Ok(Manifest::default()),
+        });
+        manifest.unwrap_or_else(|err| {
+            log::warn!("Ignoring the sound pack: {:?}", err);
+            Manifest::default()
+        })
+    }
+
+    /// The pack's sound for `event`, if it lists one and it can be played.
+    fn pack_sound(files: &dyn Files, manifest: &Manifest, event: SoundEvent) -> Option<PackSound> {
+        let entry = manifest.entry(event)?;
+        let audio = files.read(&entry.file).and_then(|bytes| {
+            let audio = Audio::parse(&bytes.ok_or_else(|| anyhow!("{} is missing", entry.file))?)?;
+            if !manifest::is_playable(audio.channels(), audio.sample_rate()) {
+                bail!("{} is {} channel {} Hz", entry.file, audio.channels(), audio.sample_rate());
+            }
+            Ok(audio)
+        });
+        match audio {
+            Ok(audio) => Some(PackSound {audio, gain: entry.gain}),
+            Err(err) => {
+                log::warn!("Using the built-in {} sound: {:?}", event.name(), err);
+                None
+            },
         }
     }
 
-    fn convert_wav_to_samples(wav: &[u8]) -> Arc<Vec<i16>> {
-        let wav = &wav[44..];
-        let mut samples = vec![0i16; wav.len()/2];
-        for i in 0..samples.len() {
-            samples[i] = (wav[i * 2] as i16) | ((wav[i * 2 + 1] as i16) << 8);
+    /// The start of the beep, short enough not to get in the way of opening the door.
+    fn door(pip: &[i16], beep: &Audio) -> Audio {
+        Audio::Pcm(Wav {
+            channels: beep.channels(),
+            sample_rate: beep.sample_rate(),
+            samples: Arc::new(pip.to_vec()),
+        })
+    }
+
+    /// Three pairs of short pips cut from the beep, so the kitchen timer can't be mistaken for
+    /// the end of cooking. Mostly silence, so it's kept as ADPCM if the beep is mono.
+    fn chime(pip: &[i16], beep: &Audio) -> Audio {
+        let samples_per_ms = beep.sample_rate() as usize * beep.channels() as usize / 1000;
+        let mut samples = Vec::new();
+        for _ in 0..3 {
+            samples.extend_from_slice(pip);
+            samples.resize(samples.len() + 80 * samples_per_ms, 0);
+            samples.extend_from_slice(pip);
+            samples.resize(samples.len() + 500 * samples_per_ms, 0);
+        }
+        if beep.channels() == 1 {
+            return Audio::Adpcm(Adpcm::encode(&samples, beep.sample_rate(), adpcm::BLOCK_ALIGN));
         }
-        Arc::new(samples)
+        Audio::Pcm(Wav {
+            channels: beep.channels(),
+            sample_rate: beep.sample_rate(),
+            samples: Arc::new(samples),
+        })
     }
 
-    fn beep_sound(&self) -> Box<MemorySound> {
-        Box::new(MemorySound::from_samples(self.beep.clone(), 1, 16000))
+    /// The first 120 ms of the beep.
+    fn pip(beep: &Audio) -> Vec<i16> {
+        let samples_per_ms = beep.sample_rate() as usize * beep.channels() as usize / 1000;
+        let mut samples = beep.decode();
+        samples.truncate(120 * samples_per_ms);
+        samples
     }
 
-    fn start_sound(&self) -> Box<MemorySound> {
-        Box::new(MemorySound::from_samples(self.start.clone(), 1, 16000))
+    fn gained_sound(sound: &PackSound) -> Box<AdjustableVolume<ClipSound>> {
+        Box::new(ClipSound::new(&sound.audio).with_adjustable_volume_of(sound.gain))
     }
 
-    fn running_sound(&self) -> Box<dyn Sound> {
-        let mut sound = MemorySound::from_samples(self.running.clone(), 1, 16000);
+    fn beep_sound(&self) -> Box<AdjustableVolume<ClipSound>> {
+        Self::gained_sound(&self.beep)
+    }
+
+    fn start_sound(&self) -> Box<AdjustableVolume<ClipSound>> {
+        Self::gained_sound(&self.start)
+    }
+
+    fn chime_sound(&self) -> Box<AdjustableVolume<ClipSound>> {
+        Self::gained_sound(&self.chime)
+    }
+
+    fn door_sound(&self) -> Box<AdjustableVolume<ClipSound>> {
+        Self::gained_sound(&self.door)
+    }
+
+    /// What to play for the `beep`th of the beeps that end cooking. A pack's own done sound
+    /// plays once, with the first.
+    fn done_sound(&self, beep: u64) -> Option<Box<AdjustableVolume<ClipSound>>> {
+        match &self.done {
+            Some(done) => (beep == 0).then(|| Self::gained_sound(done)),
+            None => Some(self.beep_sound()),
+        }
+    }
+
+    fn preset_sound(&self, sound: PresetSound) -> Option<Box<AdjustableVolume<ClipSound>>> {
+        match sound {
+            PresetSound::Start => Some(self.start_sound()),
+            PresetSound::Chime => Some(self.chime_sound()),
+            PresetSound::Beep => Some(self.beep_sound()),
+            PresetSound::Silent => None,
+        }
+    }
+
+    fn running_sound(&self) -> (Box<dyn Sound>, Controller<AdjustableVolume<ClipSound>>) {
+        let mut sound = ClipSound::new(&self.running.audio);
         sound.set_looping(true);
-        Box::new(sound)
+        let (sound, controller) = sound.with_adjustable_volume_of(self.running.gain).controllable();
+        (Box::new(sound), controller)
+    }
+
+    /// The running sound's volume with the magnetron on or off.
+    fn running_volume(&self, magnetron_on: bool) -> f32 {
+        let volume = if magnetron_on { 1.0 } else { MAGNETRON_OFF_VOLUME };
+        volume * self.running.gain
     }
 }
 
-struct App<'a> {
-    display: SevenSegment<'a>,
-    keypad: Keypad<'a>,
-    speaker: Speaker,
-    timer: TimerDriver<'a>,
-    start_button: PinDriver<'a, AnyInputPin, Input>,
-    stop_button: PinDriver<'a, AnyInputPin, Input>,
-    door_switch: PinDriver<'a, AnyInputPin, Input>,
-    remote: Remote<'a>,
+pub struct App<'a> {
+    display: Box<dyn Display + 'a>,
+    animator: Animator,
+    keypad: Box<dyn KeyInput + 'a>,
+    key_events: KeyEvents,
+    speaker: Box<dyn AudioOut + 'a>,
+    timer: Box<dyn Clock + 'a>,
+    start_button: Box<dyn InputLine + 'a>,
+    stop_button: Box<dyn InputLine + 'a>,
+    door_switch: Box<dyn InputLine + 'a>,
+    remote: Box<dyn Light + 'a>,
+    power: Box<dyn Power + 'a>,
+    wall_clock: Box<dyn WallClock + 'a>,
+    settings: Box<dyn Settings + 'a>,
     sounds: SoundPack,
+    /// Stages still to run after the current countdown finishes.
+    program: VecDeque<Stage>,
+    /// Which timed stage of the program is running, counting from 1, out of how many.
+    stage: u8,
+    stage_count: u8,
+    twelve_hour: bool,
+    /// While set the panel ignores everything but holding stop.
+    locked: bool,
+    /// Set on waking up into idle, so the clock fades in rather than appearing at once.
+    fade_in: bool,
+    /// 0-`MAX_VOLUME`, as set from the menu.
+    volume: u8,
+    /// Whether the volume is turned down overnight.
+    quiet_hours: bool,
+    /// The level last given to the speaker, which quiet hours can make lower than `volume`.
+    speaker_volume: Option<u8>,
+    /// When the kitchen timer goes off, in `timer` ticks. It counts down whatever mode the
+    /// app is in, so every mode's loop polls it.
+    kitchen_timer: Option<u64>,
 }
 
 impl<'a> App<'a> {
-    pub fn new(
-        display: SevenSegment<'a>,
-        keypad: Keypad<'a>,
-        speaker: Speaker,
-        timer: TimerDriver<'a>,
-        start_button: PinDriver<'a, AnyInputPin, Input>,
-        stop_button: PinDriver<'a, AnyInputPin, Input>,
-        door_switch: PinDriver<'a, AnyInputPin, Input>,
-        remote: Remote<'a>,
-    ) -> Self {
-        Self {
-            display,
-            keypad,
-            speaker,
-            timer,
-            start_button,
-            stop_button,
-            door_switch,
-            remote,
-            sounds: SoundPack::new(),
-        }
-    }
-
-    fn run(&mut self) -> Result<()> {
-        self.timer.enable(true)?;
-        let mut mode = Mode::Idle;
+    pub fn new(board: Board<'a>) -> Result<Self> {
+        let twelve_hour = board.settings.get_u32(SETTING_TWELVE_HOUR)? == Some(1);
+        let locked = board.settings.get_u32(SETTING_CHILD_LOCK)? == Some(1);
+        let volume = board.settings.get_u32(SETTING_VOLUME)?
+            .map_or(MAX_VOLUME, |volume| volume.min(MAX_VOLUME as u32) as u8);
+        let quiet_hours = board.settings.get_u32(SETTING_QUIET_HOURS)? == Some(1);
+        Ok(Self {
+            display: board.display,
+            animator: Animator::new(),
+            keypad: board.keypad,
+            key_events: KeyEvents::new(Timings::default()),
+            speaker: board.speaker,
+            timer: board.clock,
+            start_button: board.start_button,
+            stop_button: board.stop_button,
+            door_switch: board.door_switch,
+            remote: board.remote,
+            power: board.power,
+            wall_clock: board.wall_clock,
+            settings: board.settings,
+            sounds: SoundPack::load(board.files.as_ref())?,
+            program: VecDeque::new(),
+            stage: 0,
+            stage_count: 0,
+            twelve_hour,
+            locked,
+            fade_in: false,
+            volume,
+            quiet_hours,
+            speaker_volume: None,
+            kitchen_timer: None,
+        })
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        let mut mode = self.wake_mode()?;
         loop {
-            let next_mode = match mode {
-                Mode::Idle => self.run_idle()?,
-                Mode::UserInput => self.run_user_input()?,
-                Mode::Running{seconds, minutes} => self.run_running(seconds, minutes)?,
-                Mode::Done => self.run_done()?,
-                Mode::Paused{seconds, minutes} => self.run_paused(seconds, minutes)?,
-                Mode::Sleep => self.run_sleep()?,
-            };
-            mode = next_mode;
+            mode = self.step(mode)?;
+        }
+    }
+
+    /// Runs a single mode to completion and returns the mode that follows it.
+    pub fn step(&mut self, mode: Mode) -> Result<Mode> {
+        // Close enough for quiet hours, which only need to start and end around the hour.
+        self.apply_volume();
+        self.keypad.clear();
+        // Every mode is entered through here, so a locked panel can't reach any of them.
+        if self.locked && mode != Mode::Boot {
+            // The key that woke the board has already been taken, so it gets its `LOC` here.
+            let key_pressed = matches!(mode, Mode::UserInput{first_key: Some(_)});
+            return self.run_locked(key_pressed);
+        }
+        match mode {
+            Mode::Boot => self.run_boot(),
+            Mode::Idle => self.run_idle(),
+            Mode::UserInput{first_key} => self.run_user_input(first_key),
+            Mode::Running{seconds, minutes, power} => self.run_running(seconds, minutes, power),
+            Mode::Done => self.run_done(),
+            Mode::Paused{seconds, minutes, power} => self.run_paused(seconds, minutes, power),
+            Mode::Sleep => self.run_sleep(),
+            Mode::Learn{code} => self.run_learn(code),
+            Mode::TurnOver => self.run_turn_over(),
+            Mode::SetClock => self.run_set_clock(),
+            Mode::KitchenTimer => self.run_kitchen_timer(),
+            Mode::Preset{dish} => self.run_preset(dish),
+            Mode::EditPreset{dish} => self.run_edit_preset(dish),
+        }
+    }
+
+    /// The mode to start in, based on what woke the board.
+    pub fn wake_mode(&mut self) -> Result<Mode> {
+        // Nothing has set the volume yet after a reset, and a start wake plays a sound.
+        self.apply_volume();
+        let mode = match self.power.wake_reason() {
+            WakeReason::Keypad => {
+                // The key that woke the board is handled here, so it mustn't be pressed again.
+                let key = self.keypad.get_key()?;
+                let now_ms = self.now_ms()?;
+                self.key_events.sync(now_ms, key);
+                // A tap can be over before the first scan, leaving nothing to start entry with.
+                match key {
+                    Some(key) => Mode::UserInput{first_key: Some(key)},
+                    None => Mode::Idle,
+                }
+            },
+            WakeReason::PowerOn => Mode::Boot,
+            // Start wakes the board and express starts in one press, as it would when idle.
+            WakeReason::StartButton if !self.locked && self.door_switch.is_low() => {
+                self.speaker.play(self.sounds.start_sound())?;
+                express_start(MAX_POWER)
+            },
+            WakeReason::StartButton | WakeReason::Door => Mode::Idle,
+        };
+        self.fade_in = mode == Mode::Idle;
+        Ok(mode)
+    }
+
+    fn run_boot(&mut self) -> Result<Mode> {
+        self.play(Animation::Boot)?;
+        loop {
+            self.animate()?;
+            if self.animator.is_finished(self.now_ms()?) {
+                return Ok(Mode::Idle);
+            }
+            self.timer.delay_ms(50)?;
         }
     }
 
     fn run_idle(&mut self) -> Result<Mode> {
-        self.display.set_segments([0b00000000; 4])?;
+        self.load_program(Vec::new());
         let start_time = self.timer.counter()?;
         const TIMEOUT: u64 = 60;
+        let mut shown = None;
         loop {
-            let key = self.keypad.get_key()?;
-            if key.is_some() {
-                return Ok(Mode::UserInput);
+            self.poll_kitchen_timer()?;
+            self.show_clock(&mut shown)?;
+            if let Some(key) = self.pressed_key()? {
+                return Ok(Mode::UserInput{first_key: Some(key)});
+            }
+            if self.start_button.is_low() && self.door_switch.is_low() {
+                self.speaker.play(self.sounds.start_sound())?;
+                return Ok(express_start(MAX_POWER));
+            }
+            // Holding stop locks the panel, a short press cancels the kitchen timer. Every other
+            // mode comes back here on stop, so holding it from any of them carries on to here.
+            if self.stop_button.is_low() {
+                if self.stop_held_for(LOCK_HOLD_MS)? {
+                    self.set_locked(true)?;
+                    self.speaker.play(self.sounds.beep_sound())?;
+                    self.play(locked_message())?;
+                    self.wait_for_stop_release()?;
+                    return Ok(Mode::Idle);
+                }
+                if self.kitchen_timer.take().is_some() {
+                    self.speaker.play(self.sounds.beep_sound())?;
+                }
             }
+            // Deep sleep would lose the kitchen timer, so stay awake until it goes off.
             let elapsed = self.timer.counter()? - start_time;
             let seconds_elapsed = elapsed / self.timer.tick_hz();
-            if seconds_elapsed > TIMEOUT {
+            if seconds_elapsed > TIMEOUT && self.kitchen_timer.is_none() {
                 return Ok(Mode::Sleep);
             }
-            FreeRtos::delay_ms(50u32);
+            self.timer.delay_ms(50)?;
         }
     }
 
-
-    fn run_user_input(&mut self) -> Result<Mode> {
-        self.display.set_segments([0b00000000; 4])?;
+    fn run_user_input(&mut self, first_key: Option<Key>) -> Result<Mode> {
+        self.show([0b00000000; 4])?;
         let start_time = self.timer.counter()?;
         const TIMEOUT: u64 = 60 * 5;
 
-        let mut last_key = None;
+        let mut pending_key = first_key;
+        let mut sequence = Vec::new();
         let mut digits = [10u8; 4];
+        let mut power = MAX_POWER;
+        let mut entering_power = false;
+        let mut in_menu = false;
+        let mut choosing = None;
+        let mut defrost_food = None;
+        let mut stages = Vec::new();
         loop {
-            let key = self.keypad.get_key()?;
-            if key != last_key && key.is_some() {
-                let mut digit = key.unwrap() + 1;
-                if digit > 9 {
-                    digit = 0;
-                }
-                if digits[1] == 10 {
-                    digits = [digits[1], digits[2], digits[3], digit];
-                }
-
-                self.display.set_segments([
-                    DISPLAY_DIGITS[digits[0] as usize],
-                    DISPLAY_DIGITS[digits[1] as usize] | 0x80,
-                    DISPLAY_DIGITS[digits[2] as usize],
-                    DISPLAY_DIGITS[digits[3] as usize],
-                ])?;
+            let event = match pending_key.take() {
+                Some(key) => Some(KeyEvent::Pressed(key)),
+                None => self.key_event()?,
+            };
+            match event {
+                // Holding 1-4 as the first key starts a one-touch preset.
+                Some(KeyEvent::LongPress(Key::Digit(digit))) if sequence == [Key::Digit(digit)] => {
+                    if let Some(dish) = Dish::from_key(digit) {
+                        return Ok(Mode::Preset{dish});
+                    }
+                },
+                // Holding `*` throws away everything typed so far.
+                Some(KeyEvent::LongPress(Key::Star)) => {
+                    self.speaker.play(self.sounds.beep_sound())?;
+                    return Ok(Mode::Idle);
+                },
+                _ => {},
+            }
+            if let Some(KeyEvent::Pressed(key)) = event {
                 self.speaker.play(self.sounds.beep_sound())?;
+                sequence.push(key);
+
+                let entry_segments = |digits| match defrost_food {
+                    Some(_) => weight_segments(digits),
+                    None => clock_entry_digits(digits),
+                };
+                if let Some(choice) = choosing.take() {
+                    // A slot for the last menu choice, 1-4, or a volume level.
+                    match (choice, key.digit()) {
+                        (MenuChoice::EditPreset, Some(digit)) => if let Some(dish) = Dish::from_key(digit) {
+                            return Ok(Mode::EditPreset{dish});
+                        },
+                        (MenuChoice::Learn, Some(digit)) => if let Some(code) = learn_code(digit) {
+                            return Ok(Mode::Learn{code});
+                        },
+                        // The mixer picks the new volume up straight away, so the beep for
+                        // this key is already at the new level.
+                        (MenuChoice::Volume, Some(digit)) => {
+                            self.set_volume(digit.min(MAX_VOLUME))?;
+                            return Ok(Mode::Idle);
+                        },
+                        (MenuChoice::Volume, None) if key == Key::Hash => {
+                            self.quiet_hours = !self.quiet_hours;
+                            self.settings.set_u32(SETTING_QUIET_HOURS, self.quiet_hours as u32)?;
+                            self.apply_volume();
+                            let text = if self.quiet_hours { "q on" } else { "q oF" };
+                            self.play_to_end(Animation::Message{segments: font::text(text, Align::Left), ms: MESSAGE_MS})?;
+                            return Ok(Mode::Idle);
+                        },
+                        _ => {},
+                    }
+                    self.show(entry_segments(digits))?;
+                } else if in_menu {
+                    // `# #` opens the function menu.
+                    in_menu = false;
+                    match key {
+                        Key::Digit(digit @ 1..=4) => {
+                            defrost_food = defrost_selection(digit);
+                            digits = [10; 4];
+                            self.show_text(&format!("dF {}", digit), Align::Left)?;
+                        },
+                        Key::Digit(5) => return Ok(Mode::KitchenTimer),
+                        Key::Digit(6) => {
+                            choosing = Some(MenuChoice::EditPreset);
+                            self.show_text("PrE", Align::Left)?;
+                        },
+                        Key::Digit(7) => return Ok(Mode::SetClock),
+                        Key::Digit(8) => {
+                            self.twelve_hour = !self.twelve_hour;
+                            self.settings.set_u32(SETTING_TWELVE_HOUR, self.twelve_hour as u32)?;
+                            return Ok(Mode::Idle);
+                        },
+                        Key::Digit(9) => {
+                            choosing = Some(MenuChoice::Learn);
+                            self.show_text("Lrn", Align::Left)?;
+                        },
+                        Key::Hash => {
+                            choosing = Some(MenuChoice::Volume);
+                            self.show_text(&format!("voL{}", self.volume), Align::Left)?;
+                        },
+                        Key::Digit(0) => {
+                            power = 0;
+                            self.show(power_segments(power))?;
+                        },
+                        _ => self.show(entry_segments(digits))?,
+                    }
+                } else if entering_power {
+                    // `#` followed by a digit sets the power level, with 0 meaning full power.
+                    // `# *` ends this stage and starts entering the next one.
+                    entering_power = false;
+                    match key {
+                        Key::Digit(digit) => {
+                            power = if digit == 0 { MAX_POWER } else { digit };
+                            self.show(power_segments(power))?;
+                        },
+                        Key::Hash => {
+                            in_menu = true;
+                            self.show_text("Fn", Align::Left)?;
+                        },
+                        Key::Star => {
+                            let stage = entered_stage(digits, power).filter(|_| defrost_food.is_none());
+                            if let Some(stage) = stage.filter(|_| stages.len() + 1 < MAX_STAGES) {
+                                stages.push(stage);
+                                digits = [10; 4];
+                                power = MAX_POWER;
+                                self.show(stage_segments(stages.len() as u8 + 1))?;
+                            } else {
+                                self.show(entry_segments(digits))?;
+                            }
+                        },
+                        Key::Letter(_) => self.show(entry_segments(digits))?,
+                    }
+                } else {
+                    match key {
+                        Key::Hash => {
+                            entering_power = true;
+                            self.show(power_segments(power))?;
+                        },
+                        // `*` deletes the last digit. With nothing to delete it goes back to the
+                        // stage before, or gives up if this is the first.
+                        Key::Star => {
+                            if digits == [10; 4] {
+                                let Some(stage) = stages.pop() else {
+                                    return Ok(Mode::Idle);
+                                };
+                                (digits, power) = stage_entry(stage);
+                            } else {
+                                digits = backspace(digits);
+                            }
+                            self.show(entry_segments(digits))?;
+                        },
+                        Key::Digit(digit) => {
+                            // Times take up to three digits, weights in grams up to four.
+                            let has_room = match defrost_food {
+                                Some(_) => digits[0] == 10,
+                                None => digits[1] == 10,
+                            };
+                            if has_room {
+                                digits = [digits[1], digits[2], digits[3], digit];
+                            }
+                            self.show(entry_segments(digits))?;
+                        },
+                        Key::Letter(_) => {},
+                    }
+                }
             }
-            if self.start_button.get_level() == Level::Low && self.door_switch.get_level() == Level::Low {
+            if self.start_button.is_low() && self.door_switch.is_low() {
                 self.speaker.play(self.sounds.start_sound())?;
-                let mut seconds = if digits[2] == 10 { 0 } else { digits[2] * 10 };
-                seconds += if digits[3] == 10 { 0 } else { digits[3] };
-                let mut minutes = if digits[0] == 10 { 0 } else { digits[0] * 10 };
-                minutes += if digits[1] == 10 { 0 } else { digits[1] };
-                return Ok(Mode::Running{seconds, minutes});
+                if let Some(food) = defrost_food {
+                    let grams = digits.iter()
+                        .fold(0u16, |grams, digit| grams * 10 + if *digit == 10 { 0 } else { *digit as u16 });
+                    self.load_program(defrost::schedule(food, grams));
+                    return Ok(self.next_stage());
+                }
+                stages.extend(entered_stage(digits, power));
+                if stages.is_empty() {
+                    return Ok(express_start(power));
+                }
+                self.load_program(stages);
+                return Ok(self.next_stage());
             }
-            if self.stop_button.get_level() == Level::Low {
+            if self.stop_button.is_low() {
                 self.speaker.play(self.sounds.beep_sound())?;
                 return Ok(Mode::Idle);
             }
@@ -206,20 +645,33 @@ impl<'a> App<'a> {
                 self.speaker.play(self.sounds.beep_sound())?;
                 return Ok(Mode::Idle);
             }
-            FreeRtos::delay_ms(50u32);
-            last_key = key;
+            self.poll_kitchen_timer()?;
+            self.timer.delay_ms(50)?;
         }
     }
 
-    fn run_running(&mut self, seconds: u8, minutes: u8) -> Result<Mode> {
-        let running_sound = self.sounds.running_sound();
-        self.speaker.play(running_sound)?;
+    fn run_running(&mut self, seconds: u8, minutes: u8, power: u8) -> Result<Mode> {
+        // Power 0 is standing time, with the light off and no running sound.
+        let (running_sound, mut running_controller) = self.sounds.running_sound();
+        let mut magnetron_on = power > 0;
+        if magnetron_on {
+            self.speaker.play(running_sound)?;
+            self.remote.send_on()?;
+        }
         let start_time = self.timer.counter()?;
         let mut last_seconds_elapsed = 0;
         let mut seconds = seconds;
         let mut minutes = minutes;
-        self.remote.send_on()?;
+        // Start is usually still held from whatever started the countdown.
+        let mut start_was_pressed = self.start_button.is_low();
+        // Programs show which stage this is for the first second.
+        if self.stage_count > 1 {
+            self.play(Animation::Still(stage_segments(self.stage)))?;
+        } else {
+            self.play(Animation::BlinkColon(time_segments(minutes, seconds)))?;
+        }
         loop {
+            self.poll_kitchen_timer()?;
             let elapsed = self.timer.counter()? - start_time;
             let seconds_elapsed = elapsed / self.timer.tick_hz();
             if seconds_elapsed != last_seconds_elapsed {
@@ -230,68 +682,551 @@ impl<'a> App<'a> {
                     seconds -= 1;
                 }
                 if minutes == 0 && seconds == 0 {
-                    self.speaker.clear();
+                    // Only the hum, the kitchen timer could be chiming.
+                    stop_running_sound(&mut running_controller);
                     self.remote.send_off()?;
-                    return Ok(Mode::Done);
+                    if !self.program.is_empty() {
+                        self.speaker.play(self.sounds.beep_sound())?;
+                    }
+                    return Ok(self.next_stage());
+                }
+                match self.kitchen_timer_segments()? {
+                    Some(segments) if seconds_elapsed % KITCHEN_TIMER_SHOW_EVERY == 0 => {
+                        self.play(Animation::Still(segments))?;
+                    },
+                    _ => self.play(Animation::BlinkColon(time_segments(minutes, seconds)))?,
                 }
-                let mut digits = [
-                    minutes as usize / 10,
-                    minutes as usize % 10,
-                    seconds as usize / 10,
-                    seconds as usize % 10,
-                ];
-                for i in 0..4 {
-                    if digits[i] == 0 {
-                        digits[i] = 10;
+
+                let on = seconds_elapsed % POWER_CYCLE_SECONDS < power as u64;
+                if on != magnetron_on {
+                    if on {
+                        self.remote.send_on()?;
+                        running_controller.set_volume(self.sounds.running_volume(true));
                     } else {
-                        break;
+                        self.remote.send_off()?;
+                        running_controller.set_volume(self.sounds.running_volume(false));
                     }
+                    magnetron_on = on;
                 }
-                
-                self.display.set_segments([
-                    DISPLAY_DIGITS[digits[0]],
-                    DISPLAY_DIGITS[digits[1]] | 0x80,
-                    DISPLAY_DIGITS[digits[2]],
-                    DISPLAY_DIGITS[digits[3]],
-                ])?;
                 last_seconds_elapsed = seconds_elapsed;
             }
-            if self.door_switch.get_level() == Level::High {
+            let start_pressed = self.start_button.is_low();
+            if start_pressed && !start_was_pressed {
+                let total = (minutes as u16 * 60 + seconds as u16 + EXPRESS_SECONDS).min(MAX_SECONDS);
+                minutes = (total / 60) as u8;
+                seconds = (total % 60) as u8;
+                self.speaker.play(self.sounds.beep_sound())?;
+                self.play(Animation::BlinkColon(time_segments(minutes, seconds)))?;
+            }
+            start_was_pressed = start_pressed;
+            if !self.door_switch.is_low() {
                 self.remote.send_off()?;
-                self.speaker.clear();
-                return Ok(Mode::Paused{seconds, minutes});
+                stop_running_sound(&mut running_controller);
+                self.speaker.play(self.sounds.door_sound())?;
+                return Ok(Mode::Paused{seconds, minutes, power});
             }
-            if self.stop_button.get_level() == Level::Low {
+            if self.stop_button.is_low() {
                 self.remote.send_off()?;
-                self.speaker.clear();
+                stop_running_sound(&mut running_controller);
+                self.speaker.play(self.sounds.beep_sound())?;
+                self.load_program(Vec::new());
+                return Ok(Mode::Idle);
+            }
+            self.animate()?;
+
+            self.timer.delay_ms(50)?;
+        }
+    }
+
+    /// The time of day with a blinking colon, or a blinking 12:00 if the clock was never set.
+    fn clock_segments(&self) -> Result<[u8; 4]> {
+        let half_seconds = self.timer.counter()? * 2 / self.timer.tick_hz();
+        let blink_on = half_seconds & 1 == 0;
+        let Some(time) = self.wall_clock.time_of_day() else {
+            return Ok(if blink_on { clock_digits(12, 0, true) } else { [0b00000000; 4] });
+        };
+        let minutes = time / 60 % 60;
+        let mut hours = time / 3600;
+        if self.twelve_hour {
+            hours %= 12;
+            if hours == 0 {
+                hours = 12;
+            }
+        }
+        Ok(clock_digits(hours, minutes, blink_on))
+    }
+
+    /// Takes a 24 hour HH:MM time from the keypad, confirmed with start.
+    fn run_set_clock(&mut self) -> Result<Mode> {
+        self.show(clock_entry_digits([10; 4]))?;
+        self.speaker.play(self.sounds.beep_sound())?;
+        let start_time = self.timer.counter()?;
+        const TIMEOUT: u64 = 60;
+
+        let mut digits = [10u8; 4];
+        loop {
+            if let Some(entered) = self.pressed_key()?.and_then(|key| edit_entry(digits, key)) {
+                digits = entered;
+                self.show(clock_entry_digits(digits))?;
+                self.speaker.play(self.sounds.beep_sound())?;
+            }
+            if self.start_button.is_low() {
+                let value = |digit: u8| if digit == 10 { 0 } else { digit as u32 };
+                let hours = value(digits[0]) * 10 + value(digits[1]);
+                let minutes = value(digits[2]) * 10 + value(digits[3]);
+                if hours < 24 && minutes < 60 {
+                    self.wall_clock.set_time_of_day((hours * 60 + minutes) * 60)?;
+                    self.speaker.play(self.sounds.start_sound())?;
+                    self.wait_for_start_release()?;
+                    return Ok(Mode::Idle);
+                }
+                // Not a real time, so start again.
+                self.speaker.play(self.sounds.beep_sound())?;
+                digits = [10; 4];
+                self.show(clock_entry_digits(digits))?;
+                self.wait_for_start_release()?;
+            }
+            if self.stop_button.is_low() {
+                self.speaker.play(self.sounds.beep_sound())?;
+                return Ok(Mode::Idle);
+            }
+            let elapsed = self.timer.counter()? - start_time;
+            let seconds_elapsed = elapsed / self.timer.tick_hz();
+            if seconds_elapsed > TIMEOUT {
+                return Ok(Mode::Idle);
+            }
+            self.poll_kitchen_timer()?;
+            self.timer.delay_ms(50)?;
+        }
+    }
+
+    /// Takes an MM:SS time from the keypad and starts the kitchen timer with it on start.
+    fn run_kitchen_timer(&mut self) -> Result<Mode> {
+        self.show(kitchen_timer_entry_digits([10; 4]))?;
+        let start_time = self.timer.counter()?;
+        const TIMEOUT: u64 = 60;
+
+        let mut digits = [10u8; 4];
+        loop {
+            if let Some(entered) = self.pressed_key()?.and_then(|key| edit_entry(digits, key)) {
+                digits = entered;
+                self.show(kitchen_timer_entry_digits(digits))?;
+                self.speaker.play(self.sounds.beep_sound())?;
+            }
+            if self.start_button.is_low() {
+                let value = |digit: u8| if digit == 10 { 0 } else { digit as u64 };
+                let minutes = value(digits[0]) * 10 + value(digits[1]);
+                let seconds = minutes * 60 + value(digits[2]) * 10 + value(digits[3]);
+                if seconds > 0 {
+                    self.kitchen_timer = Some(self.timer.counter()? + seconds * self.timer.tick_hz());
+                    self.speaker.play(self.sounds.start_sound())?;
+                } else {
+                    self.speaker.play(self.sounds.beep_sound())?;
+                }
+                // Otherwise idle would take the same press as an express start.
+                self.wait_for_start_release()?;
+                return Ok(Mode::Idle);
+            }
+            if self.stop_button.is_low() {
+                self.speaker.play(self.sounds.beep_sound())?;
+                return Ok(Mode::Idle);
+            }
+            let elapsed = self.timer.counter()? - start_time;
+            let seconds_elapsed = elapsed / self.timer.tick_hz();
+            if seconds_elapsed > TIMEOUT {
+                return Ok(Mode::Idle);
+            }
+            self.poll_kitchen_timer()?;
+            self.timer.delay_ms(50)?;
+        }
+    }
+
+    /// Shows the clock and ignores the keypad and start until stop is held to unlock. Remote
+    /// learning can only be started from the keypad, so IR is ignored too.
+    fn run_locked(&mut self, key_pressed: bool) -> Result<Mode> {
+        let start_time = self.timer.counter()?;
+        const TIMEOUT: u64 = 60;
+        let mut shown = None;
+        let mut key_pressed = key_pressed;
+        let mut start_was_pressed = false;
+        loop {
+            self.poll_kitchen_timer()?;
+            let start_pressed = self.start_button.is_low();
+            if std::mem::take(&mut key_pressed) || self.pressed_key()?.is_some() || (start_pressed && !start_was_pressed) {
+                self.play(locked_message())?;
+                shown = None;
+            }
+            start_was_pressed = start_pressed;
+            self.show_clock(&mut shown)?;
+            if self.stop_button.is_low() && self.stop_held_for(LOCK_HOLD_MS)? {
+                self.set_locked(false)?;
+                self.speaker.play(self.sounds.beep_sound())?;
+                self.wait_for_stop_release()?;
+                return Ok(Mode::Idle);
+            }
+            let elapsed = self.timer.counter()? - start_time;
+            let seconds_elapsed = elapsed / self.timer.tick_hz();
+            if seconds_elapsed > TIMEOUT && self.kitchen_timer.is_none() {
+                return self.run_sleep();
+            }
+            self.timer.delay_ms(50)?;
+        }
+    }
+
+    /// Starts `animation` and shows its first frame.
+    fn play(&mut self, animation: Animation) -> Result<()> {
+        let now_ms = self.now_ms()?;
+        self.animator.play(animation, now_ms);
+        self.animator.tick(now_ms, self.display.as_mut())
+    }
+
+    /// Shows `segments` until something else is played. Everything on the display goes
+    /// through the animator, so nothing left playing can draw over it.
+    fn show(&mut self, segments: [u8; 4]) -> Result<()> {
+        self.play(Animation::Still(segments))
+    }
+
+    fn show_text(&mut self, text: &str, align: Align) -> Result<()> {
+        self.show(font::text(text, align))
+    }
+
+    /// Shows the playing animation's frame for now. Loops that play one call this each time
+    /// round.
+    fn animate(&mut self) -> Result<()> {
+        let now_ms = self.now_ms()?;
+        self.animator.tick(now_ms, self.display.as_mut())
+    }
+
+    /// Plays `animation` through to the end, for modes with nothing else to do meanwhile.
+    fn play_to_end(&mut self, animation: Animation) -> Result<()> {
+        self.play(animation)?;
+        while !self.animator.is_finished(self.now_ms()?) {
+            self.timer.delay_ms(50)?;
+            self.poll_kitchen_timer()?;
+            self.animate()?;
+        }
+        Ok(())
+    }
+
+    /// Shows the kitchen timer, or the clock when there isn't one, if it's changed from
+    /// `shown`. Messages and fades are left to finish first.
+    fn show_clock(&mut self, shown: &mut Option<[u8; 4]>) -> Result<()> {
+        let segments = match self.kitchen_timer_segments()? {
+            Some(segments) => segments,
+            None => self.clock_segments()?,
+        };
+        let now_ms = self.now_ms()?;
+        if shown.is_none() && std::mem::take(&mut self.fade_in) {
+            self.play(Animation::FadeIn(segments))?;
+            *shown = Some(segments);
+        } else if *shown != Some(segments) && !self.animator.is_holding(now_ms) {
+            self.play(Animation::Still(segments))?;
+            *shown = Some(segments);
+        }
+        self.animate()
+    }
+
+    fn now_ms(&self) -> Result<u64> {
+        Ok(self.timer.counter()? * 1000 / self.timer.tick_hz())
+    }
+
+    /// Scans the keypad and returns the oldest key event not yet handled.
+    fn key_event(&mut self) -> Result<Option<KeyEvent>> {
+        let scan = self.keypad.get_key()?;
+        let now_ms = self.now_ms()?;
+        self.key_events.update(now_ms, scan);
+        Ok(self.key_events.next_event())
+    }
+
+    /// Like `key_event`, for loops that only care about keys going down.
+    fn pressed_key(&mut self) -> Result<Option<Key>> {
+        Ok(match self.key_event()? {
+            Some(KeyEvent::Pressed(key)) => Some(key),
+            _ => None,
+        })
+    }
+
+    fn set_locked(&mut self, locked: bool) -> Result<()> {
+        self.locked = locked;
+        self.settings.set_u32(SETTING_CHILD_LOCK, locked as u32)
+    }
+
+    fn set_volume(&mut self, volume: u8) -> Result<()> {
+        self.volume = volume;
+        self.apply_volume();
+        self.settings.set_u32(SETTING_VOLUME, volume as u32)
+    }
+
+    /// Gives the speaker the volume for now, if it's changed.
+    fn apply_volume(&mut self) {
+        let quiet = self.quiet_hours && self.wall_clock.time_of_day()
+            .is_some_and(|time| is_quiet_hour(time / 3600));
+        let volume = if quiet { self.volume.min(QUIET_VOLUME) } else { self.volume };
+        if self.speaker_volume != Some(volume) {
+            self.speaker.set_volume(volume_gain(volume));
+            self.speaker_volume = Some(volume);
+        }
+    }
+
+    /// Waits while stop is held, returning true if it was still held after `ms`.
+    fn stop_held_for(&mut self, ms: u64) -> Result<bool> {
+        let pressed_at = self.timer.counter()?;
+        while self.stop_button.is_low() {
+            if (self.timer.counter()? - pressed_at) * 1000 / self.timer.tick_hz() >= ms {
+                return Ok(true);
+            }
+            self.poll_kitchen_timer()?;
+            self.timer.delay_ms(50)?;
+        }
+        Ok(false)
+    }
+
+    fn wait_for_stop_release(&mut self) -> Result<()> {
+        while self.stop_button.is_low() {
+            self.timer.delay_ms(50)?;
+        }
+        Ok(())
+    }
+
+    fn wait_for_start_release(&mut self) -> Result<()> {
+        while self.start_button.is_low() {
+            self.timer.delay_ms(50)?;
+        }
+        Ok(())
+    }
+
+    fn preset(&self, dish: Dish) -> Result<Preset> {
+        let stored = self.settings.get_u32(dish.setting_key())?.and_then(Preset::from_u32);
+        Ok(stored.unwrap_or_else(|| dish.default_preset()))
+    }
+
+    /// Shows the dish's name, then starts its countdown, or waits for the door to close.
+    fn run_preset(&mut self, dish: Dish) -> Result<Mode> {
+        let preset = self.preset(dish)?;
+        if let Some(sound) = self.sounds.preset_sound(preset.sound) {
+            self.speaker.play(sound)?;
+        }
+        self.play_to_end(dish_message(dish))?;
+
+        let seconds = (preset.seconds % 60) as u8;
+        let minutes = (preset.seconds / 60) as u8;
+        if !self.door_switch.is_low() {
+            return Ok(Mode::Paused{seconds, minutes, power: preset.power});
+        }
+        Ok(Mode::Running{seconds, minutes, power: preset.power})
+    }
+
+    /// Changes a preset's time, power with `#` and sound with `# #`, saved with start.
+    fn run_edit_preset(&mut self, dish: Dish) -> Result<Mode> {
+        let mut preset = self.preset(dish)?;
+        self.play_to_end(dish_message(dish))?;
+        self.show(time_segments((preset.seconds / 60) as u8, (preset.seconds % 60) as u8))?;
+        let start_time = self.timer.counter()?;
+        const TIMEOUT: u64 = 60;
+
+        let mut digits = [10u8; 4];
+        let mut entering_power = false;
+        loop {
+            if let Some(key) = self.pressed_key()? {
+                self.speaker.play(self.sounds.beep_sound())?;
+                match key {
+                    Key::Digit(digit) if entering_power => {
+                        entering_power = false;
+                        preset.power = if digit == 0 { MAX_POWER } else { digit };
+                        self.show(power_segments(preset.power))?;
+                    },
+                    Key::Hash if !entering_power => {
+                        entering_power = true;
+                        self.show(power_segments(preset.power))?;
+                    },
+                    Key::Hash => {
+                        entering_power = false;
+                        preset.sound = preset.sound.next();
+                        self.speaker.clear();
+                        if let Some(sound) = self.sounds.preset_sound(preset.sound) {
+                            self.speaker.play(sound)?;
+                        }
+                        self.show_text(&format!("Snd{}", preset.sound as u8 + 1), Align::Left)?;
+                    },
+                    _ => {
+                        entering_power = false;
+                        if let Some(entered) = edit_entry(digits, key) {
+                            digits = entered;
+                        }
+                        self.show(clock_entry_digits(digits))?;
+                    },
+                }
+            }
+            if self.start_button.is_low() {
+                // With no digits typed the time stays as it was, but a typed 0:00 can't be cooked.
+                match entered_stage(digits, MAX_POWER) {
+                    Some(Stage::Cook{seconds, ..}) => preset.seconds = seconds,
+                    _ if digits != [10; 4] => {
+                        self.speaker.play(self.sounds.beep_sound())?;
+                        digits = [10; 4];
+                        self.show(clock_entry_digits(digits))?;
+                        self.wait_for_start_release()?;
+                        continue;
+                    },
+                    _ => {},
+                }
+                self.settings.set_u32(dish.setting_key(), preset.to_u32())?;
+                self.speaker.play(self.sounds.start_sound())?;
+                self.wait_for_start_release()?;
+                return Ok(Mode::Idle);
+            }
+            if self.stop_button.is_low() {
                 self.speaker.play(self.sounds.beep_sound())?;
                 return Ok(Mode::Idle);
             }
+            let elapsed = self.timer.counter()? - start_time;
+            let seconds_elapsed = elapsed / self.timer.tick_hz();
+            if seconds_elapsed > TIMEOUT {
+                return Ok(Mode::Idle);
+            }
+            self.poll_kitchen_timer()?;
+            self.timer.delay_ms(50)?;
+        }
+    }
 
-            FreeRtos::delay_ms(50u32);
+    /// Chimes once the kitchen timer has run out.
+    fn poll_kitchen_timer(&mut self) -> Result<()> {
+        if let Some(deadline) = self.kitchen_timer {
+            if self.timer.counter()? >= deadline {
+                self.kitchen_timer = None;
+                self.speaker.play(self.sounds.chime_sound())?;
+            }
         }
+        Ok(())
     }
 
+    /// The kitchen timer's remaining time behind a `t`, as M:SS under ten minutes and whole
+    /// minutes above that.
+    fn kitchen_timer_segments(&self) -> Result<Option<[u8; 4]>> {
+        let Some(deadline) = self.kitchen_timer else {
+            return Ok(None);
+        };
+        let tick_hz = self.timer.tick_hz();
+        let remaining = (deadline.saturating_sub(self.timer.counter()?) + tick_hz - 1) / tick_hz;
+        if remaining < 10 * 60 {
+            return Ok(Some(font::text(&format!("t{}:{:02}", remaining / 60, remaining % 60), Align::Left)));
+        }
+        let minutes = ((remaining + 59) / 60).min(99);
+        Ok(Some(font::text(&format!("t{:>3}", minutes), Align::Left)))
+    }
+
+    fn load_program(&mut self, stages: Vec<Stage>) {
+        self.stage = 0;
+        self.stage_count = stages.iter().filter(|stage| **stage != Stage::TurnOver).count() as u8;
+        self.program = stages.into();
+    }
+
+    /// Starts the next stage of the program, or finishes if there isn't one.
+    fn next_stage(&mut self) -> Mode {
+        match self.program.pop_front() {
+            Some(Stage::Cook{seconds, power}) => {
+                self.stage += 1;
+                Mode::Running{
+                    seconds: (seconds % 60) as u8,
+                    minutes: (seconds / 60) as u8,
+                    power,
+                }
+            },
+            Some(Stage::Stand{seconds}) => {
+                self.stage += 1;
+                Mode::Running{
+                    seconds: (seconds % 60) as u8,
+                    minutes: (seconds / 60) as u8,
+                    power: 0,
+                }
+            },
+            Some(Stage::TurnOver) => Mode::TurnOver,
+            None => Mode::Done,
+        }
+    }
+
+    /// Beeps and shows `turn`, then waits for start once the food has been turned over.
+    fn run_turn_over(&mut self) -> Result<Mode> {
+        const BEEPS: u64 = 3;
+        const TIMEOUT_MS: u64 = 5 * 60 * 1000;
+        self.show_text("turn", Align::Left)?;
+        let start_ms = self.now_ms()?;
+        let mut beeps = 0;
+        let mut start_was_pressed = self.start_button.is_low();
+        loop {
+            let elapsed_ms = self.now_ms()? - start_ms;
+            if beeps < BEEPS && elapsed_ms >= beeps * 500 {
+                self.speaker.play(self.sounds.beep_sound())?;
+                beeps += 1;
+            }
+            let start_pressed = self.start_button.is_low();
+            if start_pressed && !start_was_pressed && self.door_switch.is_low() {
+                self.speaker.play(self.sounds.start_sound())?;
+                return Ok(self.next_stage());
+            }
+            start_was_pressed = start_pressed;
+            if self.stop_button.is_low() || elapsed_ms > TIMEOUT_MS {
+                self.speaker.play(self.sounds.beep_sound())?;
+                return Ok(Mode::Idle);
+            }
+            self.poll_kitchen_timer()?;
+            self.timer.delay_ms(50)?;
+        }
+    }
+
+    /// Blinks `End` with a beep a second, then scrolls a message once.
     fn run_done(&mut self) -> Result<Mode> {
-        for _ in 0..5 {
-            self.speaker.play(self.sounds.beep_sound())?;
-            self.display.set_segments([0b01111111, 0b01111001, 0b01111001, 0b01110011])?;
-            FreeRtos::delay_ms(500u32);
-            self.display.set_segments([0b00000000, 0b00000000, 0b00000000, 0b00000000])?;
-            FreeRtos::delay_ms(500u32);
+        const BEEPS: u64 = 5;
+        let start_ms = self.now_ms()?;
+        let mut beeps = 0;
+        let mut scrolling = false;
+        self.play(Animation::Blink(font::text("End", Align::Center)))?;
+        loop {
+            let elapsed_ms = self.now_ms()? - start_ms;
+            if beeps < BEEPS && elapsed_ms >= beeps * 1000 {
+                if let Some(sound) = self.sounds.done_sound(beeps) {
+                    self.speaker.play(sound)?;
+                }
+                beeps += 1;
+            }
+            if !scrolling && elapsed_ms >= BEEPS * 1000 {
+                self.play(Animation::Scroll{marquee: Marquee::new(DONE_MESSAGE), looping: false})?;
+                scrolling = true;
+            }
+            // Stop or a key cuts the message short.
+            if scrolling && (self.stop_button.is_low() || self.pressed_key()?.is_some()) {
+                return Ok(Mode::Idle);
+            }
+            self.animate()?;
+            if scrolling && self.animator.is_finished(self.now_ms()?) {
+                return Ok(Mode::Idle);
+            }
+            self.poll_kitchen_timer()?;
+            self.timer.delay_ms(50)?;
         }
-        Ok(Mode::Idle)
     }
 
-    fn run_paused(&mut self, seconds: u8, minutes: u8) -> Result<Mode> {
+    /// Scrolls a reminder while the door is open, and blinks the time left once it's shut.
+    fn run_paused(&mut self, seconds: u8, minutes: u8, power: u8) -> Result<Mode> {
         let start_time = self.timer.counter()?;
         const TIMEOUT: u64 = 60 * 5;
+        let mut door_was_closed = None;
         loop {
-            if self.start_button.get_level() == Level::Low && self.door_switch.get_level() == Level::Low {
+            let door_closed = self.door_switch.is_low();
+            if door_was_closed != Some(door_closed) {
+                self.play(if door_closed {
+                    Animation::Blink(time_segments(minutes, seconds))
+                } else {
+                    Animation::Scroll{marquee: Marquee::new(DOOR_OPEN_MESSAGE), looping: true}
+                })?;
+                door_was_closed = Some(door_closed);
+            }
+            self.animate()?;
+            if self.start_button.is_low() && door_closed {
                 self.speaker.play(self.sounds.beep_sound())?;
-                return Ok(Mode::Running{seconds, minutes});
+                return Ok(Mode::Running{seconds, minutes, power});
             }
-            if self.stop_button.get_level() == Level::Low {
+            if self.stop_button.is_low() {
                 self.speaker.play(self.sounds.beep_sound())?;
                 return Ok(Mode::Idle);
             }
@@ -301,42 +1236,273 @@ impl<'a> App<'a> {
                 self.speaker.play(self.sounds.beep_sound())?;
                 return Ok(Mode::Idle);
             }
-            FreeRtos::delay_ms(50u32);
+            self.poll_kitchen_timer()?;
+            self.timer.delay_ms(50)?;
         }
     }
 
     fn run_sleep(&mut self) -> Result<Mode> {
-        Ok(Mode::Idle)
+        self.speaker.clear();
+        self.play_to_end(Animation::FadeOut(self.animator.segments()))?;
+        // Leave the display blank at full brightness for whatever writes to it next.
+        self.play(Animation::Still([0b00000000; 4]))?;
+        self.display.flush()?;
+        // Give the I2S DMA time to flush silence so the amp doesn't pop on power down.
+        self.timer.delay_ms(50)?;
+        self.keypad.prepare_for_sleep()?;
+        self.power.deep_sleep()?;
+        self.wake_mode()
+    }
+
+    fn run_learn(&mut self, code: LightCode) -> Result<Mode> {
+        // Which code it's learning, then the spinner while it listens.
+        let text = font::text(&format!("Lrn{}", code as u8 + 1), Align::Left);
+        self.play(Animation::Message{segments: text, ms: MESSAGE_MS})?;
+        let start_time = self.timer.counter()?;
+        const TIMEOUT: u64 = 10;
+        let mut spinning = false;
+        loop {
+            self.poll_kitchen_timer()?;
+            if !spinning && self.animator.is_finished(self.now_ms()?) {
+                self.play(Animation::Spinner)?;
+                spinning = true;
+            }
+            self.animate()?;
+            // Listen in windows as short as a spinner step, so it keeps turning and the stop
+            // button stays responsive.
+            if self.remote.learn(code, 100)? {
+                self.speaker.play(self.sounds.start_sound())?;
+                return Ok(Mode::Idle);
+            }
+            if self.stop_button.is_low() {
+                self.speaker.play(self.sounds.beep_sound())?;
+                return Ok(Mode::Idle);
+            }
+            let elapsed = self.timer.counter()? - start_time;
+            let seconds_elapsed = elapsed / self.timer.tick_hz();
+            if seconds_elapsed > TIMEOUT {
+                self.speaker.play(self.sounds.beep_sound())?;
+                self.play_to_end(Animation::Message{segments: font::text("Err", Align::Left), ms: MESSAGE_MS})?;
+                return Ok(Mode::Idle);
+            }
+        }
+    }
+}
+
+/// Ends the running sound, which would otherwise loop for as long as the mixer plays it.
+fn stop_running_sound(controller: &mut Controller<AdjustableVolume<ClipSound>>) {
+    controller.send_command(Box::new(|sound: &mut AdjustableVolume<ClipSound>| sound.inner_mut().stop()));
+}
+
+fn express_start(power: u8) -> Mode {
+    Mode::Running{seconds: (EXPRESS_SECONDS % 60) as u8, minutes: (EXPRESS_SECONDS / 60) as u8, power}
+}
+
+fn clock_digits(hours: u32, minutes: u32, colon: bool) -> [u8; 4] {
+    [
+        if hours >= 10 { DISPLAY_DIGITS[hours as usize / 10] } else { 0b00000000 },
+        DISPLAY_DIGITS[hours as usize % 10] | if colon { font::POINT } else { 0 },
+        DISPLAY_DIGITS[minutes as usize / 10],
+        DISPLAY_DIGITS[minutes as usize % 10],
+    ]
+}
+
+/// A countdown time with the colon lit, blanking leading zeros.
+fn time_segments(minutes: u8, seconds: u8) -> [u8; 4] {
+    let mut digits = [
+        minutes as usize / 10,
+        minutes as usize % 10,
+        seconds as usize / 10,
+        seconds as usize % 10,
+    ];
+    for digit in digits.iter_mut() {
+        if *digit == 0 {
+            *digit = 10;
+        } else {
+            break;
+        }
+    }
+
+    [
+        DISPLAY_DIGITS[digits[0]],
+        DISPLAY_DIGITS[digits[1]] | font::POINT,
+        DISPLAY_DIGITS[digits[2]],
+        DISPLAY_DIGITS[digits[3]],
+    ]
+}
+
+fn weight_segments(digits: [u8; 4]) -> [u8; 4] {
+    digits.map(|digit| DISPLAY_DIGITS[digit as usize])
+}
+
+fn clock_entry_digits(digits: [u8; 4]) -> [u8; 4] {
+    [
+        DISPLAY_DIGITS[digits[0] as usize],
+        DISPLAY_DIGITS[digits[1] as usize] | font::POINT,
+        DISPLAY_DIGITS[digits[2] as usize],
+        DISPLAY_DIGITS[digits[3] as usize],
+    ]
+}
+
+/// Like `clock_entry_digits`, with a `t` in the first digit until it's needed.
+fn kitchen_timer_entry_digits(digits: [u8; 4]) -> [u8; 4] {
+    let mut segments = clock_entry_digits(digits);
+    if digits[0] == 10 {
+        segments[0] = font::glyph('t').unwrap_or(0);
+    }
+    segments
+}
+
+/// Power 0 is standing time, shown as `Stnd`.
+fn power_segments(power: u8) -> [u8; 4] {
+    if power == 0 {
+        return font::text("Stnd", Align::Left);
+    }
+    font::text(&format!("PL{:>2}", power), Align::Left)
+}
+
+const LOCKED_TEXT: &str = "LOC";
+
+fn locked_message() -> Animation {
+    Animation::Message{segments: font::text(LOCKED_TEXT, Align::Left), ms: MESSAGE_MS}
+}
+
+fn dish_message(dish: Dish) -> Animation {
+    Animation::Message{segments: font::text(dish_name(dish), Align::Left), ms: MESSAGE_MS}
+}
+
+fn dish_name(dish: Dish) -> &'static str {
+    match dish {
+        Dish::Popcorn => "POP",
+        Dish::Beverage => "bEv",
+        Dish::Potato => "Pot",
+        Dish::Pizza => "PIZ",
+    }
+}
+
+fn stage_segments(stage: u8) -> [u8; 4] {
+    font::text(&format!("St{:>2}", stage.min(99)), Align::Left)
+}
+
+fn is_quiet_hour(hour: u32) -> bool {
+    !(QUIET_UNTIL_HOUR..QUIET_FROM_HOUR).contains(&hour)
+}
+
+/// The speaker volume for a level from the menu. Going by the square of the level makes the
+/// steps sound more even than a straight line would.
+fn volume_gain(level: u8) -> f32 {
+    let fraction = level as f32 / MAX_VOLUME as f32;
+    fraction * fraction
+}
+
+/// The stage for a time typed as MM:SS, if one was typed. Seconds past 59 carry into the
+/// minutes, up to 99:59.
+fn entered_stage(digits: [u8; 4], power: u8) -> Option<Stage> {
+    let value = |digit: u8| if digit == 10 { 0 } else { digit as u16 };
+    let minutes = value(digits[0]) * 10 + value(digits[1]);
+    let seconds = (minutes * 60 + value(digits[2]) * 10 + value(digits[3])).min(MAX_SECONDS);
+    match (seconds, power) {
+        (0, _) => None,
+        (_, 0) => Some(Stage::Stand{seconds}),
+        _ => Some(Stage::Cook{seconds, power}),
+    }
+}
+
+/// The digits and power that `entered_stage` would read back as `stage`.
+fn stage_entry(stage: Stage) -> ([u8; 4], u8) {
+    let (seconds, power) = match stage {
+        Stage::Cook{seconds, power} => (seconds, power),
+        Stage::Stand{seconds} => (seconds, 0),
+        Stage::TurnOver => (0, MAX_POWER),
+    };
+    let (minutes, seconds) = (seconds / 60, seconds % 60);
+    let mut digits = [minutes / 10, minutes % 10, seconds / 10, seconds % 10].map(|digit| digit as u8);
+    for digit in digits.iter_mut() {
+        if *digit != 0 {
+            break;
+        }
+        *digit = 10;
+    }
+    (digits, power)
+}
+
+fn defrost_selection(digit: u8) -> Option<Food> {
+    match digit {
+        1 => Some(Food::Meat),
+        2 => Some(Food::Poultry),
+        3 => Some(Food::Fish),
+        4 => Some(Food::Bread),
+        _ => None,
     }
 }
 
-pub fn run_app() -> Result<()> {
-    let peripherals = Peripherals::take()?;
-    let display = SevenSegment::new(peripherals.pins.gpio16, peripherals.pins.gpio17)?;
-    let keypad = Keypad::new(
-        peripherals.pins.gpio14, peripherals.pins.gpio25, peripherals.pins.gpio21,
-        peripherals.pins.gpio26, peripherals.pins.gpio19, peripherals.pins.gpio22, peripherals.pins.gpio13,
-    )?;
-    let speaker = Speaker::new(
-        peripherals.i2s0,
-        peripherals.pins.gpio15, peripherals.pins.gpio23, peripherals.pins.gpio4,
-    )?;
-    let timer = TimerDriver::new(peripherals.timer00, &Config::default())?;
-    let start_button = PinDriver::input(peripherals.pins.gpio34.into_ref().map_into::<AnyInputPin>())?;
-    let stop_button = PinDriver::input(peripherals.pins.gpio35.into_ref().map_into::<AnyInputPin>())?;
-    let door_switch = PinDriver::input(peripherals.pins.gpio39.into_ref().map_into::<AnyInputPin>())?;
-    let remote = Remote::new(peripherals.rmt.channel0, peripherals.pins.gpio12)?;
-
-    let mut app = App::new(
-        display,
-        keypad,
-        speaker,
-        timer,
-        start_button,
-        stop_button,
-        door_switch,
-        remote,
-    );
-
-    app.run()
+fn learn_code(digit: u8) -> Option<LightCode> {
+    match digit {
+        1 => Some(LightCode::On),
+        2 => Some(LightCode::Off),
+        3 => Some(LightCode::Brighter),
+        4 => Some(LightCode::Dimmer),
+        _ => None,
+    }
+}
+
+/// Drops the last digit typed into an entry.
+fn backspace(digits: [u8; 4]) -> [u8; 4] {
+    [10, digits[0], digits[1], digits[2]]
+}
+
+/// Types a digit into a four digit entry, or deletes one with `*`.
+fn edit_entry(digits: [u8; 4], key: Key) -> Option<[u8; 4]> {
+    match key {
+        Key::Digit(digit) if digits[0] == 10 => Some([digits[1], digits[2], digits[3], digit]),
+        Key::Digit(_) => Some(digits),
+        Key::Star => Some(backspace(digits)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entered_times_are_capped_at_99_59() {
+        assert_eq!(entered_stage([1, 3, 0, 0], MAX_POWER), Some(Stage::Cook{seconds: 13 * 60, power: MAX_POWER}));
+        assert_eq!(entered_stage([10, 10, 9, 0], 5), Some(Stage::Cook{seconds: 90, power: 5}));
+        assert_eq!(entered_stage([9, 9, 5, 9], MAX_POWER), Some(Stage::Cook{seconds: MAX_SECONDS, power: MAX_POWER}));
+        assert_eq!(entered_stage([9, 9, 9, 9], MAX_POWER), Some(Stage::Cook{seconds: MAX_SECONDS, power: MAX_POWER}));
+        assert_eq!(entered_stage([9, 9, 6, 0], 0), Some(Stage::Stand{seconds: MAX_SECONDS}));
+        assert_eq!(entered_stage([10, 10, 10, 10], MAX_POWER), None);
+    }
+
+    #[test]
+    fn entered_stages_can_be_typed_again() {
+        for stage in [Stage::Cook{seconds: 90, power: 5}, Stage::Stand{seconds: 605}, Stage::Cook{seconds: MAX_SECONDS, power: MAX_POWER}] {
+            let (digits, power) = stage_entry(stage);
+            assert_eq!(entered_stage(digits, power), Some(stage));
+        }
+        assert_eq!(stage_entry(Stage::Cook{seconds: 90, power: 5}), ([10, 1, 3, 0], 5));
+    }
+
+    #[test]
+    fn stages_past_nine_show_both_digits() {
+        assert_eq!(stage_segments(2), font::text("St 2", Align::Left));
+        assert_eq!(stage_segments(12), font::text("St12", Align::Left));
+    }
+
+    #[test]
+    fn volume_levels_go_by_the_square() {
+        assert_eq!(volume_gain(0), 0.0);
+        assert_eq!(volume_gain(MAX_VOLUME), 1.0);
+        assert!((volume_gain(3) - 1.0 / 9.0).abs() < 1e-6);
+        for level in 1..=MAX_VOLUME {
+            assert!(volume_gain(level) > volume_gain(level - 1));
+        }
+    }
+
+    #[test]
+    fn quiet_hours_run_from_ten_at_night_to_seven() {
+        let quiet: Vec<u32> = (0..24).filter(|hour| is_quiet_hour(*hour)).collect();
+        assert_eq!(quiet, [0, 1, 2, 3, 4, 5, 6, 22, 23]);
+    }
 }
@@ -0,0 +1,276 @@
+use anyhow::Result;
+use esp_idf_svc::hal::{
+    peripherals::Peripherals,
+    timer::*,
+    gpio::{
+        AnyInputPin,
+        Input,
+        PinDriver,
+        IOPin,
+        Level,
+    },
+    timer::config::Config,
+    delay::FreeRtos, peripheral::Peripheral,
+};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::{
+    esp,
+    esp_deep_sleep_start,
+    esp_vfs_fat_mount_config_t,
+    esp_vfs_fat_spiflash_mount_ro,
+    esp_sleep_enable_ext0_wakeup,
+    esp_sleep_enable_ext1_wakeup,
+    esp_sleep_ext1_wakeup_mode_t_ESP_EXT1_WAKEUP_ANY_HIGH,
+    esp_sleep_get_ext1_wakeup_status,
+    esp_sleep_get_wakeup_cause,
+    esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT0,
+    esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT1,
+    gpio_get_level,
+};
+use crate::app::App;
+use crate::hal::{Board, Clock, Files, InputLine, Power, Settings, WakeReason, WallClock};
+use std::ffi::CString;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(not(any(feature = "display-max7219", feature = "display-ht16k33")))]
+use crate::seven_segment::SevenSegment;
+#[cfg(feature = "display-max7219")]
+use crate::{max7219, max7219_display::Max7219Display};
+#[cfg(feature = "display-ht16k33")]
+use crate::{ht16k33, ht16k33_display::Ht16k33Display};
+use crate::keypad::Keypad;
+use crate::keys;
+use crate::speaker::Speaker;
+use crate::remote::Remote;
+
+#[cfg(all(feature = "display-max7219", feature = "display-ht16k33"))]
+compile_error!("only one of the display-max7219 and display-ht16k33 features can be enabled");
+
+pub struct EspClock<'d> {
+    timer: TimerDriver<'d>,
+}
+
+impl<'d> EspClock<'d> {
+    pub fn new(mut timer: TimerDriver<'d>) -> Result<Self> {
+        timer.enable(true)?;
+        Ok(Self { timer })
+    }
+}
+
+impl Clock for EspClock<'_> {
+    fn counter(&self) -> Result<u64> {
+        Ok(self.timer.counter()?)
+    }
+
+    fn tick_hz(&self) -> u64 {
+        self.timer.tick_hz()
+    }
+
+    fn delay_ms(&mut self, ms: u32) -> Result<()> {
+        FreeRtos::delay_ms(ms);
+        Ok(())
+    }
+}
+
+impl InputLine for PinDriver<'_, AnyInputPin, Input> {
+    fn is_low(&self) -> bool {
+        self.get_level() == Level::Low
+    }
+}
+
+const START_BUTTON_GPIO: i32 = 34;
+const DOOR_SWITCH_GPIO: i32 = 39;
+// ext1 can only wake on "any high" or "all low", so the keypad is armed with the rows driven
+// high and the columns pulled down. Only the first two columns sit on RTC GPIOs, so keys in
+// the right-hand column (GPIO21) can't wake the board.
+const KEYPAD_WAKE_GPIOS: [i32; 2] = [14, 25];
+
+pub struct EspPower;
+
+impl Power for EspPower {
+    fn wake_reason(&self) -> WakeReason {
+        match unsafe { esp_sleep_get_wakeup_cause() } {
+            esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT0 => WakeReason::StartButton,
+            esp_sleep_source_t_ESP_SLEEP_WAKEUP_EXT1 => {
+                let status = unsafe { esp_sleep_get_ext1_wakeup_status() };
+                if status & (1 << DOOR_SWITCH_GPIO) != 0 {
+                    WakeReason::Door
+                } else {
+                    WakeReason::Keypad
+                }
+            },
+            _ => WakeReason::PowerOn,
+        }
+    }
+
+    fn deep_sleep(&mut self) -> Result<()> {
+        let mut ext1_mask = KEYPAD_WAKE_GPIOS.iter().fold(0u64, |mask, gpio| mask | (1 << gpio));
+        // An open door would wake us straight back up, so only watch it if it's closed.
+        if unsafe { gpio_get_level(DOOR_SWITCH_GPIO) } == 0 {
+            ext1_mask |= 1 << DOOR_SWITCH_GPIO;
+        }
+        unsafe {
+            esp!(esp_sleep_enable_ext0_wakeup(START_BUTTON_GPIO, 0))?;
+            esp!(esp_sleep_enable_ext1_wakeup(ext1_mask, esp_sleep_ext1_wakeup_mode_t_ESP_EXT1_WAKEUP_ANY_HIGH))?;
+            esp_deep_sleep_start();
+        }
+    }
+}
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+const CLOCK_UNSET: i64 = i64::MIN;
+
+// RTC slow memory is kept through deep sleep but cleared on power up, which is exactly when
+// the clock needs setting again.
+#[link_section = ".rtc.data"]
+static mut CLOCK_OFFSET: i64 = CLOCK_UNSET;
+
+/// Time of day as an offset from system time. ESP-IDF keeps system time running from the RTC
+/// timer through deep sleep, so only the offset needs to be remembered.
+pub struct EspWallClock;
+
+impl EspWallClock {
+    fn system_seconds() -> i64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs() as i64)
+    }
+}
+
+impl WallClock for EspWallClock {
+    fn time_of_day(&self) -> Option<u32> {
+        let offset = unsafe { CLOCK_OFFSET };
+        if offset == CLOCK_UNSET {
+            return None;
+        }
+        Some((Self::system_seconds() + offset).rem_euclid(SECONDS_PER_DAY) as u32)
+    }
+
+    fn set_time_of_day(&mut self, seconds: u32) -> Result<()> {
+        unsafe { CLOCK_OFFSET = seconds as i64 - Self::system_seconds() };
+        Ok(())
+    }
+}
+
+pub struct NvsSettings {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl NvsSettings {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(Self { nvs: EspNvs::new(partition, "settings", true)? })
+    }
+}
+
+impl Settings for NvsSettings {
+    fn get_u32(&self, key: &str) -> Result<Option<u32>> {
+        Ok(self.nvs.get_u32(key)?)
+    }
+
+    fn set_u32(&mut self, key: &str, value: u32) -> Result<()> {
+        Ok(self.nvs.set_u32(key, value)?)
+    }
+}
+
+/// The data partition in `partitions.csv` that holds the sound pack.
+const SOUNDS_PARTITION: &str = "sounds";
+const SOUNDS_MOUNT_POINT: &str = "/sounds";
+
+/// The sound pack's FAT partition, mounted read-only. A board that was never sent a pack, or
+/// still has the old partition table, has nothing mounted and reads no files.
+pub struct FlashFiles {
+    root: Option<PathBuf>,
+}
+
+impl FlashFiles {
+    pub fn mount() -> Self {
+        match Self::mount_fat() {
+            Ok(()) => Self { root: Some(PathBuf::from(SOUNDS_MOUNT_POINT)) },
+            Err(err) => {
+                log::warn!("No sound pack mounted: {:?}", err);
+                Self { root: None }
+            },
+        }
+    }
+
+    fn mount_fat() -> Result<()> {
+        let base_path = CString::new(SOUNDS_MOUNT_POINT)?;
+        let partition_label = CString::new(SOUNDS_PARTITION)?;
+        let config = esp_vfs_fat_mount_config_t {
+            max_files: 2,
+            ..Default::default()
+        };
+        esp!(unsafe { esp_vfs_fat_spiflash_mount_ro(base_path.as_ptr(), partition_label.as_ptr(), &config) })?;
+        Ok(())
+    }
+}
+
+impl Files for FlashFiles {
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let Some(root) = &self.root else {
+            return Ok(None);
+        };
+        match std::fs::read(root.join(name)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+pub fn run_app() -> Result<()> {
+    let peripherals = Peripherals::take()?;
+    let nvs = EspDefaultNvsPartition::take()?;
+    // The display module is picked with a cargo feature. They all hang off GPIO16 and GPIO17.
+    #[cfg(not(any(feature = "display-max7219", feature = "display-ht16k33")))]
+    let display = SevenSegment::new(peripherals.pins.gpio16, peripherals.pins.gpio17)?;
+    #[cfg(feature = "display-max7219")]
+    let display = Max7219Display::new(
+        peripherals.spi2,
+        peripherals.pins.gpio17, peripherals.pins.gpio16, peripherals.pins.gpio5,
+        max7219::MAX_DIGITS,
+    )?;
+    #[cfg(feature = "display-ht16k33")]
+    let display = Ht16k33Display::new(
+        peripherals.i2c0,
+        peripherals.pins.gpio16, peripherals.pins.gpio17,
+        ht16k33::DEFAULT_ADDRESS, &ht16k33::FOUR_DIGITS,
+    )?;
+    let keypad = Keypad::new(
+        [peripherals.pins.gpio14.downgrade(), peripherals.pins.gpio25.downgrade(), peripherals.pins.gpio21.downgrade()],
+        [
+            peripherals.pins.gpio26.downgrade(), peripherals.pins.gpio19.downgrade(),
+            peripherals.pins.gpio22.downgrade(), peripherals.pins.gpio13.downgrade(),
+        ],
+        &keys::PHONE_3X4,
+    )?;
+    let speaker = Speaker::new(
+        peripherals.i2s0,
+        peripherals.pins.gpio15, peripherals.pins.gpio23, peripherals.pins.gpio4,
+    )?;
+    let timer = TimerDriver::new(peripherals.timer00, &Config::default())?;
+    let start_button = PinDriver::input(peripherals.pins.gpio34.into_ref().map_into::<AnyInputPin>())?;
+    let stop_button = PinDriver::input(peripherals.pins.gpio35.into_ref().map_into::<AnyInputPin>())?;
+    let door_switch = PinDriver::input(peripherals.pins.gpio39.into_ref().map_into::<AnyInputPin>())?;
+    let remote = Remote::new(
+        peripherals.rmt.channel0, peripherals.pins.gpio12,
+        peripherals.rmt.channel1, peripherals.pins.gpio27,
+        nvs.clone(),
+    )?;
+
+    let mut app = App::new(Board {
+        display: Box::new(display),
+        keypad: Box::new(keypad),
+        speaker: Box::new(speaker),
+        clock: Box::new(EspClock::new(timer)?),
+        start_button: Box::new(start_button),
+        stop_button: Box::new(stop_button),
+        door_switch: Box::new(door_switch),
+        remote: Box::new(remote),
+        power: Box::new(EspPower),
+        wall_clock: Box::new(EspWallClock),
+        settings: Box::new(NvsSettings::new(nvs)?),
+        files: Box::new(FlashFiles::mount()),
+    })?;
+
+    app.run()
+}
@@ -0,0 +1,109 @@
+//! Plays the audio in a WAV file, decoding IMA ADPCM a sample at a time as it goes so that
+//! compressed sounds stay compressed in flash and RAM.
+
+use crate::adpcm::{Adpcm, Position};
+use crate::wav::Audio;
+use awedio::{sounds::MemorySound, NextSample, Sound};
+use std::sync::Arc;
+
+pub struct AdpcmSound {
+    clip: Adpcm,
+    position: Position,
+    looping: bool,
+}
+
+impl AdpcmSound {
+    pub fn new(clip: Adpcm) -> Self {
+        Self { clip, position: Position::default(), looping: false }
+    }
+
+    /// Whether to start again from the beginning instead of finishing.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+}
+
+impl Sound for AdpcmSound {
+    fn channel_count(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.clip.sample_rate
+    }
+
+    fn next_sample(&mut self) -> Result<NextSample, awedio::Error> {
+        if let Some(sample) = self.clip.next_sample(&mut self.position) {
+            return Ok(NextSample::Sample(sample));
+        }
+        if !self.looping {
+            return Ok(NextSample::Finished);
+        }
+        self.position = Position::default();
+        // An empty clip would otherwise loop forever without a sample.
+        Ok(self.clip.next_sample(&mut self.position).map_or(NextSample::Finished, NextSample::Sample))
+    }
+
+    fn on_start_of_batch(&mut self) {}
+}
+
+/// A sound for either kind of audio a WAV file can hold.
+pub enum ClipSound {
+    Pcm(MemorySound),
+    Adpcm(AdpcmSound),
+}
+
+impl ClipSound {
+    /// Plays `audio` without copying its samples, which it shares.
+    pub fn new(audio: &Audio) -> Self {
+        match audio {
+            Audio::Pcm(wav) => {
+                ClipSound::Pcm(MemorySound::from_samples(wav.samples.clone(), wav.channels, wav.sample_rate))
+            },
+            Audio::Adpcm(adpcm) => ClipSound::Adpcm(AdpcmSound::new(adpcm.clone())),
+        }
+    }
+
+    pub fn set_looping(&mut self, looping: bool) {
+        match self {
+            ClipSound::Pcm(sound) => sound.set_looping(looping),
+            ClipSound::Adpcm(sound) => sound.set_looping(looping),
+        }
+    }
+
+    /// Finishes straight away, even if it's looping.
+    pub fn stop(&mut self) {
+        let empty = MemorySound::from_samples(Arc::new(Vec::new()), self.channel_count(), self.sample_rate());
+        *self = ClipSound::Pcm(empty);
+    }
+}
+
+impl Sound for ClipSound {
+    fn channel_count(&self) -> u16 {
+        match self {
+            ClipSound::Pcm(sound) => sound.channel_count(),
+            ClipSound::Adpcm(sound) => sound.channel_count(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self {
+            ClipSound::Pcm(sound) => sound.sample_rate(),
+            ClipSound::Adpcm(sound) => sound.sample_rate(),
+        }
+    }
+
+    fn next_sample(&mut self) -> Result<NextSample, awedio::Error> {
+        match self {
+            ClipSound::Pcm(sound) => sound.next_sample(),
+            ClipSound::Adpcm(sound) => sound.next_sample(),
+        }
+    }
+
+    fn on_start_of_batch(&mut self) {
+        match self {
+            ClipSound::Pcm(sound) => sound.on_start_of_batch(),
+            ClipSound::Adpcm(sound) => sound.on_start_of_batch(),
+        }
+    }
+}
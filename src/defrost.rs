@@ -0,0 +1,164 @@
+//! Defrost-by-weight schedules. Defrosting alternates low power bursts with rest periods so the
+//! outside doesn't start cooking before the middle has thawed, and stops halfway so the food
+//! can be turned over.
+
+use crate::program::Stage;
+
+pub const MIN_GRAMS: u16 = 100;
+pub const MAX_GRAMS: u16 = 3000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Food {
+    Meat,
+    Poultry,
+    Fish,
+    Bread,
+}
+
+struct Profile {
+    /// Total time spent defrosting a kilogram, not counting rests.
+    seconds_per_kg: u32,
+    power: u8,
+    burst_seconds: u16,
+    rest_seconds: u16,
+}
+
+impl Food {
+    fn profile(self) -> Profile {
+        match self {
+            Food::Meat => Profile { seconds_per_kg: 1200, power: 3, burst_seconds: 120, rest_seconds: 60 },
+            Food::Poultry => Profile { seconds_per_kg: 1400, power: 3, burst_seconds: 120, rest_seconds: 90 },
+            Food::Fish => Profile { seconds_per_kg: 900, power: 2, burst_seconds: 90, rest_seconds: 60 },
+            Food::Bread => Profile { seconds_per_kg: 360, power: 3, burst_seconds: 30, rest_seconds: 30 },
+        }
+    }
+}
+
+/// Builds the stages for defrosting `grams` of `food`. Weights outside
+/// `MIN_GRAMS..=MAX_GRAMS` are clamped.
+pub fn schedule(food: Food, grams: u16) -> Vec<Stage> {
+    let profile = food.profile();
+    let grams = grams.clamp(MIN_GRAMS, MAX_GRAMS);
+    let total = (grams as u32 * profile.seconds_per_kg / 1000) as u16;
+
+    let mut stages = Vec::new();
+    let mut defrosted = 0;
+    let mut turned_over = false;
+    while defrosted < total {
+        if defrosted > 0 {
+            stages.push(Stage::Stand{seconds: profile.rest_seconds});
+        }
+        let burst = profile.burst_seconds.min(total - defrosted);
+        stages.push(Stage::Cook{seconds: burst, power: profile.power});
+        defrosted += burst;
+        if !turned_over && defrosted * 2 >= total && defrosted < total {
+            stages.push(Stage::TurnOver);
+            turned_over = true;
+        }
+    }
+    // A final rest lets the temperature even out before it comes out.
+    stages.push(Stage::Stand{seconds: profile.rest_seconds});
+    stages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cook(seconds: u16, power: u8) -> Stage {
+        Stage::Cook{seconds, power}
+    }
+
+    fn stand(seconds: u16) -> Stage {
+        Stage::Stand{seconds}
+    }
+
+    /// The time spent cooking, before and after the turn.
+    fn cook_seconds(stages: &[Stage]) -> (u32, u32) {
+        let turn = stages.iter().position(|stage| *stage == Stage::TurnOver).unwrap_or(stages.len());
+        let total = |stages: &[Stage]| stages.iter()
+            .map(|stage| match stage {
+                Stage::Cook{seconds, ..} => *seconds as u32,
+                _ => 0,
+            })
+            .sum();
+        (total(&stages[..turn]), total(&stages[turn..]))
+    }
+
+    #[test]
+    fn small_weights_get_whole_schedules() {
+        assert_eq!(schedule(Food::Bread, 100), [cook(30, 3), Stage::TurnOver, stand(30), cook(6, 3), stand(30)]);
+        assert_eq!(schedule(Food::Meat, 500), [
+            cook(120, 3), stand(60),
+            cook(120, 3), stand(60),
+            cook(120, 3), Stage::TurnOver, stand(60),
+            cook(120, 3), stand(60),
+            cook(120, 3), stand(60),
+        ]);
+    }
+
+    #[test]
+    fn each_food_has_its_own_time_power_and_rests() {
+        // (food, cooking seconds for 1 kg, power, longest burst, rest)
+        let table = [
+            (Food::Meat, 1200, 3, 120, 60),
+            (Food::Poultry, 1400, 3, 120, 90),
+            (Food::Fish, 900, 2, 90, 60),
+            (Food::Bread, 360, 3, 30, 30),
+        ];
+        for (food, seconds, power, burst, rest) in table {
+            let stages = schedule(food, 1000);
+            let (before, after) = cook_seconds(&stages);
+            assert_eq!(before + after, seconds, "{:?}", food);
+            for stage in &stages {
+                match *stage {
+                    Stage::Cook{seconds, power: stage_power} => {
+                        assert_eq!(stage_power, power, "{:?}", food);
+                        assert!(seconds <= burst, "{:?}", food);
+                    },
+                    Stage::Stand{seconds} => assert_eq!(seconds, rest, "{:?}", food),
+                    Stage::TurnOver => {},
+                }
+            }
+            assert_eq!(stages.last(), Some(&stand(rest)));
+        }
+    }
+
+    #[test]
+    fn weights_are_clamped() {
+        for food in [Food::Meat, Food::Poultry, Food::Fish, Food::Bread] {
+            assert_eq!(schedule(food, 0), schedule(food, MIN_GRAMS));
+            assert_eq!(schedule(food, u16::MAX), schedule(food, MAX_GRAMS));
+            assert_ne!(schedule(food, MIN_GRAMS), schedule(food, MAX_GRAMS));
+        }
+    }
+
+    #[test]
+    fn turns_over_once_at_the_first_burst_past_halfway() {
+        for food in [Food::Meat, Food::Poultry, Food::Fish, Food::Bread] {
+            for grams in (MIN_GRAMS..=MAX_GRAMS).step_by(50) {
+                let stages = schedule(food, grams);
+                let turns = stages.iter().filter(|stage| **stage == Stage::TurnOver).count();
+                let bursts = stages.iter().filter(|stage| matches!(stage, Stage::Cook{..})).count();
+                // A single burst has no halfway point to stop at.
+                if bursts == 1 {
+                    assert_eq!(turns, 0, "{:?} {} g", food, grams);
+                    continue;
+                }
+                assert_eq!(turns, 1, "{:?} {} g", food, grams);
+                let (before, after) = cook_seconds(&stages);
+                assert!(before >= after, "{:?} {} g", food, grams);
+                // One burst fewer would have been short of half.
+                let last_burst = stages.iter()
+                    .take_while(|stage| **stage != Stage::TurnOver)
+                    .filter_map(|stage| match stage {
+                        Stage::Cook{seconds, ..} => Some(*seconds as u32),
+                        _ => None,
+                    })
+                    .last()
+                    .unwrap();
+                assert!((before - last_burst) < after + last_burst, "{:?} {} g", food, grams);
+            }
+        }
+    }
+}
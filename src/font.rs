@@ -0,0 +1,255 @@
+//! A seven-segment font and the text layout built on it.
+//!
+//! Segments are the usual `0bPGFEDCBA` bits, A being the top bar and going round clockwise,
+//! G the middle bar and P the decimal point. On clock modules the point of the second digit
+//! drives the colon. Nothing here touches a display, so it's shared by the hardware, the
+//! simulator and anything else that wants to know what a message looks like.
+
+/// The digits 0-9, then a blank.
+pub const DISPLAY_DIGITS: [u8;11] = [
+    0b00111111,
+    0b00000110,
+    0b01011011,
+    0b01001111,
+    0b01100110,
+    0b01101101,
+    0b01111101,
+    0b00000111,
+    0b01111111,
+    0b01101111,
+    0b00000000,
+];
+
+/// The decimal point, or the colon after the second digit of a clock display.
+pub const POINT: u8 = 0b10000000;
+
+/// Letters that only come in one case on seven segments are drawn the same for both, and a
+/// few, like `M` and `W`, are a best effort.
+const LETTERS: [(char, u8); 39] = [
+    ('A', 0b01110111),
+    ('b', 0b01111100),
+    ('C', 0b00111001),
+    ('c', 0b01011000),
+    ('d', 0b01011110),
+    ('E', 0b01111001),
+    ('F', 0b01110001),
+    ('G', 0b00111101),
+    ('H', 0b01110110),
+    ('h', 0b01110100),
+    ('I', 0b00110000),
+    ('i', 0b00010000),
+    ('J', 0b00011110),
+    ('K', 0b01110101),
+    ('L', 0b00111000),
+    ('M', 0b00110111),
+    ('n', 0b01010100),
+    ('O', 0b00111111),
+    ('o', 0b01011100),
+    ('P', 0b01110011),
+    ('q', 0b01100111),
+    ('r', 0b01010000),
+    ('S', 0b01101101),
+    ('t', 0b01111000),
+    ('U', 0b00111110),
+    ('u', 0b00011100),
+    ('v', 0b00011100),
+    ('W', 0b00101010),
+    ('X', 0b01110110),
+    ('y', 0b01101110),
+    ('Z', 0b01011011),
+    (' ', 0b00000000),
+    ('-', 0b01000000),
+    ('_', 0b00001000),
+    ('=', 0b01001000),
+    ('\'', 0b00100000),
+    ('"', 0b00100010),
+    ('°', 0b01100011),
+    ('?', 0b01010011),
+];
+
+/// The segments for `c`, if the font has it. A letter that's missing in one case falls back
+/// to the other, so `e` is drawn as `E` and `N` as `n`.
+pub fn glyph(c: char) -> Option<u8> {
+    if let Some(digit) = c.to_digit(10) {
+        return Some(DISPLAY_DIGITS[digit as usize]);
+    }
+    let find = |c: char| LETTERS.iter().find(|(letter, _)| *letter == c).map(|(_, glyph)| *glyph);
+    find(c)
+        .or_else(|| find(c.to_ascii_uppercase()))
+        .or_else(|| find(c.to_ascii_lowercase()))
+}
+
+/// The character `segments` was most likely drawn from, preferring digits since `S` and `5`,
+/// `O` and `0` and so on look the same. The point is ignored.
+pub fn character(segments: u8) -> Option<char> {
+    let segments = segments & !POINT;
+    if let Some(digit) = DISPLAY_DIGITS[..10].iter().position(|glyph| *glyph == segments) {
+        return Some((b'0' + digit as u8) as char);
+    }
+    LETTERS.iter().find(|(_, glyph)| *glyph == segments).map(|(letter, _)| *letter)
+}
+
+/// One glyph per character of `text`. A `.` or `:` lights the point of the glyph before it
+/// rather than taking a digit of its own, and characters the font doesn't have are blank.
+pub fn segments(text: &str) -> Vec<u8> {
+    let mut segments: Vec<u8> = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match (c, segments.last_mut()) {
+            ('.' | ':', Some(last)) if *last & POINT == 0 => *last |= POINT,
+            ('.' | ':', _) => segments.push(POINT),
+            _ => segments.push(glyph(c).unwrap_or(0)),
+        }
+    }
+    segments
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+    /// Any odd space goes on the right.
+    Center,
+}
+
+/// Places `segments` on a display `width` digits wide. Text that doesn't fit is cut off on
+/// the side away from the alignment, or both sides when centered.
+pub fn place(segments: &[u8], width: usize, align: Align) -> Vec<u8> {
+    let mut placed = vec![0; width];
+    let (skip, start) = match align {
+        Align::Left => (0, 0),
+        Align::Right if segments.len() > width => (segments.len() - width, 0),
+        Align::Right => (0, width - segments.len()),
+        Align::Center if segments.len() > width => ((segments.len() - width) / 2, 0),
+        Align::Center => (0, (width - segments.len()) / 2),
+    };
+    for (digit, segment) in placed[start..].iter_mut().zip(&segments[skip..]) {
+        *digit = *segment;
+    }
+    placed
+}
+
+/// `text` laid out on an `N` digit display.
+pub fn text<const N: usize>(text: &str, align: Align) -> [u8; N] {
+    let mut digits = [0; N];
+    digits.copy_from_slice(&place(&segments(text), N, align));
+    digits
+}
+
+/// A message scrolled right to left across an `N` digit display, for text too long to show
+/// at once.
+///
+/// The first frame has the first character on the right hand digit, and each frame moves it
+/// along one until the last character has reached the left hand digit.
+pub struct Marquee<const N: usize> {
+    /// The message with a display's width of blanks on either side.
+    padded: Vec<u8>,
+}
+
+impl<const N: usize> Marquee<N> {
+    pub fn new(text: &str) -> Self {
+        let mut padded = vec![0; N];
+        padded.extend(segments(text));
+        padded.extend([0; N]);
+        Self { padded }
+    }
+
+    /// How many frames it takes to scroll the whole message through.
+    pub fn frames(&self) -> usize {
+        (self.padded.len() - N - 1).max(1)
+    }
+
+    /// Frame `index`, wrapping round to the start after the last one.
+    pub fn frame(&self, index: usize) -> [u8; N] {
+        let start = index % self.frames() + 1;
+        let mut digits = [0; N];
+        digits.copy_from_slice(&self.padded[start..start + N]);
+        digits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const H: u8 = 0b01110110;
+    const I: u8 = 0b00010000;
+
+    #[test]
+    fn glyphs_fall_back_to_the_other_case() {
+        assert_eq!(glyph('0'), Some(DISPLAY_DIGITS[0]));
+        assert_eq!(glyph('9'), Some(DISPLAY_DIGITS[9]));
+        assert_eq!(glyph('A'), Some(0b01110111));
+        assert_eq!(glyph('a'), glyph('A'));
+        assert_eq!(glyph('e'), glyph('E'));
+        assert_eq!(glyph('N'), glyph('n'));
+        // Both cases of some letters are drawn, and differently.
+        assert_ne!(glyph('c'), glyph('C'));
+        assert_ne!(glyph('h'), glyph('H'));
+        assert_eq!(glyph('°'), Some(0b01100011));
+        assert_eq!(glyph('&'), None);
+        assert_eq!(glyph('é'), None);
+    }
+
+    #[test]
+    fn characters_prefer_digits() {
+        assert_eq!(character(glyph('S').unwrap()), Some('5'));
+        assert_eq!(character(glyph('O').unwrap()), Some('0'));
+        assert_eq!(character(glyph('E').unwrap() | POINT), Some('E'));
+        assert_eq!(character(0b01000001), None);
+        for c in "0123456789AbCdEFHLnoPrtU-_".chars() {
+            assert_eq!(glyph(character(glyph(c).unwrap()).unwrap()), glyph(c), "{}", c);
+        }
+    }
+
+    #[test]
+    fn points_join_the_glyph_before_them() {
+        assert_eq!(segments("12:30"), [DISPLAY_DIGITS[1], DISPLAY_DIGITS[2] | POINT, DISPLAY_DIGITS[3], DISPLAY_DIGITS[0]]);
+        assert_eq!(segments(".5"), [POINT, DISPLAY_DIGITS[5]]);
+        assert_eq!(segments("1.."), [DISPLAY_DIGITS[1] | POINT, POINT]);
+        assert_eq!(segments("H&i"), [H, 0, I]);
+        assert!(segments("").is_empty());
+    }
+
+    #[test]
+    fn text_is_placed_by_alignment() {
+        assert_eq!(place(&[1, 2], 4, Align::Left), [1, 2, 0, 0]);
+        assert_eq!(place(&[1, 2], 4, Align::Right), [0, 0, 1, 2]);
+        assert_eq!(place(&[1, 2], 4, Align::Center), [0, 1, 2, 0]);
+        assert_eq!(place(&[1, 2, 3], 4, Align::Center), [1, 2, 3, 0]);
+        assert_eq!(place(&[1, 2, 3, 4], 4, Align::Center), [1, 2, 3, 4]);
+        assert_eq!(place(&[], 4, Align::Right), [0; 4]);
+    }
+
+    #[test]
+    fn text_too_wide_is_cut_off_away_from_the_alignment() {
+        let segments = [1, 2, 3, 4, 5, 6];
+        assert_eq!(place(&segments, 4, Align::Left), [1, 2, 3, 4]);
+        assert_eq!(place(&segments, 4, Align::Right), [3, 4, 5, 6]);
+        assert_eq!(place(&segments, 4, Align::Center), [2, 3, 4, 5]);
+        assert_eq!(place(&[1, 2, 3, 4, 5], 4, Align::Center), [1, 2, 3, 4]);
+        assert_eq!(text::<4>("Hi", Align::Center), [0, H, I, 0]);
+    }
+
+    #[test]
+    fn marquees_scroll_through_and_wrap_round() {
+        let marquee = Marquee::<4>::new("Hi");
+        assert_eq!(marquee.frames(), 5);
+        let frames: Vec<_> = (0..5).map(|index| marquee.frame(index)).collect();
+        assert_eq!(frames, [[0, 0, 0, H], [0, 0, H, I], [0, H, I, 0], [H, I, 0, 0], [I, 0, 0, 0]]);
+        assert_eq!(marquee.frame(5), marquee.frame(0));
+        assert_eq!(marquee.frame(13), marquee.frame(3));
+
+        // The point doesn't take a frame of its own.
+        let marquee = Marquee::<4>::new("1.5");
+        assert_eq!(marquee.frames(), 5);
+        assert_eq!(marquee.frame(1), [0, 0, DISPLAY_DIGITS[1] | POINT, DISPLAY_DIGITS[5]]);
+    }
+
+    #[test]
+    fn an_empty_marquee_still_has_frames() {
+        let marquee = Marquee::<4>::new("");
+        assert_eq!(marquee.frames(), 3);
+        assert!((0..6).all(|index| marquee.frame(index) == [0; 4]));
+        assert_eq!(Marquee::<1>::new("").frames(), 1);
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::Result;
+use awedio::Sound;
+use crate::font::{self, Align};
+use crate::keys::Key;
+
+/// A digital input line. Buttons and the door switch are wired active low.
+pub trait InputLine {
+    fn is_low(&self) -> bool;
+}
+
+/// The brightest of the eight levels a `Display` dims through.
+pub const MAX_BRIGHTNESS: u8 = 7;
+
+pub trait Display {
+    /// The app only draws four digits. Wider modules show them at the right hand end and leave
+    /// the rest blank, there's no way to use the extra digits.
+    fn set_segments(&mut self, segments: [u8; 4]) -> Result<()>;
+
+    fn show_text(&mut self, text: &str, align: Align) -> Result<()> {
+        self.set_segments(font::text(text, align))
+    }
+
+    /// One of eight levels, 0 to `MAX_BRIGHTNESS`. Displays that can't dim ignore it.
+    fn set_brightness(&mut self, _brightness: u8) -> Result<()> {
+        Ok(())
+    }
+
+    /// Waits until everything written so far is showing, for displays that update in the
+    /// background.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub trait KeyInput {
+    fn get_key(&mut self) -> Result<Option<Key>>;
+
+    /// Forgets presses that haven't been read yet, so a new mode doesn't act on keys pressed
+    /// before it started.
+    fn clear(&mut self) {}
+
+    /// Leaves the matrix in a state where a key press can wake the board from deep sleep.
+    fn prepare_for_sleep(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub trait AudioOut {
+    fn play(&mut self, sound: Box<dyn Sound>) -> Result<()>;
+    fn clear(&mut self);
+    /// The master volume, from 0.0 for silence to 1.0 for the sounds' own level.
+    fn set_volume(&mut self, volume: f32);
+}
+
+/// The buttons on the light puck's remote that can be learned and replayed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightCode {
+    On,
+    Off,
+    Brighter,
+    Dimmer,
+}
+
+pub trait Light {
+    fn send(&mut self, code: LightCode) -> Result<()>;
+
+    /// Listens for up to `timeout_ms` for `code` from the original remote and stores it.
+    /// Returns whether a code was learned.
+    fn learn(&mut self, code: LightCode, timeout_ms: u32) -> Result<bool>;
+
+    fn send_on(&mut self) -> Result<()> {
+        self.send(LightCode::On)
+    }
+
+    fn send_off(&mut self) -> Result<()> {
+        self.send(LightCode::Off)
+    }
+}
+
+pub trait Clock {
+    fn counter(&self) -> Result<u64>;
+    fn tick_hz(&self) -> u64;
+    fn delay_ms(&mut self, ms: u32) -> Result<()>;
+}
+
+pub trait WallClock {
+    /// Seconds since midnight, or `None` if the clock hasn't been set since power was lost.
+    fn time_of_day(&self) -> Option<u32>;
+    fn set_time_of_day(&mut self, seconds: u32) -> Result<()>;
+}
+
+/// Small values that survive power loss.
+pub trait Settings {
+    fn get_u32(&self, key: &str) -> Result<Option<u32>>;
+    fn set_u32(&mut self, key: &str, value: u32) -> Result<()>;
+}
+
+/// Read-only files, like the sound pack on the data partition.
+pub trait Files {
+    /// The whole of the file `name`, or `None` if there isn't one.
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WakeReason {
+    PowerOn,
+    StartButton,
+    Door,
+    Keypad,
+}
+
+pub trait Power {
+    /// Why the board last came out of deep sleep.
+    fn wake_reason(&self) -> WakeReason;
+
+    /// Arms the wake sources and enters deep sleep. On hardware this never returns,
+    /// the board reboots on wake instead.
+    fn deep_sleep(&mut self) -> Result<()>;
+}
+
+/// Everything `App` needs from the outside world.
+pub struct Board<'a> {
+    pub display: Box<dyn Display + 'a>,
+    pub keypad: Box<dyn KeyInput + 'a>,
+    pub speaker: Box<dyn AudioOut + 'a>,
+    pub clock: Box<dyn Clock + 'a>,
+    pub start_button: Box<dyn InputLine + 'a>,
+    pub stop_button: Box<dyn InputLine + 'a>,
+    pub door_switch: Box<dyn InputLine + 'a>,
+    pub remote: Box<dyn Light + 'a>,
+    pub power: Box<dyn Power + 'a>,
+    pub wall_clock: Box<dyn WallClock + 'a>,
+    pub settings: Box<dyn Settings + 'a>,
+    pub files: Box<dyn Files + 'a>,
+}
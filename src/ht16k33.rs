@@ -0,0 +1,203 @@
+//! The HT16K33 LED driver on an I2C backpack.
+//!
+//! The chip has sixteen bytes of display RAM, one pair per common line, written in one go
+//! from address 0. Which bytes drive which digit depends on how the backpack is wired, so
+//! that's described by a `Layout`. The driver is generic over `Bus` so it can run against a
+//! mock.
+
+use anyhow::Result;
+use crate::font::POINT;
+use crate::hal::MAX_BRIGHTNESS;
+use std::fmt;
+
+/// With none of the address jumpers bridged.
+pub const DEFAULT_ADDRESS: u8 = 0x70;
+
+const RAM_ADDRESS: u8 = 0x00;
+const OSCILLATOR_ON: u8 = 0x21;
+const DISPLAY_SETUP: u8 = 0x80;
+const DISPLAY_ON: u8 = 0x01;
+const DIMMING: u8 = 0xE0;
+
+const RAM_BYTES: usize = 16;
+
+/// An I2C bus.
+pub trait Bus {
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<()>;
+}
+
+/// Where each digit, left to right, and the colon sit in display RAM.
+pub struct Layout {
+    pub digits: &'static [usize],
+    /// The byte and bit that light the colon, for backpacks that have one. The app's colon
+    /// is the point of the second digit, so on these that point lights the colon instead.
+    pub colon: Option<(usize, u8)>,
+}
+
+/// The usual four digit backpack with a clock colon between the middle digits.
+pub static FOUR_DIGITS: Layout = Layout {
+    digits: &[0, 2, 6, 8],
+    colon: Some((4, 0x02)),
+};
+
+/// Eight digits on the eight common lines, with no colon.
+pub static EIGHT_DIGITS: Layout = Layout {
+    digits: &[0, 2, 4, 6, 8, 10, 12, 14],
+    colon: None,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum Ht16k33Error {
+    TooManyDigits(usize),
+}
+
+impl fmt::Display for Ht16k33Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ht16k33Error::TooManyDigits(digits) => write!(f, "{} digits won't fit on the display", digits),
+        }
+    }
+}
+
+impl std::error::Error for Ht16k33Error {}
+
+pub struct Ht16k33<B> {
+    bus: B,
+    address: u8,
+    layout: &'static Layout,
+}
+
+impl<B: Bus> Ht16k33<B> {
+    /// Starts the oscillator and turns the display on, blank and at full brightness.
+    pub fn new(bus: B, address: u8, layout: &'static Layout) -> Result<Self> {
+        let mut ht16k33 = Self { bus, address, layout };
+        ht16k33.command(OSCILLATOR_ON)?;
+        ht16k33.write_segments(&[])?;
+        ht16k33.set_brightness(MAX_BRIGHTNESS)?;
+        ht16k33.set_on(true)?;
+        Ok(ht16k33)
+    }
+
+    pub fn digits(&self) -> usize {
+        self.layout.digits.len()
+    }
+
+    /// Sets one of the eight brightness levels, 0-7.
+    pub fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+        // Sixteen duty cycle steps, two for each level.
+        self.command(DIMMING | (brightness.min(MAX_BRIGHTNESS) * 2 + 1))
+    }
+
+    /// Turns the display off or on again, keeping what's on it.
+    pub fn set_on(&mut self, on: bool) -> Result<()> {
+        self.command(DISPLAY_SETUP | if on { DISPLAY_ON } else { 0 })
+    }
+
+    /// Writes segments to the digits from the left, blanking any past the end of `segments`.
+    pub fn write_segments(&mut self, segments: &[u8]) -> Result<()> {
+        if segments.len() > self.layout.digits.len() {
+            return Err(Ht16k33Error::TooManyDigits(segments.len()).into());
+        }
+        let mut frame = [0u8; RAM_BYTES + 1];
+        frame[0] = RAM_ADDRESS;
+        let ram = &mut frame[1..];
+        for (i, (segment, offset)) in segments.iter().zip(self.layout.digits).enumerate() {
+            let mut segment = *segment;
+            if let Some((colon, bit)) = self.layout.colon {
+                if i == 1 && segment & POINT != 0 {
+                    segment &= !POINT;
+                    ram[colon] |= bit;
+                }
+            }
+            ram[*offset] = segment;
+        }
+        self.bus.write(self.address, &frame)
+    }
+
+    fn command(&mut self, command: u8) -> Result<()> {
+        self.bus.write(self.address, &[command])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records each write's bytes, checking they all go to the same address.
+    #[derive(Default)]
+    struct MockBus(Vec<Vec<u8>>);
+
+    impl Bus for MockBus {
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<()> {
+            assert_eq!(address, DEFAULT_ADDRESS);
+            self.0.push(bytes.to_vec());
+            Ok(())
+        }
+    }
+
+    fn take(ht16k33: &mut Ht16k33<MockBus>) -> Vec<Vec<u8>> {
+        std::mem::take(&mut ht16k33.bus.0)
+    }
+
+    /// A RAM write with `bytes` set, after the address.
+    fn ram(bytes: &[(usize, u8)]) -> Vec<u8> {
+        let mut frame = vec![0; RAM_BYTES + 1];
+        for (offset, byte) in bytes {
+            frame[offset + 1] = *byte;
+        }
+        frame
+    }
+
+    #[test]
+    fn init_starts_the_oscillator_and_blanks_the_display() {
+        let mut ht16k33 = Ht16k33::new(MockBus::default(), DEFAULT_ADDRESS, &FOUR_DIGITS).unwrap();
+        assert_eq!(take(&mut ht16k33), [vec![0x21], ram(&[]), vec![0xEF], vec![0x81]]);
+        assert_eq!(ht16k33.digits(), 4);
+    }
+
+    #[test]
+    fn digits_go_to_their_ram_bytes() {
+        let mut ht16k33 = Ht16k33::new(MockBus::default(), DEFAULT_ADDRESS, &FOUR_DIGITS).unwrap();
+        take(&mut ht16k33);
+        ht16k33.write_segments(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(take(&mut ht16k33), [ram(&[(0, 1), (2, 2), (6, 3), (8, 4)])]);
+        ht16k33.write_segments(&[5]).unwrap();
+        assert_eq!(take(&mut ht16k33), [ram(&[(0, 5)])]);
+
+        let mut ht16k33 = Ht16k33::new(MockBus::default(), DEFAULT_ADDRESS, &EIGHT_DIGITS).unwrap();
+        take(&mut ht16k33);
+        ht16k33.write_segments(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        let expected: Vec<_> = (0..8).map(|i| (i * 2, i as u8 + 1)).collect();
+        assert_eq!(take(&mut ht16k33), [ram(&expected)]);
+
+        let err = ht16k33.write_segments(&[0; 9]).unwrap_err();
+        assert_eq!(err.downcast_ref::<Ht16k33Error>(), Some(&Ht16k33Error::TooManyDigits(9)));
+    }
+
+    #[test]
+    fn the_second_point_lights_the_colon() {
+        let mut ht16k33 = Ht16k33::new(MockBus::default(), DEFAULT_ADDRESS, &FOUR_DIGITS).unwrap();
+        take(&mut ht16k33);
+        ht16k33.write_segments(&[1, 2 | POINT, 3 | POINT, 4]).unwrap();
+        assert_eq!(take(&mut ht16k33), [ram(&[(0, 1), (2, 2), (4, 0x02), (6, 3 | POINT), (8, 4)])]);
+
+        // Without a colon it's just a point.
+        let mut ht16k33 = Ht16k33::new(MockBus::default(), DEFAULT_ADDRESS, &EIGHT_DIGITS).unwrap();
+        take(&mut ht16k33);
+        ht16k33.write_segments(&[1, 2 | POINT]).unwrap();
+        assert_eq!(take(&mut ht16k33), [ram(&[(0, 1), (2, 2 | POINT)])]);
+    }
+
+    #[test]
+    fn brightness_uses_two_dimming_steps_a_level() {
+        let mut ht16k33 = Ht16k33::new(MockBus::default(), DEFAULT_ADDRESS, &FOUR_DIGITS).unwrap();
+        take(&mut ht16k33);
+        for brightness in [0, 3, MAX_BRIGHTNESS, 20] {
+            ht16k33.set_brightness(brightness).unwrap();
+        }
+        assert_eq!(take(&mut ht16k33), [vec![0xE1], vec![0xE7], vec![0xEF], vec![0xEF]]);
+
+        ht16k33.set_on(false).unwrap();
+        assert_eq!(take(&mut ht16k33), [vec![0x80]]);
+    }
+}
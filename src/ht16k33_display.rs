@@ -0,0 +1,46 @@
+use anyhow::Result;
+use esp_idf_svc::hal::{
+    delay::BLOCK,
+    gpio::IOPin,
+    i2c::{I2c, I2cConfig, I2cDriver},
+    peripheral::Peripheral,
+    units::FromValueType,
+};
+use crate::font::{self, Align};
+use crate::hal::Display;
+use crate::ht16k33::{self, Ht16k33};
+
+impl ht16k33::Bus for I2cDriver<'_> {
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<()> {
+        Ok(I2cDriver::write(self, address, bytes, BLOCK)?)
+    }
+}
+
+/// An HT16K33 backpack on its own I2C bus.
+pub struct Ht16k33Display<'d> {
+    ht16k33: Ht16k33<I2cDriver<'d>>,
+}
+
+impl<'d> Ht16k33Display<'d> {
+    pub fn new<I2C: I2c>(
+        i2c: impl Peripheral<P = I2C> + 'd,
+        sda: impl Peripheral<P = impl IOPin> + 'd,
+        scl: impl Peripheral<P = impl IOPin> + 'd,
+        address: u8,
+        layout: &'static ht16k33::Layout,
+    ) -> Result<Self> {
+        let driver = I2cDriver::new(i2c, sda, scl, &I2cConfig::new().baudrate(400.kHz().into()))?;
+        Ok(Self { ht16k33: Ht16k33::new(driver, address, layout)? })
+    }
+}
+
+impl Display for Ht16k33Display<'_> {
+    /// Wider backpacks show the app's four digits at the right hand end.
+    fn set_segments(&mut self, segments: [u8; 4]) -> Result<()> {
+        self.ht16k33.write_segments(&font::place(&segments, self.ht16k33.digits(), Align::Right))
+    }
+
+    fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+        self.ht16k33.set_brightness(brightness)
+    }
+}
@@ -0,0 +1,215 @@
+//! Turns raw keypad scans into debounced key events.
+//!
+//! `KeyEvents` is fed the key index from each scan, along with the time it was taken, and
+//! queues an event whenever the debounced state changes or a held key reaches its long-press
+//! or repeat time. It doesn't touch the hardware, so it can be driven from any scan loop.
+
+use crate::keys::Key;
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyEvent {
+    Pressed(Key),
+    Released(Key),
+    /// Sent once when a key has been held for `long_press_ms`.
+    LongPress(Key),
+    /// Sent every `repeat_interval_ms` once a key has been held for `repeat_delay_ms`.
+    Repeat(Key),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timings {
+    /// How long a scan has to read the same before it's believed.
+    pub debounce_ms: u64,
+    pub long_press_ms: u64,
+    pub repeat_delay_ms: u64,
+    pub repeat_interval_ms: u64,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Self {
+            debounce_ms: 20,
+            long_press_ms: 1000,
+            repeat_delay_ms: 500,
+            repeat_interval_ms: 150,
+        }
+    }
+}
+
+pub struct KeyEvents {
+    timings: Timings,
+    /// What the scans have read since `changed_ms`, which may still be bouncing.
+    raw: Option<Key>,
+    changed_ms: u64,
+    /// The debounced key.
+    held: Option<Key>,
+    pressed_ms: u64,
+    long_press_sent: bool,
+    next_repeat_ms: u64,
+    events: VecDeque<KeyEvent>,
+}
+
+impl KeyEvents {
+    pub fn new(timings: Timings) -> Self {
+        Self {
+            timings,
+            raw: None,
+            changed_ms: 0,
+            held: None,
+            pressed_ms: 0,
+            long_press_sent: false,
+            next_repeat_ms: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Feeds in a scan taken at `now_ms`.
+    pub fn update(&mut self, now_ms: u64, scan: Option<Key>) {
+        if scan != self.raw {
+            self.raw = scan;
+            self.changed_ms = now_ms;
+        }
+        if self.raw != self.held && now_ms - self.changed_ms >= self.timings.debounce_ms {
+            if let Some(key) = self.held {
+                self.events.push_back(KeyEvent::Released(key));
+            }
+            if let Some(key) = self.raw {
+                self.events.push_back(KeyEvent::Pressed(key));
+            }
+            self.hold(self.raw, now_ms);
+        }
+
+        let Some(key) = self.held else {
+            return;
+        };
+        let held_ms = now_ms - self.pressed_ms;
+        if !self.long_press_sent && held_ms >= self.timings.long_press_ms {
+            self.events.push_back(KeyEvent::LongPress(key));
+            self.long_press_sent = true;
+        }
+        if now_ms >= self.next_repeat_ms {
+            self.events.push_back(KeyEvent::Repeat(key));
+            self.next_repeat_ms = now_ms + self.timings.repeat_interval_ms;
+        }
+    }
+
+    /// Takes the oldest event that hasn't been handled yet.
+    pub fn next_event(&mut self) -> Option<KeyEvent> {
+        self.events.pop_front()
+    }
+
+    /// Treats `scan` as already pressed without sending `Pressed`, for a key that was handled
+    /// before the scan loop started, like the one that woke the board.
+    pub fn sync(&mut self, now_ms: u64, scan: Option<Key>) {
+        self.raw = scan;
+        self.changed_ms = now_ms;
+        self.hold(scan, now_ms);
+        self.events.clear();
+    }
+
+    fn hold(&mut self, key: Option<Key>, now_ms: u64) {
+        self.held = key;
+        self.pressed_ms = now_ms;
+        self.long_press_sent = false;
+        self.next_repeat_ms = now_ms + self.timings.repeat_delay_ms;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: Key = Key::Digit(1);
+    const TWO: Key = Key::Digit(2);
+
+    /// Feeds a scan every 10 ms from `from_ms` up to but not including `to_ms`, and returns
+    /// the events with the time of the scan that produced them.
+    fn scan(keys: &mut KeyEvents, from_ms: u64, to_ms: u64, key: Option<Key>) -> Vec<(u64, KeyEvent)> {
+        let mut events = Vec::new();
+        for now_ms in (from_ms..to_ms).step_by(10) {
+            keys.update(now_ms, key);
+            while let Some(event) = keys.next_event() {
+                events.push((now_ms, event));
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        let mut keys = KeyEvents::new(Timings::default());
+        let script = [(0, Some(ONE)), (5, None), (10, Some(ONE)), (15, None), (20, Some(ONE)), (35, Some(ONE))];
+        for (now_ms, key) in script {
+            keys.update(now_ms, key);
+        }
+        assert_eq!(keys.next_event(), None);
+        keys.update(40, Some(ONE));
+        assert_eq!(keys.next_event(), Some(KeyEvent::Pressed(ONE)));
+        assert_eq!(keys.next_event(), None);
+
+        // A release has to settle too.
+        for (now_ms, key) in [(50, None), (55, Some(ONE)), (60, None), (75, None)] {
+            keys.update(now_ms, key);
+        }
+        assert_eq!(keys.next_event(), None);
+        keys.update(80, None);
+        assert_eq!(keys.next_event(), Some(KeyEvent::Released(ONE)));
+    }
+
+    #[test]
+    fn changing_keys_releases_the_old_one_first() {
+        let mut keys = KeyEvents::new(Timings::default());
+        let mut events = scan(&mut keys, 0, 100, Some(ONE));
+        events.extend(scan(&mut keys, 100, 200, Some(TWO)));
+        assert_eq!(
+            events,
+            [(20, KeyEvent::Pressed(ONE)), (120, KeyEvent::Released(ONE)), (120, KeyEvent::Pressed(TWO))]
+        );
+    }
+
+    #[test]
+    fn a_held_key_long_presses_once_and_repeats() {
+        let mut keys = KeyEvents::new(Timings::default());
+        let events = scan(&mut keys, 0, 1300, Some(ONE));
+        let long_presses: Vec<_> =
+            events.iter().filter(|(_, event)| *event == KeyEvent::LongPress(ONE)).map(|(at, _)| *at).collect();
+        assert_eq!(long_presses, [1020]);
+        let repeats: Vec<_> =
+            events.iter().filter(|(_, event)| *event == KeyEvent::Repeat(ONE)).map(|(at, _)| *at).collect();
+        assert_eq!(repeats, [520, 670, 820, 970, 1120, 1270]);
+
+        // Letting go and pressing again starts the timings over.
+        let events = scan(&mut keys, 1300, 1400, None);
+        assert_eq!(events, [(1320, KeyEvent::Released(ONE))]);
+        let events = scan(&mut keys, 1400, 2000, Some(ONE));
+        assert_eq!(events, [(1420, KeyEvent::Pressed(ONE)), (1920, KeyEvent::Repeat(ONE))]);
+    }
+
+    #[test]
+    fn short_presses_dont_long_press_or_repeat() {
+        let mut keys = KeyEvents::new(Timings::default());
+        let mut events = scan(&mut keys, 0, 400, Some(ONE));
+        events.extend(scan(&mut keys, 400, 500, None));
+        assert_eq!(events, [(20, KeyEvent::Pressed(ONE)), (420, KeyEvent::Released(ONE))]);
+    }
+
+    #[test]
+    fn sync_suppresses_the_wake_key() {
+        let mut keys = KeyEvents::new(Timings::default());
+        keys.update(0, Some(ONE));
+        keys.sync(10, Some(ONE));
+        let events = scan(&mut keys, 20, 400, Some(ONE));
+        assert_eq!(events, []);
+
+        // It's still held, so it repeats and is released like any other key.
+        let events = scan(&mut keys, 400, 600, Some(ONE));
+        assert_eq!(events, [(510, KeyEvent::Repeat(ONE))]);
+        let events = scan(&mut keys, 600, 700, None);
+        assert_eq!(events, [(620, KeyEvent::Released(ONE))]);
+
+        let mut keys = KeyEvents::new(Timings::default());
+        keys.sync(0, None);
+        assert_eq!(scan(&mut keys, 0, 100, Some(TWO)), [(20, KeyEvent::Pressed(TWO))]);
+    }
+}
@@ -0,0 +1,119 @@
+//! Hands keypad changes to the app at the pace it polls.
+//!
+//! The keypad is scanned in the background, and each change it sees is queued here with the
+//! time it happened. `read` reports each change for a few calls so a tap shorter than the
+//! app's polling isn't missed. A change that has waited longer than `STALE_MS`, because the
+//! app was busy in a mode that doesn't read keys, is skipped.
+
+use crate::keys::Key;
+use std::collections::VecDeque;
+
+/// Each change is reported for this many calls to `read`, so a press shorter than the app's
+/// polling still lasts long enough to get past its debouncing.
+const READS_PER_CHANGE: u8 = 2;
+/// How long a change can wait to be read before it's too late to act on. A few polls of the
+/// slowest loop, so a quick tap still gets through.
+pub const STALE_MS: u64 = 200;
+
+pub struct KeyQueue {
+    pending: VecDeque<(u64, Option<Key>)>,
+    current: Option<Key>,
+    reads: u8,
+}
+
+impl KeyQueue {
+    /// Starts with `current` held, as found by the first scan.
+    pub fn new(current: Option<Key>) -> Self {
+        Self {
+            pending: VecDeque::new(),
+            current,
+            reads: READS_PER_CHANGE,
+        }
+    }
+
+    /// Queues the keypad changing to `key` at `at_ms`.
+    pub fn push(&mut self, at_ms: u64, key: Option<Key>) {
+        self.pending.push_back((at_ms, key));
+    }
+
+    /// The key to report for a read at `now_ms`.
+    pub fn read(&mut self, now_ms: u64) -> Option<Key> {
+        while let Some(&(at_ms, key)) = self.pending.front() {
+            if now_ms.saturating_sub(at_ms) <= STALE_MS {
+                break;
+            }
+            self.pending.pop_front();
+            self.current = key;
+            self.reads = READS_PER_CHANGE;
+        }
+        if self.reads >= READS_PER_CHANGE {
+            if let Some((_, key)) = self.pending.pop_front() {
+                self.current = key;
+                self.reads = 0;
+            }
+        }
+        self.reads = self.reads.saturating_add(1);
+        self.current
+    }
+
+    /// Skips every queued change, leaving the keypad as it was last seen.
+    pub fn clear(&mut self) {
+        if let Some(&(_, key)) = self.pending.back() {
+            self.current = key;
+        }
+        self.pending.clear();
+        self.reads = READS_PER_CHANGE;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: Key = Key::Digit(1);
+    const TWO: Key = Key::Digit(2);
+
+    #[test]
+    fn a_tap_between_reads_is_still_seen() {
+        let mut queue = KeyQueue::new(None);
+        assert_eq!(queue.read(0), None);
+        queue.push(10, Some(ONE));
+        queue.push(30, None);
+        assert_eq!(queue.read(50), Some(ONE));
+        assert_eq!(queue.read(100), Some(ONE));
+        assert_eq!(queue.read(150), None);
+        assert_eq!(queue.read(200), None);
+    }
+
+    #[test]
+    fn stale_changes_are_skipped() {
+        let mut queue = KeyQueue::new(None);
+        queue.push(0, Some(ONE));
+        queue.push(100, None);
+        queue.push(200, Some(TWO));
+        queue.push(300, None);
+        queue.push(1000, Some(ONE));
+        assert_eq!(queue.read(2000), Some(ONE));
+
+        // Only the fresh ones are replayed.
+        queue.push(2010, None);
+        queue.push(2020, Some(TWO));
+        queue.push(2030, None);
+        let reads: Vec<_> = (0..7).map(|i| queue.read(2100 + i * 10)).collect();
+        assert_eq!(reads, [None, None, Some(TWO), Some(TWO), None, None, None]);
+    }
+
+    #[test]
+    fn clear_keeps_only_the_latest_state() {
+        let mut queue = KeyQueue::new(None);
+        queue.push(0, Some(ONE));
+        queue.push(10, None);
+        queue.push(20, Some(TWO));
+        queue.clear();
+        assert_eq!(queue.read(30), Some(TWO));
+
+        queue.push(40, None);
+        queue.clear();
+        assert_eq!(queue.read(50), None);
+    }
+}
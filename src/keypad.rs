@@ -1,62 +1,265 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use esp_idf_svc::hal::{
+    delay::{FreeRtos, BLOCK},
     gpio::*,
-    peripheral::Peripheral,
+    task::notification::{Notification, Notifier},
 };
-use esp_idf_svc::sys::EspError;
+use esp_idf_svc::sys::{
+    esp,
+    EspError,
+    esp_sleep_enable_gpio_wakeup,
+    esp_timer_get_time,
+    gpio_deep_sleep_hold_en,
+    gpio_hold_dis,
+    gpio_hold_en,
+    gpio_int_type_t_GPIO_INTR_LOW_LEVEL,
+    gpio_mode_t_GPIO_MODE_OUTPUT,
+    gpio_set_direction,
+    gpio_set_level,
+    gpio_wakeup_enable,
+    rtc_gpio_is_valid_gpio,
+    rtc_gpio_pulldown_en,
+    rtc_gpio_pullup_dis,
+};
+use crate::hal::KeyInput;
+use crate::key_queue::KeyQueue;
+use crate::keys::{Key, Layout};
+use std::num::NonZeroU32;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::Arc;
+use std::thread;
+
+/// How long to let the contacts settle after a column falls before scanning.
+const SETTLE_MS: u32 = 10;
+/// While a key is held the matrix is rescanned this often to see it released.
+const HELD_SCAN_MS: u32 = 20;
+
+enum Request {
+    PrepareForSleep(SyncSender<Result<()>>),
+}
 
-pub struct Keypad<'a> {
-    cols: [PinDriver<'a, AnyIOPin, InputOutput>; 3],
-    rows: [PinDriver<'a, AnyIOPin, InputOutput>; 4],
+/// The matrix pins, owned by the scanning thread.
+struct Matrix {
+    cols: Vec<PinDriver<'static, AnyIOPin, InputOutput>>,
+    rows: Vec<PinDriver<'static, AnyIOPin, InputOutput>>,
+    layout: &'static Layout,
 }
 
-impl<'d> Keypad<'d> {
+/// A matrix keypad scanned by its own thread.
+///
+/// Between presses the rows are parked low with a low level interrupt on every column, so
+/// nothing runs until a key pulls its column down. The thread then scans the matrix until the
+/// key is released and queues each change for `get_key`, timed so that changes the app was
+/// too busy to read can be skipped. The columns are also light sleep wake sources, so a key
+/// press wakes the chip from light sleep.
+pub struct Keypad {
+    changes: Receiver<(u64, Option<Key>)>,
+    requests: Sender<Request>,
+    notifier: Arc<Notifier>,
+    queue: KeyQueue,
+}
+
+impl Keypad {
+    /// `cols` and `rows` are the matrix pins left to right and top to bottom, and must match
+    /// the size of `layout`.
     pub fn new(
-        c1: impl Peripheral<P = impl IOPin> + 'd,
-        c2: impl Peripheral<P = impl IOPin> + 'd,
-        c3: impl Peripheral<P = impl IOPin> + 'd,
-        r1: impl Peripheral<P = impl IOPin> + 'd,
-        r2: impl Peripheral<P = impl IOPin> + 'd,
-        r3: impl Peripheral<P = impl IOPin> + 'd,
-        r4: impl Peripheral<P = impl IOPin> + 'd,
-    ) -> Result<Self, EspError> {
-        let mut col_drivers = [
-            PinDriver::input_output_od(c1.into_ref().map_into::<AnyIOPin>())?,
-            PinDriver::input_output_od(c2.into_ref().map_into::<AnyIOPin>())?,
-            PinDriver::input_output_od(c3.into_ref().map_into::<AnyIOPin>())?,
-        ];
-        let mut row_drivers = [
-            PinDriver::input_output_od(r1.into_ref().map_into::<AnyIOPin>())?,
-            PinDriver::input_output_od(r2.into_ref().map_into::<AnyIOPin>())?,
-            PinDriver::input_output_od(r3.into_ref().map_into::<AnyIOPin>())?,
-            PinDriver::input_output_od(r4.into_ref().map_into::<AnyIOPin>())?,
-        ];
+        cols: impl IntoIterator<Item = AnyIOPin>,
+        rows: impl IntoIterator<Item = AnyIOPin>,
+        layout: &'static Layout,
+    ) -> Result<Self> {
+        let matrix = Matrix::new(cols, rows, layout)?;
+        let (changes_tx, changes) = mpsc::channel();
+        let (requests, requests_rx) = mpsc::channel();
+        let (ready_tx, ready) = mpsc::sync_channel(1);
+        thread::Builder::new()
+            .name("keypad".into())
+            .stack_size(4096)
+            .spawn(move || {
+                if let Err(err) = matrix.run(changes_tx, requests_rx, ready_tx) {
+                    log::error!("Keypad scanning stopped: {:?}", err);
+                }
+            })?;
+        // The first scan is done before the thread reports in, so a key that woke the board
+        // is already held when the app first asks.
+        let (notifier, current) = ready.recv()
+            .map_err(|_| anyhow!("keypad thread exited during setup"))?;
+
+        Ok(Self {
+            changes,
+            requests,
+            notifier,
+            queue: KeyQueue::new(current),
+        })
+    }
+
+    pub fn get_key(&mut self) -> Result<Option<Key>> {
+        self.receive();
+        Ok(self.queue.read(now_ms()))
+    }
+
+    fn receive(&mut self) {
+        for (at_ms, key) in self.changes.try_iter() {
+            self.queue.push(at_ms, key);
+        }
+    }
+}
+
+impl KeyInput for Keypad {
+    fn get_key(&mut self) -> Result<Option<Key>> {
+        Keypad::get_key(self)
+    }
+
+    fn clear(&mut self) {
+        self.receive();
+        self.queue.clear();
+    }
+
+    fn prepare_for_sleep(&mut self) -> Result<()> {
+        let (reply, result) = mpsc::sync_channel(1);
+        self.requests.send(Request::PrepareForSleep(reply))
+            .map_err(|_| anyhow!("keypad thread isn't running"))?;
+        unsafe { self.notifier.notify(NonZeroU32::MIN) };
+        result.recv().map_err(|_| anyhow!("keypad thread isn't running"))?
+    }
+}
+
+impl Matrix {
+    fn new(
+        cols: impl IntoIterator<Item = AnyIOPin>,
+        rows: impl IntoIterator<Item = AnyIOPin>,
+        layout: &'static Layout,
+    ) -> Result<Self> {
+        let mut col_drivers = cols.into_iter()
+            .map(PinDriver::input_output_od)
+            .collect::<Result<Vec<_>, EspError>>()?;
+        let mut row_drivers = rows.into_iter()
+            .map(PinDriver::input_output_od)
+            .collect::<Result<Vec<_>, EspError>>()?;
+        if col_drivers.len() != layout.columns || row_drivers.len() != layout.rows() {
+            bail!(
+                "keypad has {}x{} pins but the layout is {}x{}",
+                col_drivers.len(), row_drivers.len(), layout.columns, layout.rows(),
+            );
+        }
 
         for col in col_drivers.iter_mut() {
             col.set_pull(Pull::Up)?;
             col.set_high()?;
+            col.set_interrupt_type(InterruptType::LowLevel)?;
         }
 
         for row in row_drivers.iter_mut() {
+            // Rows are latched high while in deep sleep, release them after waking.
+            esp!(unsafe { gpio_hold_dis(row.pin()) })?;
             row.set_pull(Pull::Up)?;
             row.set_high()?;
         }
 
+        esp!(unsafe { esp_sleep_enable_gpio_wakeup() })?;
+        Ok(Self{ cols: col_drivers, rows: row_drivers, layout })
+    }
+
+    fn run(
+        mut self,
+        changes: Sender<(u64, Option<Key>)>,
+        requests: Receiver<Request>,
+        ready: SyncSender<(Arc<Notifier>, Option<Key>)>,
+    ) -> Result<()> {
+        // A notification belongs to the task that creates it, so it has to be made here.
+        let notification = Notification::new();
+        for col in self.cols.iter_mut() {
+            let notifier = notification.notifier();
+            unsafe {
+                col.subscribe(move || {
+                    notifier.notify_and_yield(NonZeroU32::MIN);
+                })?;
+            }
+        }
+
+        let mut held = self.scan()?;
+        self.park()?;
+        if ready.send((notification.notifier(), held)).is_err() {
+            return Ok(());
+        }
+        loop {
+            if held.is_some() {
+                FreeRtos::delay_ms(HELD_SCAN_MS);
+            } else {
+                notification.wait(BLOCK);
+            }
+            if let Ok(Request::PrepareForSleep(reply)) = requests.try_recv() {
+                let _ = reply.send(self.prepare_for_sleep());
+                return Ok(());
+            }
 
-        Ok(Self{ cols: col_drivers, rows: row_drivers })
+            FreeRtos::delay_ms(SETTLE_MS);
+            let key = self.scan()?;
+            self.park()?;
+            if key != held {
+                held = key;
+                if changes.send((now_ms(), key)).is_err() {
+                    return Ok(());
+                }
+            }
+        }
     }
 
-    pub fn get_key(&mut self) -> Result<Option<u8>> {
+    fn scan(&mut self) -> Result<Option<Key>> {
+        for row in self.rows.iter_mut() {
+            row.set_high()?;
+        }
         for (i, row) in self.rows.iter_mut().enumerate() {
             row.set_low()?;
             for (j, col) in self.cols.iter_mut().enumerate() {
                 if col.get_level() == Level::Low {
                     row.set_high()?;
-                    return Ok(Some((i * 3 + j) as u8));
+                    return Ok(self.layout.key(i, j));
                 }
             }
             row.set_high()?;
         }
         Ok(None)
     }
+
+    /// Drives every row low so any key pulls its column down, and rearms the column
+    /// interrupts, which are disabled each time they fire.
+    fn park(&mut self) -> Result<()> {
+        for row in self.rows.iter_mut() {
+            row.set_low()?;
+        }
+        for col in self.cols.iter_mut() {
+            esp!(unsafe { gpio_wakeup_enable(col.pin(), gpio_int_type_t_GPIO_INTR_LOW_LEVEL) })?;
+            col.enable_interrupt()?;
+        }
+        Ok(())
+    }
+
+    fn prepare_for_sleep(&mut self) -> Result<()> {
+        for col in self.cols.iter_mut() {
+            col.disable_interrupt()?;
+        }
+        // Open drain can't drive a row high, so switch the rows to push-pull and hold them
+        // there through deep sleep. A key press then pulls its column high.
+        for row in self.rows.iter() {
+            unsafe {
+                esp!(gpio_set_direction(row.pin(), gpio_mode_t_GPIO_MODE_OUTPUT))?;
+                esp!(gpio_set_level(row.pin(), 1))?;
+                esp!(gpio_hold_en(row.pin()))?;
+            }
+        }
+        for col in self.cols.iter() {
+            if unsafe { rtc_gpio_is_valid_gpio(col.pin()) } {
+                unsafe {
+                    esp!(rtc_gpio_pullup_dis(col.pin()))?;
+                    esp!(rtc_gpio_pulldown_en(col.pin()))?;
+                }
+            }
+        }
+        unsafe { gpio_deep_sleep_hold_en() };
+        Ok(())
+    }
+}
+
+fn now_ms() -> u64 {
+    (unsafe { esp_timer_get_time() } / 1000) as u64
 }
@@ -0,0 +1,112 @@
+//! Keypad keys and the layouts that map matrix positions to them.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    Digit(u8),
+    Star,
+    Hash,
+    /// The `A`-`D` column of a 4x4 keypad.
+    Letter(char),
+}
+
+impl Key {
+    pub fn digit(self) -> Option<u8> {
+        match self {
+            Key::Digit(digit) => Some(digit),
+            _ => None,
+        }
+    }
+}
+
+/// The keys of a matrix keypad, row by row.
+pub struct Layout {
+    pub columns: usize,
+    pub keys: &'static [Key],
+}
+
+impl Layout {
+    pub fn rows(&self) -> usize {
+        self.keys.len() / self.columns
+    }
+
+    pub fn key(&self, row: usize, column: usize) -> Option<Key> {
+        if column >= self.columns {
+            return None;
+        }
+        self.keys.get(row * self.columns + column).copied()
+    }
+
+    /// The row and column of `key`, if the layout has it.
+    pub fn position(&self, key: Key) -> Option<(usize, usize)> {
+        let index = self.keys.iter().position(|k| *k == key)?;
+        Some((index / self.columns, index % self.columns))
+    }
+}
+
+/// The usual telephone style 3x4 membrane keypad.
+pub static PHONE_3X4: Layout = Layout {
+    columns: 3,
+    keys: &[
+        Key::Digit(1), Key::Digit(2), Key::Digit(3),
+        Key::Digit(4), Key::Digit(5), Key::Digit(6),
+        Key::Digit(7), Key::Digit(8), Key::Digit(9),
+        Key::Star, Key::Digit(0), Key::Hash,
+    ],
+};
+
+/// A 4x4 keypad with an extra `A`-`D` column on the right.
+pub static MATRIX_4X4: Layout = Layout {
+    columns: 4,
+    keys: &[
+        Key::Digit(1), Key::Digit(2), Key::Digit(3), Key::Letter('A'),
+        Key::Digit(4), Key::Digit(5), Key::Digit(6), Key::Letter('B'),
+        Key::Digit(7), Key::Digit(8), Key::Digit(9), Key::Letter('C'),
+        Key::Star, Key::Digit(0), Key::Hash, Key::Letter('D'),
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_phone_layout_reads_like_a_telephone() {
+        assert_eq!(PHONE_3X4.rows(), 4);
+        assert_eq!(PHONE_3X4.key(0, 0), Some(Key::Digit(1)));
+        assert_eq!(PHONE_3X4.key(1, 2), Some(Key::Digit(6)));
+        assert_eq!(PHONE_3X4.key(3, 0), Some(Key::Star));
+        assert_eq!(PHONE_3X4.key(3, 1), Some(Key::Digit(0)));
+        assert_eq!(PHONE_3X4.key(3, 2), Some(Key::Hash));
+        assert_eq!(PHONE_3X4.key(0, 3), None);
+        assert_eq!(PHONE_3X4.key(4, 0), None);
+        assert_eq!(PHONE_3X4.position(Key::Letter('A')), None);
+    }
+
+    #[test]
+    fn the_4x4_layout_adds_a_letter_column() {
+        assert_eq!(MATRIX_4X4.rows(), 4);
+        assert_eq!(MATRIX_4X4.key(0, 2), Some(Key::Digit(3)));
+        assert_eq!(MATRIX_4X4.key(0, 3), Some(Key::Letter('A')));
+        assert_eq!(MATRIX_4X4.key(3, 1), Some(Key::Digit(0)));
+        assert_eq!(MATRIX_4X4.key(3, 3), Some(Key::Letter('D')));
+        assert_eq!(MATRIX_4X4.key(0, 4), None);
+    }
+
+    #[test]
+    fn positions_lead_back_to_their_keys() {
+        for layout in [&PHONE_3X4, &MATRIX_4X4] {
+            for key in layout.keys {
+                let (row, column) = layout.position(*key).unwrap();
+                assert_eq!(layout.key(row, column), Some(*key));
+            }
+        }
+    }
+
+    #[test]
+    fn only_digits_have_a_digit() {
+        assert_eq!(Key::Digit(7).digit(), Some(7));
+        assert_eq!(Key::Star.digit(), None);
+        assert_eq!(Key::Hash.digit(), None);
+        assert_eq!(Key::Letter('B').digit(), None);
+    }
+}
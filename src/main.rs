@@ -1,11 +1,40 @@
+pub mod adpcm;
+pub mod animation;
 pub mod app;
+pub mod clip_sound;
+pub mod hal;
+pub mod ht16k33;
+pub mod key_events;
+pub mod key_queue;
+pub mod keys;
+pub mod manifest;
+pub mod max7219;
+pub mod mixer;
+pub mod nec;
+pub mod program;
+pub mod defrost;
+pub mod font;
+pub mod preset;
+pub mod tm1637;
+pub mod wav;
+#[cfg(target_os = "espidf")]
+pub mod board;
+#[cfg(target_os = "espidf")]
 pub mod seven_segment;
+#[cfg(all(target_os = "espidf", feature = "display-max7219"))]
+pub mod max7219_display;
+#[cfg(all(target_os = "espidf", feature = "display-ht16k33"))]
+pub mod ht16k33_display;
+#[cfg(target_os = "espidf")]
 pub mod keypad;
+#[cfg(target_os = "espidf")]
 pub mod speaker;
+#[cfg(target_os = "espidf")]
 pub mod remote;
+#[cfg(not(target_os = "espidf"))]
+pub mod sim;
 
-use crate::app::run_app;
-
+#[cfg(target_os = "espidf")]
 fn main() {
     // It is necessary to call this function once. Otherwise some patches to the runtime
     // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
@@ -14,5 +43,28 @@ fn main() {
     // Bind the log crate to the ESP Logging facilities
     esp_idf_svc::log::EspLogger::initialize_default();
 
-    run_app().unwrap();
+    board::run_app().unwrap();
+}
+
+// On the host, run a short scripted session through the simulator and print what happened.
+#[cfg(not(target_os = "espidf"))]
+fn main() {
+    use keys::Key;
+    use sim::{Event, Input, Simulator};
+
+    let mut sim = Simulator::new();
+    sim.press(Input::Key(Key::Digit(1)), 1000)
+        .press(Input::Key(Key::Digit(0)), 1500)
+        .press(Input::Start, 2000)
+        .hold(Input::DoorOpen, 6000, 2000)
+        .press(Input::Start, 9000);
+    let mode = sim.run_until(30000).unwrap();
+
+    for (time, event) in sim.events() {
+        match event {
+            Event::Display(segments) => println!("{:>6} ms  [{}]", time, sim::render(segments)),
+            event => println!("{:>6} ms  {:?}", time, event),
+        }
+    }
+    println!("ended in {:?}", mode);
 }
@@ -0,0 +1,262 @@
+//! The manifest of a sound pack on the data partition.
+//!
+//! A pack is a read-only FAT volume holding `MANIFEST.TXT` and the WAV files it names. Each
+//! line of the manifest is an event, the file to play for it and optionally a gain:
+//!
+//! ```text
+//! # event  file         gain
+//! beep     BEEP.WAV
+//! running  RUNNING.WAV  0.7
+//! ```
+//!
+//! Blank lines and anything after a `#` are ignored, and events that aren't listed keep their
+//! built-in sound. The firmware mounts the volume without long file name support, so files
+//! need 8.3 names. `util/sound-pack` shares this module to write packs the firmware can read.
+
+use std::fmt;
+
+pub const MANIFEST_FILE: &str = "MANIFEST.TXT";
+
+/// The format the speaker mixes in. Every sound in a pack has to be mono 16 kHz, either PCM or
+/// IMA ADPCM.
+pub const CHANNELS: u16 = 1;
+pub const SAMPLE_RATE: u32 = 16000;
+
+/// The loudest a sound can be set to relative to its recording. The mixer's limiter keeps
+/// anything louder from clipping, but past this it would be squashing most of the sound.
+pub const MAX_GAIN: f32 = 4.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SoundEvent {
+    /// Key presses and most other feedback.
+    Beep,
+    /// Cooking starting.
+    Start,
+    /// Looped while the magnetron is running.
+    Running,
+    /// Cooking finishing.
+    Done,
+    /// The door being opened while cooking.
+    Door,
+}
+
+impl SoundEvent {
+    pub const ALL: [SoundEvent; 5] = [
+        SoundEvent::Beep,
+        SoundEvent::Start,
+        SoundEvent::Running,
+        SoundEvent::Done,
+        SoundEvent::Door,
+    ];
+
+    /// The name used in the manifest.
+    pub fn name(self) -> &'static str {
+        match self {
+            SoundEvent::Beep => "beep",
+            SoundEvent::Start => "start",
+            SoundEvent::Running => "running",
+            SoundEvent::Done => "done",
+            SoundEvent::Door => "door",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|event| event.name() == name)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ManifestError {
+    UnknownEvent{line: usize, name: String},
+    DuplicateEvent{line: usize, event: SoundEvent},
+    MissingFile{line: usize},
+    BadFileName{line: usize, name: String},
+    BadGain{line: usize, gain: String},
+    ExtraField{line: usize},
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::UnknownEvent{line, name} => write!(f, "line {}: unknown event {:?}", line, name),
+            ManifestError::DuplicateEvent{line, event} => {
+                write!(f, "line {}: {} is already listed", line, event.name())
+            },
+            ManifestError::MissingFile{line} => write!(f, "line {}: no file given", line),
+            ManifestError::BadFileName{line, name} => {
+                write!(f, "line {}: {:?} isn't an 8.3 file name", line, name)
+            },
+            ManifestError::BadGain{line, gain} => {
+                write!(f, "line {}: gain {:?} isn't a number from 0 to {}", line, gain, MAX_GAIN)
+            },
+            ManifestError::ExtraField{line} => write!(f, "line {}: too many fields", line),
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub event: SoundEvent,
+    pub file: String,
+    /// Applied on top of the master volume, 1.0 for the file's own level.
+    pub gain: f32,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Manifest {
+    pub entries: Vec<Entry>,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Self, ManifestError> {
+        let mut entries: Vec<Entry> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.split('#').next().unwrap_or("");
+            let mut fields = line.split_whitespace();
+            let Some(name) = fields.next() else {
+                continue;
+            };
+            let event = SoundEvent::from_name(name).ok_or_else(|| ManifestError::UnknownEvent {
+                line: line_number,
+                name: name.to_string(),
+            })?;
+            if entries.iter().any(|entry| entry.event == event) {
+                return Err(ManifestError::DuplicateEvent{line: line_number, event});
+            }
+            let file = fields.next().ok_or(ManifestError::MissingFile{line: line_number})?;
+            if !is_short_name(file) {
+                return Err(ManifestError::BadFileName{line: line_number, name: file.to_string()});
+            }
+            let gain = match fields.next() {
+                Some(gain) => parse_gain(gain).ok_or_else(|| ManifestError::BadGain {
+                    line: line_number,
+                    gain: gain.to_string(),
+                })?,
+                None => 1.0,
+            };
+            if fields.next().is_some() {
+                return Err(ManifestError::ExtraField{line: line_number});
+            }
+            entries.push(Entry { event, file: file.to_string(), gain });
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn entry(&self, event: SoundEvent) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.event == event)
+    }
+
+    /// The manifest as `parse` reads it.
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# event  file          gain\n");
+        for entry in &self.entries {
+            text.push_str(&format!("{:<8} {:<13} {}\n", entry.event.name(), entry.file, entry.gain));
+        }
+        text
+    }
+}
+
+/// Whether a sound in this format can go in a pack.
+pub fn is_playable(channels: u16, sample_rate: u32) -> bool {
+    channels == CHANNELS && sample_rate == SAMPLE_RATE
+}
+
+/// Whether `name` is an 8.3 name, up to eight characters then optionally a dot and up to
+/// three more, that doesn't need a long file name entry.
+pub fn is_short_name(name: &str) -> bool {
+    let valid = |part: &str, max: usize| {
+        !part.is_empty()
+            && part.len() <= max
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || "_-~!$%&'()@^`{}".contains(c))
+    };
+    match name.split_once('.') {
+        Some((stem, extension)) => valid(stem, 8) && valid(extension, 3),
+        None => valid(name, 8),
+    }
+}
+
+fn parse_gain(gain: &str) -> Option<f32> {
+    gain.parse::<f32>().ok().filter(|gain| (0.0..=MAX_GAIN).contains(gain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(event: SoundEvent, file: &str, gain: f32) -> Entry {
+        Entry { event, file: file.to_string(), gain }
+    }
+
+    #[test]
+    fn parses_events_files_and_gains() {
+        let text = "# event  file  gain\n\nbeep BEEP.WAV\n  running\tRUNNING.WAV 0.7  # quieter\ndone d.wav 4\n";
+        let manifest = Manifest::parse(text).unwrap();
+        assert_eq!(
+            manifest.entries,
+            [
+                entry(SoundEvent::Beep, "BEEP.WAV", 1.0),
+                entry(SoundEvent::Running, "RUNNING.WAV", 0.7),
+                entry(SoundEvent::Done, "d.wav", 4.0),
+            ]
+        );
+        assert_eq!(manifest.entry(SoundEvent::Running).unwrap().gain, 0.7);
+        assert_eq!(manifest.entry(SoundEvent::Door), None);
+        assert_eq!(Manifest::parse("").unwrap(), Manifest::default());
+    }
+
+    #[test]
+    fn to_text_parses_back() {
+        let manifest = Manifest {
+            entries: vec![
+                entry(SoundEvent::Door, "DOOR.WAV", 1.0),
+                entry(SoundEvent::Beep, "B", 0.25),
+                entry(SoundEvent::Start, "START1.WAV", 0.0),
+                entry(SoundEvent::Running, "RUNNING.WAV", 1.3),
+                entry(SoundEvent::Done, "DONE_2~1.WAV", MAX_GAIN),
+            ],
+        };
+        assert_eq!(Manifest::parse(&manifest.to_text()).unwrap(), manifest);
+        assert_eq!(Manifest::parse(&Manifest::default().to_text()).unwrap(), Manifest::default());
+    }
+
+    #[test]
+    fn each_mistake_is_reported_with_its_line() {
+        let cases = [
+            ("beep B.WAV\nbop BOP.WAV", ManifestError::UnknownEvent{line: 2, name: "bop".into()}),
+            ("BEEP B.WAV", ManifestError::UnknownEvent{line: 1, name: "BEEP".into()}),
+            ("beep A.WAV\n\nbeep B.WAV", ManifestError::DuplicateEvent{line: 3, event: SoundEvent::Beep}),
+            ("door # DOOR.WAV", ManifestError::MissingFile{line: 1}),
+            ("done LONGNAME1.WAV", ManifestError::BadFileName{line: 1, name: "LONGNAME1.WAV".into()}),
+            ("done DONE.WAVE", ManifestError::BadFileName{line: 1, name: "DONE.WAVE".into()}),
+            ("done A.B.C", ManifestError::BadFileName{line: 1, name: "A.B.C".into()}),
+            ("start S.WAV loud", ManifestError::BadGain{line: 1, gain: "loud".into()}),
+            ("start S.WAV -0.5", ManifestError::BadGain{line: 1, gain: "-0.5".into()}),
+            ("start S.WAV 4.5", ManifestError::BadGain{line: 1, gain: "4.5".into()}),
+            ("start S.WAV NaN", ManifestError::BadGain{line: 1, gain: "NaN".into()}),
+            ("running R.WAV 1 2", ManifestError::ExtraField{line: 1}),
+        ];
+        for (text, error) in cases {
+            assert_eq!(Manifest::parse(text), Err(error), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn short_names_are_8_3() {
+        for name in ["A", "BEEP.WAV", "12345678.ABC", "DONE_2~1.WAV", "x.y"] {
+            assert!(is_short_name(name), "{}", name);
+        }
+        for name in ["", ".WAV", "BEEP.", "123456789", "BEEP.WAVE", "BEEP WAV", "BÉEP.WAV", "A.B.C", "A+B.WAV"] {
+            assert!(!is_short_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn only_the_mix_format_is_playable() {
+        assert!(is_playable(1, 16000));
+        assert!(!is_playable(2, 16000));
+        assert!(!is_playable(1, 44100));
+    }
+}
@@ -0,0 +1,178 @@
+//! The MAX7219 LED driver, as found on the common eight digit SPI modules.
+//!
+//! Every write is a 16 bit frame, a register address then its value, latched when chip
+//! select rises. The driver is generic over `Bus` so it can run against a mock.
+
+use anyhow::Result;
+use crate::hal::MAX_BRIGHTNESS;
+use std::fmt;
+
+const NO_DECODE: u8 = 0x00;
+
+const REG_DIGIT_0: u8 = 0x01;
+const REG_DECODE_MODE: u8 = 0x09;
+const REG_INTENSITY: u8 = 0x0A;
+const REG_SCAN_LIMIT: u8 = 0x0B;
+const REG_SHUTDOWN: u8 = 0x0C;
+const REG_DISPLAY_TEST: u8 = 0x0F;
+
+pub const MAX_DIGITS: usize = 8;
+/// The chip has sixteen intensity steps, two for each brightness level.
+const MAX_INTENSITY: u8 = 0x0F;
+
+/// An SPI device with its own chip select.
+pub trait Bus {
+    /// Sends `bytes` as one transaction, with chip select held low throughout.
+    fn write(&mut self, bytes: &[u8]) -> Result<()>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Max7219Error {
+    TooManyDigits(usize),
+}
+
+impl fmt::Display for Max7219Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Max7219Error::TooManyDigits(digits) => write!(f, "{} digits won't fit on the display", digits),
+        }
+    }
+}
+
+impl std::error::Error for Max7219Error {}
+
+pub struct Max7219<B> {
+    bus: B,
+    digits: usize,
+}
+
+impl<B: Bus> Max7219<B> {
+    /// Sets the chip up for raw segments on `digits` digits and turns it on at full brightness.
+    pub fn new(bus: B, digits: usize) -> Result<Self> {
+        if digits == 0 || digits > MAX_DIGITS {
+            return Err(Max7219Error::TooManyDigits(digits).into());
+        }
+        let mut max7219 = Self { bus, digits };
+        max7219.write_register(REG_DISPLAY_TEST, 0)?;
+        max7219.write_register(REG_DECODE_MODE, NO_DECODE)?;
+        max7219.write_register(REG_SCAN_LIMIT, digits as u8 - 1)?;
+        max7219.write_register(REG_INTENSITY, MAX_INTENSITY)?;
+        max7219.write_segments(&[])?;
+        max7219.set_on(true)?;
+        Ok(max7219)
+    }
+
+    pub fn digits(&self) -> usize {
+        self.digits
+    }
+
+    /// Sets one of the eight brightness levels, 0-7.
+    pub fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+        self.write_register(REG_INTENSITY, brightness.min(MAX_BRIGHTNESS) * 2 + 1)
+    }
+
+    /// Shuts the display down or wakes it again, keeping what's on it.
+    pub fn set_on(&mut self, on: bool) -> Result<()> {
+        self.write_register(REG_SHUTDOWN, on as u8)
+    }
+
+    /// Writes segments to the digits from the left, blanking any past the end of `segments`.
+    pub fn write_segments(&mut self, segments: &[u8]) -> Result<()> {
+        if segments.len() > self.digits {
+            return Err(Max7219Error::TooManyDigits(segments.len()).into());
+        }
+        // Digit 0 is the right hand end of the module.
+        for position in 0..self.digits {
+            let segment = segments.get(position).copied().unwrap_or(0);
+            let digit = (self.digits - 1 - position) as u8;
+            self.write_register(REG_DIGIT_0 + digit, encode_segments(segment))?;
+        }
+        Ok(())
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<()> {
+        self.bus.write(&[register, value])
+    }
+}
+
+/// The MAX7219 wants the point in the top bit and then segments A to G, the reverse of the
+/// usual order.
+pub fn encode_segments(segments: u8) -> u8 {
+    (segments & 0x80) | ((segments & 0x7F).reverse_bits() >> 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the frames written, as (register, value).
+    #[derive(Default)]
+    struct MockBus(Vec<(u8, u8)>);
+
+    impl Bus for MockBus {
+        fn write(&mut self, bytes: &[u8]) -> Result<()> {
+            assert_eq!(bytes.len(), 2, "frames are a register and a value");
+            self.0.push((bytes[0], bytes[1]));
+            Ok(())
+        }
+    }
+
+    fn take(max7219: &mut Max7219<MockBus>) -> Vec<(u8, u8)> {
+        std::mem::take(&mut max7219.bus.0)
+    }
+
+    #[test]
+    fn init_sets_raw_segments_and_blanks_the_digits() {
+        let mut max7219 = Max7219::new(MockBus::default(), 4).unwrap();
+        assert_eq!(
+            take(&mut max7219),
+            [(0x0F, 0), (0x09, 0), (0x0B, 3), (0x0A, 0x0F), (0x04, 0), (0x03, 0), (0x02, 0), (0x01, 0), (0x0C, 1)]
+        );
+
+        let mut max7219 = Max7219::new(MockBus::default(), 8).unwrap();
+        assert!(take(&mut max7219).contains(&(0x0B, 7)));
+    }
+
+    #[test]
+    fn digit_counts_outside_the_chip_are_rejected() {
+        for digits in [0, 9] {
+            let err = Max7219::new(MockBus::default(), digits).err().unwrap();
+            assert_eq!(err.downcast_ref::<Max7219Error>(), Some(&Max7219Error::TooManyDigits(digits)));
+        }
+    }
+
+    #[test]
+    fn digits_are_written_right_to_left() {
+        let mut max7219 = Max7219::new(MockBus::default(), 4).unwrap();
+        take(&mut max7219);
+        max7219.write_segments(&[0x01, 0x40, 0x81]).unwrap();
+        assert_eq!(take(&mut max7219), [(0x04, 0x40), (0x03, 0x01), (0x02, 0xC0), (0x01, 0)]);
+
+        let err = max7219.write_segments(&[0; 5]).unwrap_err();
+        assert_eq!(err.downcast_ref::<Max7219Error>(), Some(&Max7219Error::TooManyDigits(5)));
+        assert!(take(&mut max7219).is_empty());
+    }
+
+    #[test]
+    fn segments_are_reordered_for_the_chip() {
+        // A is the top bit after the point, and G the bottom one.
+        assert_eq!(encode_segments(0b0000_0001), 0b0100_0000);
+        assert_eq!(encode_segments(0b0100_0000), 0b0000_0001);
+        assert_eq!(encode_segments(0b1000_0000), 0b1000_0000);
+        // 2 is A, B, D, E and G.
+        assert_eq!(encode_segments(0b0101_1011), 0b0110_1101);
+    }
+
+    #[test]
+    fn brightness_uses_two_intensity_steps_a_level() {
+        let mut max7219 = Max7219::new(MockBus::default(), 4).unwrap();
+        take(&mut max7219);
+        for brightness in [0, 3, MAX_BRIGHTNESS, 20] {
+            max7219.set_brightness(brightness).unwrap();
+        }
+        assert_eq!(take(&mut max7219), [(0x0A, 1), (0x0A, 7), (0x0A, 0x0F), (0x0A, 0x0F)]);
+
+        max7219.set_on(false).unwrap();
+        assert_eq!(take(&mut max7219), [(0x0C, 0)]);
+    }
+}
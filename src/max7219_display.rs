@@ -0,0 +1,53 @@
+use anyhow::Result;
+use esp_idf_svc::hal::{
+    gpio::{AnyIOPin, OutputPin},
+    peripheral::Peripheral,
+    spi::{config::{Config as SpiConfig, DriverConfig}, SpiAnyPins, SpiDeviceDriver, SpiDriver},
+    units::FromValueType,
+};
+use crate::font::{self, Align};
+use crate::hal::Display;
+use crate::max7219::{self, Max7219};
+
+impl max7219::Bus for SpiDeviceDriver<'_, SpiDriver<'_>> {
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        Ok(SpiDeviceDriver::write(self, bytes)?)
+    }
+}
+
+/// A MAX7219 module on its own SPI device.
+pub struct Max7219Display<'d> {
+    max7219: Max7219<SpiDeviceDriver<'d, SpiDriver<'d>>>,
+}
+
+impl<'d> Max7219Display<'d> {
+    pub fn new<SPI: SpiAnyPins>(
+        spi: impl Peripheral<P = SPI> + 'd,
+        sclk: impl Peripheral<P = impl OutputPin> + 'd,
+        mosi: impl Peripheral<P = impl OutputPin> + 'd,
+        cs: impl Peripheral<P = impl OutputPin> + 'd,
+        digits: usize,
+    ) -> Result<Self> {
+        let device = SpiDeviceDriver::new_single(
+            spi,
+            sclk,
+            mosi,
+            Option::<AnyIOPin>::None,
+            Some(cs),
+            &DriverConfig::new(),
+            &SpiConfig::new().baudrate(1.MHz().into()),
+        )?;
+        Ok(Self { max7219: Max7219::new(device, digits)? })
+    }
+}
+
+impl Display for Max7219Display<'_> {
+    /// Wider modules show the app's four digits at the right hand end.
+    fn set_segments(&mut self, segments: [u8; 4]) -> Result<()> {
+        self.max7219.write_segments(&font::place(&segments, self.max7219.digits(), Align::Right))
+    }
+
+    fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+        self.max7219.set_brightness(brightness)
+    }
+}
@@ -0,0 +1,210 @@
+//! Mixes everything the speaker plays into one stream at the master volume.
+//!
+//! awedio's manager mixes by adding samples and saturating, so a beep over the running hum
+//! clips hard. `Mixer` takes that job over as the one sound the manager plays: it sums in 32
+//! bits, scales by the master volume and then limits, turning the gain down just far enough
+//! to keep every sample under `CEILING` and easing it back up afterwards. The app talks to it
+//! through a `MixerControl`, and the sound thread only takes their shared lock at the start
+//! of each batch.
+
+use awedio::{NextSample, Sound};
+use std::sync::{Arc, Mutex};
+
+/// The loudest sample the limiter lets through, leaving a little headroom.
+const CEILING: f32 = i16::MAX as f32 * 0.9;
+/// Roughly how long the limiter takes to recover after a peak.
+const RELEASE_MS: f32 = 50.0;
+
+struct Shared {
+    /// Sounds played since the last batch, waiting to join the mix.
+    pending: Vec<Box<dyn Sound>>,
+    /// Whether the sounds already mixing should be dropped before `pending` joins them.
+    cleared: bool,
+    volume: f32,
+}
+
+pub struct Mixer {
+    channels: u16,
+    sample_rate: u32,
+    shared: Arc<Mutex<Shared>>,
+    sounds: Vec<Box<dyn Sound>>,
+    volume: f32,
+    /// The limiter's current gain, at most 1.0.
+    gain: f32,
+    /// How much of the way back to full gain the limiter goes each sample.
+    release: f32,
+}
+
+impl Mixer {
+    /// A mix of sounds in the given format, at full volume, and the control for it.
+    pub fn new(channels: u16, sample_rate: u32) -> (Mixer, MixerControl) {
+        let shared = Arc::new(Mutex::new(Shared {
+            pending: Vec::new(),
+            cleared: false,
+            volume: 1.0,
+        }));
+        let release_samples = RELEASE_MS / 1000.0 * sample_rate as f32 * channels as f32;
+        let mixer = Mixer {
+            channels,
+            sample_rate,
+            shared: shared.clone(),
+            sounds: Vec::new(),
+            volume: 1.0,
+            gain: 1.0,
+            release: 1.0 - (-1.0 / release_samples).exp(),
+        };
+        let control = MixerControl { channels, sample_rate, shared };
+        (mixer, control)
+    }
+
+    /// The next sample of `sound`, or `None` once it's finished or can't be mixed any more.
+    fn next_from(sound: &mut Box<dyn Sound>, channels: u16, sample_rate: u32) -> Option<i16> {
+        loop {
+            match sound.next_sample() {
+                Ok(NextSample::Sample(sample)) => return Some(sample),
+                Ok(NextSample::Paused) => return Some(0),
+                Ok(NextSample::MetadataChanged) => {
+                    if sound.channel_count() != channels || sound.sample_rate() != sample_rate {
+                        log::warn!("Dropping a sound that changed format while playing");
+                        return None;
+                    }
+                },
+                Ok(NextSample::Finished) => return None,
+                Err(err) => {
+                    log::warn!("Dropping a sound that failed: {:?}", err);
+                    return None;
+                },
+            }
+        }
+    }
+}
+
+impl Sound for Mixer {
+    fn channel_count(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn next_sample(&mut self) -> Result<NextSample, awedio::Error> {
+        if self.sounds.is_empty() {
+            return Ok(NextSample::Paused);
+        }
+        let (channels, sample_rate) = (self.channels, self.sample_rate);
+        let mut sum = 0i32;
+        self.sounds.retain_mut(|sound| match Self::next_from(sound, channels, sample_rate) {
+            Some(sample) => {
+                sum += sample as i32;
+                true
+            },
+            None => false,
+        });
+        let level = sum as f32 * self.volume;
+        // Duck at once so nothing gets over the ceiling, then ease back up.
+        let recovered = self.gain + (1.0 - self.gain) * self.release;
+        self.gain = recovered.min(CEILING / level.abs().max(1.0));
+        Ok(NextSample::Sample((level * self.gain) as i16))
+    }
+
+    fn on_start_of_batch(&mut self) {
+        {
+            let mut shared = self.shared.lock().unwrap();
+            if shared.cleared {
+                self.sounds.clear();
+                shared.cleared = false;
+            }
+            self.sounds.append(&mut shared.pending);
+            self.volume = shared.volume;
+        }
+        for sound in &mut self.sounds {
+            sound.on_start_of_batch();
+        }
+    }
+}
+
+/// Adds sounds to a `Mixer` and sets its volume from outside the sound thread.
+#[derive(Clone)]
+pub struct MixerControl {
+    channels: u16,
+    sample_rate: u32,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl MixerControl {
+    /// Adds `sound` to the mix from the next batch. There's no resampling, so a sound that
+    /// isn't in the mix's format is left out. Sound packs are checked when they're loaded, so
+    /// this is only a backstop, and a missing beep isn't worth stopping the app for.
+    pub fn play(&self, sound: Box<dyn Sound>) {
+        if sound.channel_count() != self.channels || sound.sample_rate() != self.sample_rate {
+            log::warn!(
+                "Not playing a {} channel {} Hz sound in a {} channel {} Hz mix",
+                sound.channel_count(),
+                sound.sample_rate(),
+                self.channels,
+                self.sample_rate,
+            );
+            return;
+        }
+        self.shared.lock().unwrap().pending.push(sound);
+    }
+
+    /// Stops everything playing, or about to.
+    pub fn clear(&self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.pending.clear();
+        shared.cleared = true;
+    }
+
+    /// From 0.0 for silence to 1.0 for the sounds' own level.
+    pub fn set_volume(&self, volume: f32) {
+        self.shared.lock().unwrap().volume = volume.clamp(0.0, 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use awedio::sounds::MemorySound;
+
+    fn sound(samples: &[i16], channels: u16, sample_rate: u32) -> Box<dyn Sound> {
+        Box::new(MemorySound::from_samples(Arc::new(samples.to_vec()), channels, sample_rate))
+    }
+
+    fn samples(mixer: &mut Mixer, count: usize) -> Vec<Option<i16>> {
+        mixer.on_start_of_batch();
+        (0..count)
+            .map(|_| match mixer.next_sample().unwrap() {
+                NextSample::Sample(sample) => Some(sample),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sounds_in_another_format_are_left_out() {
+        let (mut mixer, control) = Mixer::new(1, 16000);
+        control.play(sound(&[100, 100], 2, 16000));
+        control.play(sound(&[100], 1, 44100));
+        assert_eq!(samples(&mut mixer, 1), [None]);
+
+        control.play(sound(&[100, 200], 2, 16000));
+        control.play(sound(&[300, 400], 1, 16000));
+        assert_eq!(samples(&mut mixer, 2), [Some(300), Some(400)]);
+    }
+
+    #[test]
+    fn sounds_are_summed_and_limited() {
+        let (mut mixer, control) = Mixer::new(1, 16000);
+        control.play(sound(&[100, 20000], 1, 16000));
+        control.play(sound(&[200, 20000, 5], 1, 16000));
+        let mixed = samples(&mut mixer, 3);
+        assert_eq!(mixed[0], Some(300));
+        assert!(mixed[1].unwrap() as f32 <= CEILING);
+        assert!(mixed[2].unwrap() < 5);
+
+        control.clear();
+        assert_eq!(samples(&mut mixer, 1), [None]);
+    }
+}
@@ -0,0 +1,269 @@
+//! Encoding and decoding of the NEC infrared protocol used by the light puck remote.
+//!
+//! A frame is a 9 ms header mark and 4.5 ms space, followed by 32 bits sent least significant
+//! bit first: address, inverted address, command, inverted command. Extended NEC replaces the
+//! first two bytes with a 16-bit address. Each bit is a 562 µs mark followed by a 562 µs space
+//! for a zero or a 1687 µs space for a one, and the frame ends with a final mark.
+
+use std::fmt;
+
+pub const HEADER_MARK: u16 = 9000;
+pub const HEADER_SPACE: u16 = 4500;
+pub const REPEAT_SPACE: u16 = 2250;
+pub const BIT_MARK: u16 = 562;
+pub const ZERO_SPACE: u16 = 562;
+pub const ONE_SPACE: u16 = 1687;
+
+/// How far a measured timing may stray from nominal, as a percentage.
+const TOLERANCE_PERCENT: u32 = 25;
+
+/// The light puck remote's buttons, decoded from logic analyzer captures of it.
+pub const LIGHT_ON: Frame = Frame::new(0x00, 0x45);
+pub const LIGHT_OFF: Frame = Frame::new(0x00, 0x47);
+
+/// A mark (carrier on) or space (carrier off), in microseconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pulse {
+    pub mark: bool,
+    pub micros: u16,
+}
+
+impl Pulse {
+    pub const fn mark(micros: u16) -> Self {
+        Self { mark: true, micros }
+    }
+
+    pub const fn space(micros: u16) -> Self {
+        Self { mark: false, micros }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Address {
+    Standard(u8),
+    Extended(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub address: Address,
+    pub command: u8,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum NecError {
+    /// The signal ended before a full frame was read.
+    TooShort,
+    BadHeader,
+    /// The header was followed by the 2.25 ms space of a repeat code rather than a frame.
+    Repeat,
+    /// Bit `n` didn't have a recognisable mark or space.
+    BadBit(usize),
+    BadStop,
+    /// The inverted command byte doesn't match the command.
+    CommandCheck,
+}
+
+impl fmt::Display for NecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NecError::TooShort => write!(f, "signal too short for an NEC frame"),
+            NecError::BadHeader => write!(f, "missing NEC header"),
+            NecError::Repeat => write!(f, "NEC repeat code"),
+            NecError::BadBit(n) => write!(f, "bit {} has invalid timing", n),
+            NecError::BadStop => write!(f, "missing stop mark"),
+            NecError::CommandCheck => write!(f, "inverted command doesn't match command"),
+        }
+    }
+}
+
+impl std::error::Error for NecError {}
+
+impl Frame {
+    pub const fn new(address: u8, command: u8) -> Self {
+        Self { address: Address::Standard(address), command }
+    }
+
+    pub const fn extended(address: u16, command: u8) -> Self {
+        Self { address: Address::Extended(address), command }
+    }
+
+    /// The 32 bits of the frame in transmission order, least significant first.
+    pub fn to_bits(self) -> u32 {
+        let address = match self.address {
+            Address::Standard(address) => (address as u32) | ((!address as u32) << 8),
+            Address::Extended(address) => address as u32,
+        };
+        address | ((self.command as u32) << 16) | ((!self.command as u32) << 24)
+    }
+
+    pub fn encode(self) -> Vec<Pulse> {
+        let bits = self.to_bits();
+        let mut pulses = Vec::with_capacity(2 + 32 * 2 + 1);
+        pulses.push(Pulse::mark(HEADER_MARK));
+        pulses.push(Pulse::space(HEADER_SPACE));
+        for i in 0..32 {
+            pulses.push(Pulse::mark(BIT_MARK));
+            if bits & (1 << i) != 0 {
+                pulses.push(Pulse::space(ONE_SPACE));
+            } else {
+                pulses.push(Pulse::space(ZERO_SPACE));
+            }
+        }
+        pulses.push(Pulse::mark(BIT_MARK));
+        pulses
+    }
+
+    /// Decodes a single frame from the start of `pulses`. A standard address is reported when
+    /// the second byte is the inverse of the first, otherwise the address is extended.
+    pub fn decode(pulses: &[Pulse]) -> Result<Self, NecError> {
+        let header = pulses.get(0..2).ok_or(NecError::TooShort)?;
+        if !is_mark(header[0], HEADER_MARK) {
+            return Err(NecError::BadHeader);
+        }
+        if is_space(header[1], REPEAT_SPACE) {
+            return Err(NecError::Repeat);
+        }
+        if !is_space(header[1], HEADER_SPACE) {
+            return Err(NecError::BadHeader);
+        }
+
+        let mut bits = 0u32;
+        for i in 0..32 {
+            let bit = pulses.get(2 + i * 2..4 + i * 2).ok_or(NecError::TooShort)?;
+            if !is_mark(bit[0], BIT_MARK) {
+                return Err(NecError::BadBit(i));
+            }
+            if is_space(bit[1], ONE_SPACE) {
+                bits |= 1 << i;
+            } else if !is_space(bit[1], ZERO_SPACE) {
+                return Err(NecError::BadBit(i));
+            }
+        }
+        let stop = pulses.get(66).ok_or(NecError::TooShort)?;
+        if !is_mark(*stop, BIT_MARK) {
+            return Err(NecError::BadStop);
+        }
+        Self::from_bits(bits)
+    }
+
+    pub fn from_bits(bits: u32) -> Result<Self, NecError> {
+        let command = (bits >> 16) as u8;
+        if (bits >> 24) as u8 != !command {
+            return Err(NecError::CommandCheck);
+        }
+        let low = bits as u8;
+        let high = (bits >> 8) as u8;
+        let address = if high == !low {
+            Address::Standard(low)
+        } else {
+            Address::Extended(bits as u16)
+        };
+        Ok(Self { address, command })
+    }
+}
+
+/// Whether a measured timing is close enough to `nominal` to count as it.
+pub fn within_tolerance(micros: u16, nominal: u16) -> bool {
+    let difference = (micros as i32 - nominal as i32).unsigned_abs();
+    difference * 100 <= nominal as u32 * TOLERANCE_PERCENT
+}
+
+fn is_mark(pulse: Pulse, nominal: u16) -> bool {
+    pulse.mark && within_tolerance(pulse.micros, nominal)
+}
+
+fn is_space(pulse: Pulse, nominal: u16) -> bool {
+    !pulse.mark && within_tolerance(pulse.micros, nominal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Logic analyzer captures of the light puck's remote, in microseconds, positive for marks
+    // and negative for spaces.
+    const LIGHT_ON_CAPTURE: [i32; 67] = [
+        9201, -4497, 587, -580, 586, -582, 613, -555, 587, -581,
+        588, -579, 587, -583, 614, -555, 586, -582, 614, -1634,
+        588, -1659, 615, -1632, 589, -1657, 615, -1634, 616, -1630,
+        617, -1631, 616, -1631, 614, -1634, 613, -556, 611, -1636,
+        614, -553, 616, -553, 615, -554, 615, -1633, 614, -554,
+        614, -555, 615, -1632, 612, -556, 615, -1632, 612, -1635,
+        616, -1631, 611, -557, 614, -1633, 614,
+    ];
+
+    const LIGHT_OFF_CAPTURE: [i32; 67] = [
+        9204, -4512, 578, -591, 579, -592, 579, -591, 580, -589,
+        582, -590, 580, -591, 580, -592, 579, -591, 582, -1669,
+        579, -1669, 581, -1669, 581, -1669, 580, -1669, 580, -1670,
+        580, -1669, 579, -1671, 579, -1669, 580, -1669, 580, -1669,
+        581, -591, 580, -592, 578, -592, 579, -1670, 580, -592,
+        579, -590, 580, -592, 578, -593, 577, -1672, 577, -1672,
+        578, -1673, 577, -592, 578, -1673, 577,
+    ];
+
+    fn pulses(capture: &[i32]) -> Vec<Pulse> {
+        capture.iter()
+            .map(|micros| if *micros > 0 { Pulse::mark(*micros as u16) } else { Pulse::space(-micros as u16) })
+            .collect()
+    }
+
+    #[test]
+    fn captures_decode_to_the_light_codes() {
+        assert_eq!(Frame::decode(&pulses(&LIGHT_ON_CAPTURE)), Ok(LIGHT_ON));
+        assert_eq!(Frame::decode(&pulses(&LIGHT_OFF_CAPTURE)), Ok(LIGHT_OFF));
+    }
+
+    #[test]
+    fn encoded_frames_match_the_captures() {
+        for (frame, capture) in [(LIGHT_ON, &LIGHT_ON_CAPTURE), (LIGHT_OFF, &LIGHT_OFF_CAPTURE)] {
+            let encoded = frame.encode();
+            let captured = pulses(capture);
+            assert_eq!(encoded.len(), captured.len());
+            for (i, (sent, seen)) in encoded.iter().zip(&captured).enumerate() {
+                assert_eq!(sent.mark, seen.mark, "pulse {}", i);
+                assert!(within_tolerance(seen.micros, sent.micros), "pulse {}: {} vs {}", i, seen.micros, sent.micros);
+            }
+        }
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        for frame in [Frame::new(0x00, 0x00), Frame::new(0xA5, 0x5A), Frame::extended(0x1234, 0xFF), LIGHT_ON] {
+            assert_eq!(Frame::decode(&frame.encode()), Ok(frame));
+            assert_eq!(Frame::from_bits(frame.to_bits()), Ok(frame));
+        }
+    }
+
+    #[test]
+    fn timings_are_checked() {
+        assert!(within_tolerance(11250, 9000));
+        assert!(!within_tolerance(11340, 9000));
+
+        let mut capture = pulses(&LIGHT_ON_CAPTURE);
+        capture[1] = Pulse::space(REPEAT_SPACE);
+        assert_eq!(Frame::decode(&capture[..3]), Err(NecError::Repeat));
+
+        let mut capture = pulses(&LIGHT_ON_CAPTURE);
+        capture[0] = Pulse::mark(4500);
+        assert_eq!(Frame::decode(&capture), Err(NecError::BadHeader));
+
+        let mut capture = pulses(&LIGHT_ON_CAPTURE);
+        capture[2 + 5 * 2 + 1] = Pulse::space(1100);
+        assert_eq!(Frame::decode(&capture), Err(NecError::BadBit(5)));
+
+        let capture = pulses(&LIGHT_ON_CAPTURE);
+        assert_eq!(Frame::decode(&capture[..66]), Err(NecError::TooShort));
+    }
+
+    #[test]
+    fn a_bad_inverted_command_is_rejected() {
+        // Flip the first bit of the inverted command.
+        let mut pulses = LIGHT_ON.encode();
+        let space = &mut pulses[3 + 24 * 2];
+        *space = Pulse::space(if space.micros == ONE_SPACE { ZERO_SPACE } else { ONE_SPACE });
+        assert_eq!(Frame::decode(&pulses), Err(NecError::CommandCheck));
+        assert_eq!(Frame::from_bits(LIGHT_ON.to_bits() ^ (1 << 24)), Err(NecError::CommandCheck));
+    }
+}
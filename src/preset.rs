@@ -0,0 +1,123 @@
+//! One-touch presets, started by holding 1-4 on the keypad. Each is stored as a single `u32`
+//! setting so they survive power loss once they've been edited.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dish {
+    Popcorn,
+    Beverage,
+    Potato,
+    Pizza,
+}
+
+/// What plays as a preset starts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PresetSound {
+    Start,
+    Chime,
+    Beep,
+    Silent,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Preset {
+    pub seconds: u16,
+    pub power: u8,
+    pub sound: PresetSound,
+}
+
+impl Dish {
+    /// The dish on keys 1-4.
+    pub fn from_key(digit: u8) -> Option<Self> {
+        match digit {
+            1 => Some(Dish::Popcorn),
+            2 => Some(Dish::Beverage),
+            3 => Some(Dish::Potato),
+            4 => Some(Dish::Pizza),
+            _ => None,
+        }
+    }
+
+    pub fn setting_key(self) -> &'static str {
+        match self {
+            Dish::Popcorn => "preset_popcorn",
+            Dish::Beverage => "preset_beverage",
+            Dish::Potato => "preset_potato",
+            Dish::Pizza => "preset_pizza",
+        }
+    }
+
+    /// The preset until it's been edited.
+    pub fn default_preset(self) -> Preset {
+        let (seconds, power) = match self {
+            Dish::Popcorn => (150, 10),
+            Dish::Beverage => (60, 10),
+            Dish::Potato => (300, 10),
+            Dish::Pizza => (90, 7),
+        };
+        Preset { seconds, power, sound: PresetSound::Start }
+    }
+}
+
+impl PresetSound {
+    /// The sound after this one, for cycling through them from the keypad.
+    pub fn next(self) -> Self {
+        match self {
+            PresetSound::Start => PresetSound::Chime,
+            PresetSound::Chime => PresetSound::Beep,
+            PresetSound::Beep => PresetSound::Silent,
+            PresetSound::Silent => PresetSound::Start,
+        }
+    }
+
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(PresetSound::Start),
+            1 => Some(PresetSound::Chime),
+            2 => Some(PresetSound::Beep),
+            3 => Some(PresetSound::Silent),
+            _ => None,
+        }
+    }
+}
+
+impl Preset {
+    /// Packs the preset as seconds in the low 16 bits, then power, then sound.
+    pub fn to_u32(self) -> u32 {
+        self.seconds as u32 | (self.power as u32) << 16 | (self.sound as u32) << 24
+    }
+
+    /// Returns `None` for values that weren't written by `to_u32`.
+    pub fn from_u32(value: u32) -> Option<Self> {
+        let seconds = value as u16;
+        let power = (value >> 16) as u8;
+        let sound = PresetSound::from_u8((value >> 24) as u8)?;
+        // Nothing longer than 99:59 fits on the display.
+        if seconds == 0 || seconds > 99 * 60 + 59 || power == 0 || power > 10 {
+            return None;
+        }
+        Some(Self { seconds, power, sound })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_survive_a_round_trip() {
+        let edited = Preset { seconds: 99 * 60 + 59, power: 3, sound: PresetSound::Silent };
+        for preset in [Dish::Popcorn.default_preset(), Dish::Pizza.default_preset(), edited] {
+            assert_eq!(Preset::from_u32(preset.to_u32()), Some(preset));
+        }
+    }
+
+    #[test]
+    fn values_that_cant_be_cooked_are_rejected() {
+        let preset = Dish::Potato.default_preset();
+        assert_eq!(Preset::from_u32(0), None);
+        assert_eq!(Preset::from_u32(Preset { seconds: 100 * 60, ..preset }.to_u32()), None);
+        assert_eq!(Preset::from_u32(Preset { seconds: u16::MAX, ..preset }.to_u32()), None);
+        assert_eq!(Preset::from_u32(Preset { power: 11, ..preset }.to_u32()), None);
+        assert_eq!(Preset::from_u32(preset.to_u32() | 4 << 24), None);
+    }
+}
@@ -0,0 +1,10 @@
+/// One step of a cook program. `App` works through these in order, so a program can mix
+/// cooking at different powers with standing time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Cook{seconds: u16, power: u8},
+    /// Light off and quiet while the heat evens out.
+    Stand{seconds: u16},
+    /// Beep so the food gets turned over, then carry on.
+    TurnOver,
+}
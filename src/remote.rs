@@ -1,195 +1,176 @@
 use anyhow::Result;
 use esp_idf_svc::hal::{
-    gpio::OutputPin,
+    gpio::{InputPin, OutputPin},
     rmt::{
         config::{
             CarrierConfig,
             DutyPercent,
             Loop,
+            ReceiveConfig,
             TransmitConfig,
         },
         PinState,
         PulseTicks,
         Pulse,
+        Receive,
         RmtChannel,
+        RxRmtDriver,
         TxRmtDriver,
         VariableLengthSignal, RmtTransmitConfig,
     },
     peripheral::Peripheral,
     units::FromValueType,
 };
-use esp_idf_svc::sys::EspError;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::{configTICK_RATE_HZ, EspError};
+use std::time::{Duration, Instant};
+use crate::hal::{Light, LightCode};
+use crate::nec;
+
+const NVS_NAMESPACE: &str = "remote";
+
+// A gap this long (in 1 µs ticks) ends a received frame. It must be longer than the 9 ms header.
+const RX_IDLE_THRESHOLD: u16 = 12000;
 
 pub struct Remote<'d> {
     tx: TxRmtDriver<'d>,
+    rx: RxRmtDriver<'d>,
+    nvs: EspNvs<NvsDefault>,
+    codes: [Option<nec::Frame>; 4],
 }
 
 impl<'d> Remote<'d> {
     pub fn new(
         channel: impl Peripheral<P = impl RmtChannel> + 'd,
         led: impl Peripheral<P = impl OutputPin> + 'd,
+        rx_channel: impl Peripheral<P = impl RmtChannel> + 'd,
+        receiver: impl Peripheral<P = impl InputPin> + 'd,
+        nvs_partition: EspDefaultNvsPartition,
     ) -> Result<Self> {
         let carrier = CarrierConfig::new()
             .duty_percent(DutyPercent::new(50)?)
             .frequency(37000.Hz());
         let mut config = TransmitConfig::new()
             .carrier(Some(carrier));
+        let rx_config = ReceiveConfig::new()
+            .idle_threshold(RX_IDLE_THRESHOLD);
+        let nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+
+        // Anything learned replaces the built-in codes.
+        let mut codes = [Some(nec::LIGHT_ON), Some(nec::LIGHT_OFF), None, None];
+        for code in [LightCode::On, LightCode::Off, LightCode::Brighter, LightCode::Dimmer] {
+            if let Some(bits) = nvs.get_u32(nvs_key(code))? {
+                codes[code as usize] = nec::Frame::from_bits(bits).ok();
+            }
+        }
+
         Ok(Self {
             tx: TxRmtDriver::new(
                 channel,
                 led,
                 &mut config,
             )?,
+            rx: RxRmtDriver::new(
+                rx_channel,
+                receiver,
+                &rx_config,
+                1000,
+            )?,
+            nvs,
+            codes,
         })
     }
 
-    pub fn send_on(&mut self) -> Result<(), EspError> {
+    /// Transmits a single NEC frame. The RMT runs at the default 1 MHz tick, so NEC timings in
+    /// microseconds can be used as ticks directly.
+    pub fn send(&mut self, frame: nec::Frame) -> Result<(), EspError> {
+        let pulses = frame.encode().iter()
+            .map(|pulse| if pulse.mark { high_pulse(pulse.micros) } else { low_pulse(pulse.micros) })
+            .collect::<Result<Vec<_>, _>>()?;
         let mut signal = VariableLengthSignal::new();
-        signal.push([
-            &high_pulse(9201)?,
-            &low_pulse(4497)?,
-            &high_pulse(587)?,
-            &low_pulse(580)?,
-            &high_pulse(586)?,
-            &low_pulse(582)?,
-            &high_pulse(613)?,
-            &low_pulse(555)?,
-            &high_pulse(587)?,
-            &low_pulse(581)?,
-            &high_pulse(588)?,
-            &low_pulse(579)?,
-            &high_pulse(587)?,
-            &low_pulse(583)?,
-            &high_pulse(614)?,
-            &low_pulse(555)?,
-            &high_pulse(586)?,
-            &low_pulse(582)?,
-            &high_pulse(614)?,
-            &low_pulse(1634)?,
-            &high_pulse(588)?,
-            &low_pulse(1659)?,
-            &high_pulse(615)?,
-            &low_pulse(1632)?,
-            &high_pulse(589)?,
-            &low_pulse(1657)?,
-            &high_pulse(615)?,
-            &low_pulse(1634)?,
-            &high_pulse(616)?,
-            &low_pulse(1630)?,
-            &high_pulse(617)?,
-            &low_pulse(1631)?,
-            &high_pulse(616)?,
-            &low_pulse(1631)?,
-            &high_pulse(614)?,
-            &low_pulse(1634)?,
-            &high_pulse(613)?,
-            &low_pulse(556)?,
-            &high_pulse(611)?,
-            &low_pulse(1636)?,
-            &high_pulse(614)?,
-            &low_pulse(553)?,
-            &high_pulse(616)?,
-            &low_pulse(553)?,
-            &high_pulse(615)?,
-            &low_pulse(554)?,
-            &high_pulse(615)?,
-            &low_pulse(1633)?,
-            &high_pulse(614)?,
-            &low_pulse(554)?,
-            &high_pulse(614)?,
-            &low_pulse(555)?,
-            &high_pulse(615)?,
-            &low_pulse(1632)?,
-            &high_pulse(612)?,
-            &low_pulse(556)?,
-            &high_pulse(615)?,
-            &low_pulse(1632)?,
-            &high_pulse(612)?,
-            &low_pulse(1635)?,
-            &high_pulse(616)?,
-            &low_pulse(1631)?,
-            &high_pulse(611)?,
-            &low_pulse(557)?,
-            &high_pulse(614)?,
-            &low_pulse(1633)?,
-            &high_pulse(614)?,
-        ])?;
+        signal.push(pulses.iter())?;
         self.tx.start(signal)
     }
 
+    /// Transmits the code stored for `code`, if there is one.
+    pub fn send_code(&mut self, code: LightCode) -> Result<(), EspError> {
+        match self.codes[code as usize] {
+            Some(frame) => self.send(frame),
+            None => {
+                log::warn!("No IR code learned for {:?}", code);
+                Ok(())
+            },
+        }
+    }
+
+    pub fn send_on(&mut self) -> Result<(), EspError> {
+        self.send_code(LightCode::On)
+    }
+
     pub fn send_off(&mut self) -> Result<(), EspError> {
-        let mut signal = VariableLengthSignal::new();
-        signal.push([
-            &high_pulse(9204)?,
-            &low_pulse(4512)?,
-            &high_pulse(578)?,
-            &low_pulse(591)?,
-            &high_pulse(579)?,
-            &low_pulse(592)?,
-            &high_pulse(579)?,
-            &low_pulse(591)?,
-            &high_pulse(580)?,
-            &low_pulse(589)?,
-            &high_pulse(582)?,
-            &low_pulse(590)?,
-            &high_pulse(580)?,
-            &low_pulse(591)?,
-            &high_pulse(580)?,
-            &low_pulse(592)?,
-            &high_pulse(579)?,
-            &low_pulse(591)?,
-            &high_pulse(582)?,
-            &low_pulse(1669)?,
-            &high_pulse(579)?,
-            &low_pulse(1669)?,
-            &high_pulse(581)?,
-            &low_pulse(1669)?,
-            &high_pulse(581)?,
-            &low_pulse(1669)?,
-            &high_pulse(580)?,
-            &low_pulse(1669)?,
-            &high_pulse(580)?,
-            &low_pulse(1670)?,
-            &high_pulse(580)?,
-            &low_pulse(1669)?,
-            &high_pulse(579)?,
-            &low_pulse(1671)?,
-            &high_pulse(579)?,
-            &low_pulse(1669)?,
-            &high_pulse(580)?,
-            &low_pulse(1669)?,
-            &high_pulse(580)?,
-            &low_pulse(1669)?,
-            &high_pulse(581)?,
-            &low_pulse(591)?,
-            &high_pulse(580)?,
-            &low_pulse(592)?,
-            &high_pulse(578)?,
-            &low_pulse(592)?,
-            &high_pulse(579)?,
-            &low_pulse(1670)?,
-            &high_pulse(580)?,
-            &low_pulse(592)?,
-            &high_pulse(579)?,
-            &low_pulse(590)?,
-            &high_pulse(580)?,
-            &low_pulse(592)?,
-            &high_pulse(578)?,
-            &low_pulse(593)?,
-            &high_pulse(577)?,
-            &low_pulse(1672)?,
-            &high_pulse(577)?,
-            &low_pulse(1672)?,
-            &high_pulse(578)?,
-            &low_pulse(1673)?,
-            &high_pulse(577)?,
-            &low_pulse(592)?,
-            &high_pulse(578)?,
-            &low_pulse(1673)?,
-            &high_pulse(577)?,
-        ])?;
-        self.tx.start(signal)
+        self.send_code(LightCode::Off)
     }
+
+    /// Listens on the IR receiver until an NEC frame arrives or `timeout_ms` passes, then
+    /// stores it in NVS under `code`. Repeat codes and noise are ignored.
+    pub fn learn(&mut self, code: LightCode, timeout_ms: u32) -> Result<bool> {
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms as u64);
+        let mut buffer = [(Pulse::zero(), Pulse::zero()); 64];
+        self.rx.start()?;
+        let learned = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break None;
+            }
+            let ticks = (remaining.as_millis() as u32 * configTICK_RATE_HZ / 1000).max(1);
+            if let Receive::Read(len) = self.rx.receive(&mut buffer, ticks)? {
+                if let Ok(frame) = nec::Frame::decode(&received_pulses(&buffer[..len])) {
+                    break Some(frame);
+                }
+            }
+        };
+        self.rx.stop()?;
+
+        let Some(frame) = learned else {
+            return Ok(false);
+        };
+        log::info!("Learned {:?} as {:?}", code, frame);
+        self.nvs.set_u32(nvs_key(code), frame.to_bits())?;
+        self.codes[code as usize] = Some(frame);
+        Ok(true)
+    }
+}
+
+impl Light for Remote<'_> {
+    fn send(&mut self, code: LightCode) -> Result<()> {
+        Ok(Remote::send_code(self, code)?)
+    }
+
+    fn learn(&mut self, code: LightCode, timeout_ms: u32) -> Result<bool> {
+        Remote::learn(self, code, timeout_ms)
+    }
+}
+
+fn nvs_key(code: LightCode) -> &'static str {
+    match code {
+        LightCode::On => "on",
+        LightCode::Off => "off",
+        LightCode::Brighter => "brighter",
+        LightCode::Dimmer => "dimmer",
+    }
+}
+
+/// The IR receiver module demodulates the carrier and pulls its output low while it sees one,
+/// so a low level is a mark. Zero length pulses pad out the end of the RMT items.
+fn received_pulses(items: &[(Pulse, Pulse)]) -> Vec<nec::Pulse> {
+    items.iter()
+        .flat_map(|(first, second)| [first, second])
+        .filter(|pulse| pulse.ticks.ticks() > 0)
+        .map(|pulse| nec::Pulse {
+            mark: pulse.pin_state == PinState::Low,
+            micros: pulse.ticks.ticks(),
+        })
+        .collect()
 }
 
 fn high_pulse(ticks: u16) -> Result<Pulse, EspError> {
@@ -1,21 +1,76 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use esp_idf_svc::hal::{
     gpio::*,
     peripheral::Peripheral,
-    delay::Ets,
+    delay::{Ets, FreeRtos},
 };
-use esp_idf_svc::sys::EspError;
+use crate::font::{self, Align, Marquee};
+use crate::hal::Display;
+use crate::tm1637::{self, Bus, ScanKey, Tm1637, Tm1637Error};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A summary of transfer times is logged after this many transfers.
+const LOG_EVERY_TRANSFERS: u32 = 100;
+
+struct Pins {
+    clk: PinDriver<'static, AnyIOPin, InputOutput>,
+    dio: PinDriver<'static, AnyIOPin, InputOutput>,
+}
+
+impl Bus for Pins {
+    fn set_clk(&mut self, high: bool) -> Result<()> {
+        Ok(self.clk.set_level(high.into())?)
+    }
+
+    fn set_dio(&mut self, high: bool) -> Result<()> {
+        Ok(self.dio.set_level(high.into())?)
+    }
+
+    fn dio_is_high(&self) -> bool {
+        self.dio.get_level() == Level::High
+    }
+
+    fn delay(&mut self) {
+        Ets::delay_us(100u32);
+    }
+}
 
-pub struct SevenSegment<'a> {
-    clk: PinDriver<'a, AnyIOPin, InputOutput>,
-    dio: PinDriver<'a, AnyIOPin, InputOutput>,
+enum Request {
+    Segments(Vec<u8>),
+    Brightness(u8),
+    On(bool),
+    ReadKey(SyncSender<Result<Option<ScanKey>>>),
+    Flush(SyncSender<()>),
 }
 
-impl<'d> SevenSegment<'d> {
+/// A TM1637 display module on two GPIOs.
+///
+/// Bit-banging a frame takes milliseconds, so the module is driven by its own thread and
+/// updates are queued for it without waiting. The driver only sends what changed, and
+/// segments that are overtaken by newer ones before the thread gets to them are skipped.
+pub struct SevenSegment {
+    requests: Sender<Request>,
+    digits: usize,
+}
+
+impl SevenSegment {
+    /// A four digit module.
     pub fn new(
-        clk: impl Peripheral<P = impl IOPin> + 'd,
-        dio: impl Peripheral<P = impl IOPin> + 'd,
-    ) -> Result<Self, EspError> {
+        clk: impl Peripheral<P = impl IOPin> + 'static,
+        dio: impl Peripheral<P = impl IOPin> + 'static,
+    ) -> Result<Self> {
+        Self::with_digits(clk, dio, tm1637::FOUR_DIGITS)
+    }
+
+    /// A module with its digits wired to the TM1637 grids in the order given, like
+    /// `tm1637::SIX_DIGITS`.
+    pub fn with_digits(
+        clk: impl Peripheral<P = impl IOPin> + 'static,
+        dio: impl Peripheral<P = impl IOPin> + 'static,
+        digits: &'static [usize],
+    ) -> Result<Self> {
         let clk = clk.into_ref();
         let dio = dio.into_ref();
         let mut clk_driver = PinDriver::input_output_od(clk.map_into::<AnyIOPin>())?;
@@ -25,84 +80,155 @@ impl<'d> SevenSegment<'d> {
         dio_driver.set_high()?;
         clk_driver.set_pull(Pull::Up)?;
         dio_driver.set_pull(Pull::Up)?;
-        Ok(Self {
-            clk: clk_driver,
-            dio: dio_driver,
-        })
+        let tm1637 = Tm1637::new(Pins { clk: clk_driver, dio: dio_driver }, digits);
+        let (requests, requests_rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("display".into())
+            .stack_size(4096)
+            .spawn(move || run(tm1637, requests_rx))?;
+        Ok(Self { requests, digits: digits.len() })
     }
 
-    pub fn start(&mut self) -> Result<()> {
-        self.dio.set_low()?;
-        self.bit_delay();
-        Ok(())
+    /// Queues segments for the digits from the left, blanking any past the end of `segments`.
+    pub fn set_segments(&mut self, segments: &[u8]) -> Result<()> {
+        if segments.len() > self.digits {
+            return Err(Tm1637Error::TooManyDigits(segments.len()).into());
+        }
+        self.send(Request::Segments(segments.to_vec()))
     }
 
-    pub fn stop(&mut self) -> Result<()> {
-        self.dio.set_low()?;
-        self.bit_delay();
-        self.clk.set_high()?;
-        self.bit_delay();
-        self.dio.set_high()?;
-        self.bit_delay();
+    /// Shows `text` across however many digits the module has. See `font::segments` for how
+    /// it's drawn.
+    pub fn show_text(&mut self, text: &str, align: Align) -> Result<()> {
+        let segments = font::place(&font::segments(text), self.digits, align);
+        self.set_segments(&segments)
+    }
 
+    /// Scrolls `text` through once, moving it along a digit every `step_ms`. This blocks until
+    /// it's done, the app scrolls a frame at a time from its own loops instead.
+    pub fn scroll_text(&mut self, text: &str, step_ms: u32) -> Result<()> {
+        match self.digits {
+            6 => self.scroll(&Marquee::<6>::new(text), step_ms),
+            _ => self.scroll(&Marquee::<4>::new(text), step_ms),
+        }
+    }
+
+    fn scroll<const N: usize>(&mut self, marquee: &Marquee<N>, step_ms: u32) -> Result<()> {
+        for frame in 0..marquee.frames() {
+            self.set_segments(&marquee.frame(frame))?;
+            FreeRtos::delay_ms(step_ms);
+        }
         Ok(())
     }
 
-    pub fn bit_delay(&self) {
-        Ets::delay_us(100u32);
+    /// One of eight levels, 0-7.
+    pub fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+        self.send(Request::Brightness(brightness))
     }
 
-    pub fn set_segments(&mut self, segments: [u8; 4]) -> Result<()> {
-        self.start()?;
-        self.write_byte(0x40)?;
-        self.stop()?;
+    pub fn set_on(&mut self, on: bool) -> Result<()> {
+        self.send(Request::On(on))
+    }
 
-        self.start()?;
-        self.write_byte(0xC0)?;
-        for segment in segments.iter() {
-            self.write_byte(*segment)?;
-        }
-        self.stop()?;
+    /// The key held on the module's own key scan inputs, for boards that wire some up. This
+    /// waits for any queued updates to go out first.
+    pub fn read_key(&mut self) -> Result<Option<ScanKey>> {
+        let (reply, result) = mpsc::sync_channel(1);
+        self.send(Request::ReadKey(reply))?;
+        result.recv().map_err(|_| anyhow!("display thread isn't running"))?
+    }
 
-        self.start()?;
-        self.write_byte(0x8F)?;
-        self.stop()?;
+    /// Waits for the queued updates to go out.
+    pub fn flush(&mut self) -> Result<()> {
+        let (reply, done) = mpsc::sync_channel(1);
+        self.send(Request::Flush(reply))?;
+        done.recv().map_err(|_| anyhow!("display thread isn't running"))
+    }
 
-        Ok(())
+    fn send(&self, request: Request) -> Result<()> {
+        self.requests.send(request).map_err(|_| anyhow!("display thread isn't running"))
     }
+}
 
-    fn write_byte(&mut self, data: u8) -> Result<()> {
-        let mut data = data;
-        for _ in 0..8 {
-            self.clk.set_low()?;
-            self.bit_delay();
-            
-            if data & 1 != 0 {
-                self.dio.set_high()?;
-            } else {
-                self.dio.set_low()?;
+fn run(mut tm1637: Tm1637<Pins>, requests: Receiver<Request>) {
+    let mut stats = TransferStats::default();
+    while let Ok(request) = requests.recv() {
+        let batch: Vec<Request> = Some(request).into_iter().chain(requests.try_iter()).collect();
+        let newest_segments = batch.iter().rposition(|request| matches!(request, Request::Segments(_)));
+        for (i, request) in batch.into_iter().enumerate() {
+            if matches!(request, Request::Segments(_)) && Some(i) != newest_segments {
+                continue;
             }
-            self.bit_delay();
-            self.clk.set_high()?;
-            self.bit_delay();
-            data >>= 1;
+            let started = Instant::now();
+            let bytes_before = tm1637.bytes_written();
+            let result = match request {
+                Request::Segments(segments) => tm1637.write_segments(&segments),
+                Request::Brightness(brightness) => tm1637.set_brightness(brightness),
+                Request::On(on) => tm1637.set_on(on),
+                Request::ReadKey(reply) => {
+                    let _ = reply.send(tm1637.read_key());
+                    Ok(())
+                },
+                Request::Flush(reply) => {
+                    let _ = reply.send(());
+                    Ok(())
+                },
+            };
+            if let Err(err) = result {
+                log::warn!("Display update failed: {:?}", err);
+            }
+            stats.record(tm1637.bytes_written() - bytes_before, started.elapsed());
         }
+    }
+}
 
-        self.clk.set_low()?;
-        self.dio.set_high()?;
-        self.bit_delay();
-
-        self.clk.set_high()?;
-        self.bit_delay();
+/// How long transfers to the TM1637 take, for keeping an eye on the bit-banging.
+#[derive(Default)]
+struct TransferStats {
+    transfers: u32,
+    bytes: u64,
+    total: Duration,
+    longest: Duration,
+}
 
-        if self.dio.get_level() == Level::Low {
-            self.dio.set_low()?;
+impl TransferStats {
+    fn record(&mut self, bytes: u64, took: Duration) {
+        // Updates that changed nothing never touched the bus.
+        if bytes == 0 {
+            return;
+        }
+        log::debug!("TM1637 sent {} bytes in {} us", bytes, took.as_micros());
+        self.transfers += 1;
+        self.bytes += bytes;
+        self.total += took;
+        self.longest = self.longest.max(took);
+        if self.transfers == LOG_EVERY_TRANSFERS {
+            log::info!(
+                "TM1637 sent {} transfers of {} bytes on average, taking {} us on average and {} us at most",
+                self.transfers,
+                self.bytes / self.transfers as u64,
+                self.total.as_micros() / self.transfers as u128,
+                self.longest.as_micros(),
+            );
+            *self = Self::default();
         }
-
-        self.bit_delay();
-        self.clk.set_low()?;
-        self.bit_delay();
-        Ok(())
     }
 }
 
+impl Display for SevenSegment {
+    fn set_segments(&mut self, segments: [u8; 4]) -> Result<()> {
+        SevenSegment::set_segments(self, &segments)
+    }
+
+    fn show_text(&mut self, text: &str, align: Align) -> Result<()> {
+        SevenSegment::show_text(self, text, align)
+    }
+
+    fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+        SevenSegment::set_brightness(self, brightness)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        SevenSegment::flush(self)
+    }
+}
@@ -0,0 +1,1272 @@
+use anyhow::Result;
+use awedio::Sound;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use crate::app::{App, Mode};
+use crate::font;
+use crate::key_queue::KeyQueue;
+use crate::keys::{Key, PHONE_3X4};
+use crate::nec;
+use crate::hal::{
+    AudioOut,
+    Board,
+    Clock,
+    Display,
+    Files,
+    InputLine,
+    KeyInput,
+    Light,
+    LightCode,
+    Power,
+    Settings,
+    WakeReason,
+    WallClock,
+};
+
+/// Something the simulated user can hold down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input {
+    Start,
+    Stop,
+    DoorOpen,
+    Key(Key),
+    /// A button on the light puck's own remote, pointed at the IR receiver.
+    Remote(nec::Frame),
+}
+
+/// Something the app did to the simulated hardware.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Display([u8; 4]),
+    Brightness(u8),
+    Sound,
+    SoundCleared,
+    Volume(f32),
+    /// The frame sent for a code, either built in or learned.
+    Light(LightCode, nec::Frame),
+    Learned(LightCode),
+    Sleep,
+}
+
+/// Returned by the virtual clock once the simulation has run out of time.
+#[derive(Debug)]
+pub struct SimulationEnded;
+
+impl fmt::Display for SimulationEnded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "simulation ended")
+    }
+}
+
+impl std::error::Error for SimulationEnded {}
+
+struct Hold {
+    input: Input,
+    from_ms: u64,
+    until_ms: u64,
+}
+
+struct State {
+    now_ms: u64,
+    end_ms: u64,
+    holds: Vec<Hold>,
+    events: Vec<(u64, Event)>,
+    wake_reason: WakeReason,
+    clock_offset_ms: Option<i64>,
+    settings: HashMap<String, u32>,
+    /// The frame for each `LightCode`, kept through restarts like the remote's NVS namespace.
+    light_codes: [Option<nec::Frame>; 4],
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            now_ms: 0,
+            end_ms: 0,
+            holds: Vec::new(),
+            events: Vec::new(),
+            wake_reason: WakeReason::PowerOn,
+            clock_offset_ms: None,
+            settings: HashMap::new(),
+            light_codes: [Some(nec::LIGHT_ON), Some(nec::LIGHT_OFF), None, None],
+        }
+    }
+}
+
+impl State {
+    /// Skips ahead to the next input that would wake the real board.
+    fn sleep_until_woken(&mut self) -> Result<()> {
+        let now_ms = self.now_ms;
+        let wake = self.holds.iter()
+            .filter(|hold| hold.from_ms >= now_ms)
+            .filter_map(|hold| {
+                let reason = match hold.input {
+                    Input::Start => WakeReason::StartButton,
+                    Input::DoorOpen => WakeReason::Door,
+                    // The right-hand keypad column isn't on an RTC GPIO.
+                    Input::Key(key) if PHONE_3X4.position(key).is_some_and(|(_, column)| column != 2) => {
+                        WakeReason::Keypad
+                    },
+                    _ => return None,
+                };
+                Some((hold.from_ms, reason))
+            })
+            .min_by_key(|(from_ms, _)| *from_ms);
+        match wake {
+            Some((from_ms, reason)) if from_ms <= self.end_ms => {
+                self.now_ms = from_ms;
+                self.wake_reason = reason;
+                Ok(())
+            },
+            _ => {
+                self.now_ms = self.end_ms;
+                Err(SimulationEnded.into())
+            },
+        }
+    }
+
+    fn is_held(&self, input: Input) -> bool {
+        self.is_held_at(input, self.now_ms)
+    }
+
+    fn is_held_at(&self, input: Input, at_ms: u64) -> bool {
+        self.holds.iter().any(|hold| hold.input == input && hold.from_ms <= at_ms && at_ms < hold.until_ms)
+    }
+
+    fn key_at(&self, at_ms: u64) -> Option<Key> {
+        PHONE_3X4.keys.iter().copied().find(|key| self.is_held_at(Input::Key(*key), at_ms))
+    }
+
+    fn record(&mut self, event: Event) {
+        self.events.push((self.now_ms, event));
+    }
+
+    fn advance(&mut self, ms: u64) -> Result<()> {
+        if self.now_ms + ms > self.end_ms {
+            self.now_ms = self.end_ms;
+            return Err(SimulationEnded.into());
+        }
+        self.now_ms += ms;
+        Ok(())
+    }
+}
+
+type Shared = Rc<RefCell<State>>;
+
+struct SimDisplay(Shared);
+
+impl Display for SimDisplay {
+    fn set_segments(&mut self, segments: [u8; 4]) -> Result<()> {
+        self.0.borrow_mut().record(Event::Display(segments));
+        Ok(())
+    }
+
+    fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+        self.0.borrow_mut().record(Event::Brightness(brightness));
+        Ok(())
+    }
+}
+
+/// Queues changes as the real keypad's scanning thread would, as they happen rather than
+/// when the app gets round to reading them.
+struct SimKeypad {
+    state: Shared,
+    queue: KeyQueue,
+    scanned_ms: u64,
+    scanned: Option<Key>,
+}
+
+impl SimKeypad {
+    fn new(state: Shared) -> Self {
+        let (now_ms, key) = {
+            let state = state.borrow();
+            (state.now_ms, state.key_at(state.now_ms))
+        };
+        Self { state, queue: KeyQueue::new(key), scanned_ms: now_ms, scanned: key }
+    }
+
+    /// Queues every change since the last call.
+    fn scan(&mut self) {
+        let state = self.state.borrow();
+        let mut edges: Vec<u64> = state.holds.iter()
+            .filter(|hold| matches!(hold.input, Input::Key(_)))
+            .flat_map(|hold| [hold.from_ms, hold.until_ms])
+            .filter(|at_ms| (self.scanned_ms + 1..=state.now_ms).contains(at_ms))
+            .collect();
+        edges.sort_unstable();
+        for at_ms in edges {
+            let key = state.key_at(at_ms);
+            if key != self.scanned {
+                self.queue.push(at_ms, key);
+                self.scanned = key;
+            }
+        }
+        self.scanned_ms = state.now_ms;
+    }
+}
+
+impl KeyInput for SimKeypad {
+    fn get_key(&mut self) -> Result<Option<Key>> {
+        self.scan();
+        let now_ms = self.state.borrow().now_ms;
+        Ok(self.queue.read(now_ms))
+    }
+
+    fn clear(&mut self) {
+        self.scan();
+        self.queue.clear();
+    }
+}
+
+struct SimSpeaker(Shared);
+
+impl AudioOut for SimSpeaker {
+    fn play(&mut self, _sound: Box<dyn Sound>) -> Result<()> {
+        self.0.borrow_mut().record(Event::Sound);
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.0.borrow_mut().record(Event::SoundCleared);
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.0.borrow_mut().record(Event::Volume(volume));
+    }
+}
+
+struct SimLight(Shared);
+
+impl Light for SimLight {
+    /// Like the real remote, sends nothing for a code without a frame.
+    fn send(&mut self, code: LightCode) -> Result<()> {
+        let mut state = self.0.borrow_mut();
+        if let Some(frame) = state.light_codes[code as usize] {
+            state.record(Event::Light(code, frame));
+        }
+        Ok(())
+    }
+
+    fn learn(&mut self, code: LightCode, timeout_ms: u32) -> Result<bool> {
+        let mut state = self.0.borrow_mut();
+        let now_ms = state.now_ms;
+        let pressed = state.holds.iter().find_map(|hold| match hold.input {
+            Input::Remote(frame) if hold.from_ms <= now_ms && now_ms < hold.until_ms => Some(frame),
+            _ => None,
+        });
+        if let Some(frame) = pressed {
+            state.light_codes[code as usize] = Some(frame);
+            state.record(Event::Learned(code));
+            return Ok(true);
+        }
+        state.advance(timeout_ms as u64)?;
+        Ok(false)
+    }
+}
+
+struct SimClock(Shared);
+
+impl Clock for SimClock {
+    fn counter(&self) -> Result<u64> {
+        Ok(self.0.borrow().now_ms)
+    }
+
+    fn tick_hz(&self) -> u64 {
+        1000
+    }
+
+    fn delay_ms(&mut self, ms: u32) -> Result<()> {
+        self.0.borrow_mut().advance(ms as u64)
+    }
+}
+
+struct SimPower(Shared);
+
+impl Power for SimPower {
+    fn wake_reason(&self) -> WakeReason {
+        self.0.borrow().wake_reason
+    }
+
+    fn deep_sleep(&mut self) -> Result<()> {
+        let mut state = self.0.borrow_mut();
+        state.record(Event::Sleep);
+        state.sleep_until_woken()
+    }
+}
+
+struct SimWallClock(Shared);
+
+impl WallClock for SimWallClock {
+    fn time_of_day(&self) -> Option<u32> {
+        let state = self.0.borrow();
+        let offset_ms = state.clock_offset_ms?;
+        Some(((state.now_ms as i64 + offset_ms) / 1000).rem_euclid(24 * 60 * 60) as u32)
+    }
+
+    fn set_time_of_day(&mut self, seconds: u32) -> Result<()> {
+        let mut state = self.0.borrow_mut();
+        state.clock_offset_ms = Some(seconds as i64 * 1000 - state.now_ms as i64);
+        Ok(())
+    }
+}
+
+struct SimSettings(Shared);
+
+impl Settings for SimSettings {
+    fn get_u32(&self, key: &str) -> Result<Option<u32>> {
+        Ok(self.0.borrow().settings.get(key).copied())
+    }
+
+    fn set_u32(&mut self, key: &str, value: u32) -> Result<()> {
+        self.0.borrow_mut().settings.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// No sound pack, so the app plays its built-in sounds.
+struct SimFiles;
+
+impl Files for SimFiles {
+    fn read(&self, _name: &str) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+}
+
+/// The button lines read low while pressed, the door switch reads low while closed.
+struct SimLine {
+    state: Shared,
+    input: Input,
+    active: bool,
+}
+
+impl InputLine for SimLine {
+    fn is_low(&self) -> bool {
+        self.state.borrow().is_held(self.input) == self.active
+    }
+}
+
+/// Drives `App` against a virtual clock and scripted inputs.
+pub struct Simulator {
+    state: Shared,
+    mode: Mode,
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(State::default())),
+            mode: Mode::Idle,
+        }
+    }
+
+    /// Holds `input` down for `duration_ms`, starting at `at_ms`.
+    pub fn hold(&mut self, input: Input, at_ms: u64, duration_ms: u64) -> &mut Self {
+        self.state.borrow_mut().holds.push(Hold {
+            input,
+            from_ms: at_ms,
+            until_ms: at_ms + duration_ms,
+        });
+        self
+    }
+
+    /// A short press, long enough to be seen by a 50 ms polling loop.
+    pub fn press(&mut self, input: Input, at_ms: u64) -> &mut Self {
+        self.hold(input, at_ms, 100)
+    }
+
+    /// Runs the app through `App::run` until the virtual clock reaches `end_ms`, as the board
+    /// does when it resets on waking. The last run must have ended asleep, and this wakes it
+    /// for the next input that would wake the real board.
+    pub fn wake_until(&mut self, end_ms: u64) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        state.end_ms = end_ms;
+        state.sleep_until_woken()?;
+        drop(state);
+        let mut app = App::new(self.board())?;
+        match app.run() {
+            Err(err) if err.is::<SimulationEnded>() => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Runs the app until the virtual clock reaches `end_ms` and returns the mode it was in.
+    ///
+    /// Calling this again re-enters that mode from its start, so a countdown that was
+    /// interrupted will begin again from the time it was entered with.
+    pub fn run_until(&mut self, end_ms: u64) -> Result<Mode> {
+        self.state.borrow_mut().end_ms = end_ms;
+        let mut app = App::new(self.board())?;
+        loop {
+            match app.step(self.mode) {
+                Ok(next) => self.mode = next,
+                Err(err) if err.is::<SimulationEnded>() => return Ok(self.mode),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.state.borrow().now_ms
+    }
+
+    pub fn events(&self) -> Vec<(u64, Event)> {
+        self.state.borrow().events.clone()
+    }
+
+    /// The last segments written to the display.
+    pub fn display(&self) -> [u8; 4] {
+        self.state.borrow().events.iter().rev().find_map(|(_, event)| match event {
+            Event::Display(segments) => Some(*segments),
+            _ => None,
+        }).unwrap_or([0; 4])
+    }
+
+    pub fn light_on(&self) -> bool {
+        self.state.borrow().events.iter().rev().find_map(|(_, event)| match event {
+            Event::Light(LightCode::On, _) => Some(true),
+            Event::Light(LightCode::Off, _) => Some(false),
+            _ => None,
+        }).unwrap_or(false)
+    }
+
+    fn board(&self) -> Board<'static> {
+        let line = |input, active| Box::new(SimLine { state: self.state.clone(), input, active });
+        Board {
+            display: Box::new(SimDisplay(self.state.clone())),
+            keypad: Box::new(SimKeypad::new(self.state.clone())),
+            speaker: Box::new(SimSpeaker(self.state.clone())),
+            clock: Box::new(SimClock(self.state.clone())),
+            start_button: line(Input::Start, true),
+            stop_button: line(Input::Stop, true),
+            door_switch: line(Input::DoorOpen, false),
+            remote: Box::new(SimLight(self.state.clone())),
+            power: Box::new(SimPower(self.state.clone())),
+            wall_clock: Box::new(SimWallClock(self.state.clone())),
+            settings: Box::new(SimSettings(self.state.clone())),
+            files: Box::new(SimFiles),
+        }
+    }
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Renders display segments as text, e.g. ` 1:30` or `En d`.
+pub fn render(segments: [u8; 4]) -> String {
+    let mut text = String::new();
+    for (i, segment) in segments.iter().enumerate() {
+        text.push(font::character(*segment).unwrap_or('?'));
+        if i == 1 {
+            text.push(if segment & font::POINT != 0 { ':' } else { ' ' });
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::Align;
+    use crate::preset::Dish;
+
+    /// Whether `text` was on the display at any point.
+    fn showed(sim: &Simulator, text: &str) -> bool {
+        let wanted = font::text(text, Align::Center);
+        let wanted_left = font::text(text, Align::Left);
+        sim.events().iter().any(|(_, event)| {
+            matches!(event, Event::Display(segments) if *segments == wanted || *segments == wanted_left)
+        })
+    }
+
+    fn count(sim: &Simulator, wanted: Event) -> usize {
+        sim.events().iter().filter(|(_, event)| *event == wanted).count()
+    }
+
+    /// Types 10 seconds and presses start.
+    fn cook_ten_seconds(sim: &mut Simulator) -> &mut Simulator {
+        sim.press(Input::Key(Key::Digit(1)), 1000)
+            .press(Input::Key(Key::Digit(0)), 1500)
+            .press(Input::Start, 2000)
+    }
+
+    #[test]
+    fn typed_digits_start_a_countdown() {
+        let mut sim = Simulator::new();
+        cook_ten_seconds(&mut sim);
+        assert_eq!(sim.run_until(1200).unwrap(), Mode::UserInput{first_key: Some(Key::Digit(1))});
+
+        let mut sim = Simulator::new();
+        cook_ten_seconds(&mut sim);
+        assert_eq!(sim.run_until(4000).unwrap(), Mode::Running{seconds: 10, minutes: 0, power: 10});
+        assert!(sim.light_on());
+        assert_eq!(render(sim.display()), "  : 8");
+    }
+
+    #[test]
+    fn typed_seconds_past_59_carry_into_the_minutes() {
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Digit(1), Key::Digit(9), Key::Digit(0)]);
+        sim.press(Input::Start, next_ms);
+        assert_eq!(sim.run_until(next_ms + 500).unwrap(), Mode::Running{seconds: 30, minutes: 2, power: 10});
+
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Digit(9), Key::Digit(9), Key::Digit(9)]);
+        sim.press(Input::Start, next_ms);
+        assert_eq!(sim.run_until(next_ms + 500).unwrap(), Mode::Running{seconds: 39, minutes: 10, power: 10});
+    }
+
+    #[test]
+    fn start_adds_time_up_to_99_59() {
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Digit(9), Key::Digit(9), Key::Digit(9)]);
+        // 10:39 plus 30 seconds a press reaches the cap after 180 presses.
+        for i in 0..200 {
+            sim.press(Input::Start, next_ms + i * 300);
+        }
+        sim.run_until(next_ms + 200 * 300).unwrap();
+        assert!(["99:58", "99 58"].contains(&render(sim.display()).as_str()), "{}", render(sim.display()));
+    }
+
+    #[test]
+    fn countdown_ends_in_done_then_idle() {
+        let mut sim = Simulator::new();
+        cook_ten_seconds(&mut sim);
+        assert_eq!(sim.run_until(13000).unwrap(), Mode::Done);
+        assert!(!sim.light_on());
+        assert!(showed(&sim, "End"));
+
+        let mut sim = Simulator::new();
+        cook_ten_seconds(&mut sim);
+        assert_eq!(sim.run_until(40000).unwrap(), Mode::Idle);
+        assert_eq!(count(&sim, Event::Sleep), 0);
+    }
+
+    #[test]
+    fn opening_the_door_pauses_and_start_resumes() {
+        let mut sim = Simulator::new();
+        cook_ten_seconds(&mut sim).hold(Input::DoorOpen, 5000, 2000);
+        let mode = sim.run_until(6000).unwrap();
+        assert!(matches!(mode, Mode::Paused{minutes: 0, seconds: 7, power: 10}), "{:?}", mode);
+        assert!(!sim.light_on());
+
+        let mut sim = Simulator::new();
+        cook_ten_seconds(&mut sim).hold(Input::DoorOpen, 5000, 2000).press(Input::Start, 8000);
+        let mode = sim.run_until(8500).unwrap();
+        assert!(matches!(mode, Mode::Running{minutes: 0, seconds: 7, power: 10}), "{:?}", mode);
+        assert!(sim.light_on());
+    }
+
+    /// Presses each key in turn, half a second apart, starting at `at_ms`.
+    fn type_keys(sim: &mut Simulator, at_ms: u64, keys: &[Key]) -> u64 {
+        for (i, key) in keys.iter().enumerate() {
+            sim.press(Input::Key(*key), at_ms + i as u64 * 500);
+        }
+        at_ms + keys.len() as u64 * 500
+    }
+
+    #[test]
+    fn star_deletes_the_last_digit() {
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Digit(1), Key::Digit(2), Key::Digit(3), Key::Star]);
+        assert_eq!(sim.run_until(next_ms).unwrap(), Mode::UserInput{first_key: Some(Key::Digit(1))});
+        assert_eq!(render(sim.display()), "  :12");
+
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Digit(1), Key::Digit(2), Key::Digit(3), Key::Star]);
+        sim.press(Input::Start, next_ms);
+        assert_eq!(sim.run_until(next_ms + 500).unwrap(), Mode::Running{seconds: 12, minutes: 0, power: 10});
+    }
+
+    #[test]
+    fn star_with_nothing_to_delete_goes_back_to_the_clock() {
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Digit(4), Key::Star]);
+        assert_eq!(sim.run_until(next_ms).unwrap(), Mode::UserInput{first_key: Some(Key::Digit(4))});
+
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Digit(4), Key::Star, Key::Star]);
+        assert_eq!(sim.run_until(next_ms).unwrap(), Mode::Idle);
+    }
+
+    /// Types 10 seconds at power 5, then 5 seconds at full power, and presses start.
+    fn cook_two_stages(sim: &mut Simulator) -> &mut Simulator {
+        let keys = [Key::Digit(1), Key::Digit(0), Key::Hash, Key::Digit(5), Key::Hash, Key::Star, Key::Digit(5)];
+        let next_ms = type_keys(sim, 1000, &keys);
+        sim.press(Input::Start, next_ms)
+    }
+
+    #[test]
+    fn a_program_runs_its_stages_in_turn() {
+        let mut sim = Simulator::new();
+        cook_two_stages(&mut sim);
+        assert_eq!(sim.run_until(5000).unwrap(), Mode::Running{seconds: 10, minutes: 0, power: 5});
+        assert!(showed(&sim, "St 1"));
+
+        let mut sim = Simulator::new();
+        cook_two_stages(&mut sim);
+        assert_eq!(sim.run_until(16000).unwrap(), Mode::Running{seconds: 5, minutes: 0, power: 10});
+        assert!(showed(&sim, "St 2"));
+        // One beep between the stages, on top of the running sound for each.
+        let between = sim.events().iter()
+            .filter(|(at_ms, event)| (14000..16000).contains(at_ms) && *event == Event::Sound)
+            .count();
+        assert_eq!(between, 2);
+
+        let mut sim = Simulator::new();
+        cook_two_stages(&mut sim);
+        assert_eq!(sim.run_until(21000).unwrap(), Mode::Done);
+    }
+
+    #[test]
+    fn a_paused_program_resumes_the_same_stage() {
+        let mut sim = Simulator::new();
+        cook_two_stages(&mut sim).hold(Input::DoorOpen, 16000, 1000).press(Input::Start, 18000);
+        let mode = sim.run_until(17500).unwrap();
+        assert!(matches!(mode, Mode::Paused{minutes: 0, power: 10, ..}), "{:?}", mode);
+
+        let mut sim = Simulator::new();
+        cook_two_stages(&mut sim).hold(Input::DoorOpen, 16000, 1000).press(Input::Start, 18000);
+        let mode = sim.run_until(18500).unwrap();
+        assert!(matches!(mode, Mode::Running{minutes: 0, power: 10, ..}), "{:?}", mode);
+
+        let mut sim = Simulator::new();
+        cook_two_stages(&mut sim).hold(Input::DoorOpen, 16000, 1000).press(Input::Start, 18000);
+        assert_eq!(sim.run_until(23000).unwrap(), Mode::Done);
+    }
+
+    /// Defrosts 100 g of bread: 30 s at power 3, the turn, a 30 s rest, 6 s at power 3 and a
+    /// last 30 s rest. Returns when start was pressed.
+    fn defrost_bread(sim: &mut Simulator) -> u64 {
+        let keys = [Key::Hash, Key::Hash, Key::Digit(4), Key::Digit(1), Key::Digit(0), Key::Digit(0)];
+        let next_ms = type_keys(sim, 1000, &keys);
+        sim.press(Input::Start, next_ms);
+        next_ms
+    }
+
+    #[test]
+    fn defrosting_stops_halfway_to_turn_the_food() {
+        let mut sim = Simulator::new();
+        let start_ms = defrost_bread(&mut sim);
+        assert_eq!(sim.run_until(start_ms - 200).unwrap(), Mode::UserInput{first_key: Some(Key::Hash)});
+        assert!(showed(&sim, "dF 4"));
+        assert_eq!(render(sim.display()), " 1 00");
+
+        let mut sim = Simulator::new();
+        let start_ms = defrost_bread(&mut sim);
+        assert_eq!(sim.run_until(start_ms + 1500).unwrap(), Mode::Running{seconds: 30, minutes: 0, power: 3});
+        assert!(sim.light_on());
+
+        // It waits at the turn, beeping three times, until start.
+        let mut sim = Simulator::new();
+        let start_ms = defrost_bread(&mut sim);
+        assert_eq!(sim.run_until(start_ms + 60_000).unwrap(), Mode::TurnOver);
+        assert_eq!(sim.display(), font::text("turn", Align::Left));
+        let events = sim.events();
+        let turn = events.iter()
+            .position(|(_, event)| *event == Event::Display(font::text("turn", Align::Left)))
+            .unwrap();
+        let beeps = events[turn..].iter().filter(|(_, event)| *event == Event::Sound).count();
+        assert_eq!(beeps, 3);
+
+        // Start carries on with a rest, then the second burst.
+        let mut sim = Simulator::new();
+        let start_ms = defrost_bread(&mut sim);
+        sim.press(Input::Start, start_ms + 40_000);
+        assert_eq!(sim.run_until(start_ms + 45_000).unwrap(), Mode::Running{seconds: 30, minutes: 0, power: 0});
+        assert!(!sim.light_on());
+
+        let mut sim = Simulator::new();
+        let start_ms = defrost_bread(&mut sim);
+        sim.press(Input::Start, start_ms + 40_000);
+        assert_eq!(sim.run_until(start_ms + 72_000).unwrap(), Mode::Running{seconds: 6, minutes: 0, power: 3});
+
+        let mut sim = Simulator::new();
+        let start_ms = defrost_bread(&mut sim);
+        sim.press(Input::Start, start_ms + 40_000);
+        assert_eq!(sim.run_until(start_ms + 110_000).unwrap(), Mode::Done);
+    }
+
+    #[test]
+    fn star_goes_back_to_the_stage_before() {
+        let keys = [Key::Digit(1), Key::Digit(0), Key::Hash, Key::Star, Key::Digit(5), Key::Star, Key::Star];
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &keys);
+        assert_eq!(sim.run_until(next_ms).unwrap(), Mode::UserInput{first_key: Some(Key::Digit(1))});
+        assert_eq!(render(sim.display()), "  :10");
+
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &keys);
+        sim.press(Input::Start, next_ms);
+        assert_eq!(sim.run_until(next_ms + 500).unwrap(), Mode::Running{seconds: 10, minutes: 0, power: 10});
+        assert!(!showed(&sim, "St 1"));
+    }
+
+    #[test]
+    fn holding_star_clears_everything() {
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Digit(4), Key::Digit(5)]);
+        sim.hold(Input::Key(Key::Star), next_ms, 1500);
+        assert_eq!(sim.run_until(next_ms + 800).unwrap(), Mode::UserInput{first_key: Some(Key::Digit(4))});
+
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Digit(4), Key::Digit(5)]);
+        sim.hold(Input::Key(Key::Star), next_ms, 1500);
+        assert_eq!(sim.run_until(next_ms + 1200).unwrap(), Mode::Idle);
+    }
+
+    #[test]
+    fn hash_sets_the_power_and_hash_hash_opens_the_menu() {
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Digit(3), Key::Digit(0), Key::Hash, Key::Digit(5)]);
+        sim.press(Input::Start, next_ms);
+        assert_eq!(sim.run_until(next_ms + 500).unwrap(), Mode::Running{seconds: 30, minutes: 0, power: 5});
+
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Hash, Key::Hash, Key::Digit(7)]);
+        assert_eq!(sim.run_until(next_ms).unwrap(), Mode::SetClock);
+    }
+
+    /// A button from another remote, for the light puck to learn.
+    const OTHER_ON: nec::Frame = nec::Frame::new(0x10, 0x01);
+
+    #[test]
+    fn a_learned_code_is_sent_from_then_on() {
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Hash, Key::Hash, Key::Digit(9), Key::Digit(1)]);
+        sim.hold(Input::Remote(OTHER_ON), next_ms + 1000, 500);
+        assert_eq!(sim.run_until(next_ms + 500).unwrap(), Mode::Learn{code: LightCode::On});
+        assert!(showed(&sim, "Lrn1"));
+
+        assert_eq!(sim.run_until(next_ms + 2000).unwrap(), Mode::Idle);
+        assert_eq!(count(&sim, Event::Learned(LightCode::On)), 1);
+
+        // Another `App`, as after a reboot, turns the light on with the learned code.
+        let next_ms = type_keys(&mut sim, next_ms + 3000, &[Key::Digit(1), Key::Digit(0)]);
+        sim.press(Input::Start, next_ms);
+        sim.run_until(next_ms + 12000).unwrap();
+        assert_eq!(count(&sim, Event::Light(LightCode::On, OTHER_ON)), 1);
+        assert_eq!(count(&sim, Event::Light(LightCode::On, nec::LIGHT_ON)), 0);
+        assert_eq!(count(&sim, Event::Light(LightCode::Off, nec::LIGHT_OFF)), 1);
+    }
+
+    #[test]
+    fn learning_spins_while_it_listens() {
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Hash, Key::Hash, Key::Digit(9), Key::Digit(1)]);
+        sim.run_until(next_ms + 2000).unwrap();
+        let lit: u32 = sim.display().iter().map(|digit| digit.count_ones()).sum();
+        assert_eq!(lit, 1);
+        let spinner_frames = sim.events().iter()
+            .filter(|(at_ms, event)| *at_ms > next_ms + 1000 && matches!(event, Event::Display(_)))
+            .count();
+        assert!(spinner_frames >= 8, "{} frames", spinner_frames);
+    }
+
+    #[test]
+    fn learning_gives_up_with_err_after_ten_seconds() {
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Hash, Key::Hash, Key::Digit(9), Key::Digit(3)]);
+        assert_eq!(sim.run_until(next_ms + 9000).unwrap(), Mode::Learn{code: LightCode::Brighter});
+        assert!(showed(&sim, "Lrn3"));
+        assert!(!showed(&sim, "Err"));
+
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Hash, Key::Hash, Key::Digit(9), Key::Digit(3)]);
+        assert_eq!(sim.run_until(next_ms + 13000).unwrap(), Mode::Idle);
+        assert!(showed(&sim, "Err"));
+        assert_eq!(count(&sim, Event::Learned(LightCode::Brighter)), 0);
+    }
+
+    #[test]
+    fn an_unset_clock_blinks_12_00() {
+        let mut sim = Simulator::new();
+        sim.run_until(1250).unwrap();
+        assert_eq!(render(sim.display()), "12:00");
+        sim.run_until(1750).unwrap();
+        assert_eq!(render(sim.display()), "     ");
+    }
+
+    /// Sets the clock through `# # 7`, returning when it was set.
+    fn set_clock(sim: &mut Simulator, time: [u8; 4]) -> u64 {
+        let next_ms = type_keys(sim, 1000, &[Key::Hash, Key::Hash, Key::Digit(7)]);
+        let digits: Vec<Key> = time.iter().map(|digit| Key::Digit(*digit)).collect();
+        let next_ms = type_keys(sim, next_ms, &digits);
+        sim.press(Input::Start, next_ms);
+        next_ms
+    }
+
+    /// The clock on the display, without the blinking colon.
+    fn clock_shown(sim: &Simulator) -> String {
+        render(sim.display()).replace(':', " ")
+    }
+
+    #[test]
+    fn the_clock_is_set_from_the_menu_and_keeps_time() {
+        let mut sim = Simulator::new();
+        let set_at = set_clock(&mut sim, [1, 3, 0, 5]);
+        assert_eq!(sim.run_until(set_at + 1000).unwrap(), Mode::Idle);
+        assert_eq!(clock_shown(&sim), "13 05");
+        sim.run_until(set_at + 60_000).unwrap();
+        assert_eq!(clock_shown(&sim), "13 06");
+
+        // Not a real time, so it starts again and the clock stays unset.
+        let mut sim = Simulator::new();
+        let set_at = set_clock(&mut sim, [2, 5, 0, 0]);
+        assert_eq!(sim.run_until(set_at + 500).unwrap(), Mode::SetClock);
+        assert_eq!(render(sim.display()), "  :  ");
+        sim.press(Input::Stop, set_at + 1000);
+        sim.run_until(set_at + 2500).unwrap();
+        assert_eq!(clock_shown(&sim), "12 00");
+    }
+
+    #[test]
+    fn the_clock_switches_between_12_and_24_hour() {
+        let mut sim = Simulator::new();
+        let set_at = set_clock(&mut sim, [1, 3, 0, 5]);
+        let next_ms = type_keys(&mut sim, set_at + 1000, &[Key::Hash, Key::Hash, Key::Digit(8)]);
+        assert_eq!(sim.run_until(next_ms + 500).unwrap(), Mode::Idle);
+        assert_eq!(clock_shown(&sim), " 1 05");
+
+        // Another `App`, as after a reboot, keeps the 12 hour clock.
+        sim.run_until(next_ms + 1000).unwrap();
+        assert_eq!(clock_shown(&sim), " 1 05");
+
+        let next_ms = type_keys(&mut sim, next_ms + 1000, &[Key::Hash, Key::Hash, Key::Digit(8)]);
+        sim.run_until(next_ms + 500).unwrap();
+        assert_eq!(clock_shown(&sim), "13 05");
+    }
+
+    #[test]
+    fn holding_a_preset_key_starts_its_preset() {
+        let mut sim = Simulator::new();
+        sim.hold(Input::Key(Key::Digit(1)), 1000, 1500);
+        assert_eq!(sim.run_until(2500).unwrap(), Mode::Preset{dish: Dish::Popcorn});
+        assert_eq!(render(sim.display()), "P0 P ");
+
+        let mut sim = Simulator::new();
+        sim.hold(Input::Key(Key::Digit(1)), 1000, 1500);
+        assert_eq!(sim.run_until(4000).unwrap(), Mode::Running{seconds: 30, minutes: 2, power: 10});
+        assert!(sim.light_on());
+    }
+
+    #[test]
+    fn a_preset_waits_for_the_door_to_close() {
+        let mut sim = Simulator::new();
+        sim.hold(Input::DoorOpen, 0, 10000).hold(Input::Key(Key::Digit(1)), 1000, 1500);
+        assert_eq!(sim.run_until(4000).unwrap(), Mode::Paused{seconds: 30, minutes: 2, power: 10});
+        assert!(!sim.light_on());
+    }
+
+    #[test]
+    fn an_edited_preset_is_kept_and_used() {
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Hash, Key::Hash, Key::Digit(6), Key::Digit(1)]);
+        assert_eq!(sim.run_until(next_ms).unwrap(), Mode::EditPreset{dish: Dish::Popcorn});
+
+        // 0:45 at power 5 with the second sound, then save.
+        let keys = [Key::Digit(4), Key::Digit(5), Key::Hash, Key::Digit(5), Key::Hash, Key::Hash];
+        let next_ms = type_keys(&mut sim, next_ms + 1000, &keys);
+        sim.press(Input::Start, next_ms);
+        assert_eq!(sim.run_until(next_ms + 500).unwrap(), Mode::Idle);
+        assert!(showed(&sim, "PL 5"));
+        assert!(showed(&sim, "Snd2"));
+
+        // Another `App` reads it back from the settings.
+        sim.hold(Input::Key(Key::Digit(1)), next_ms + 1000, 1500);
+        assert_eq!(sim.run_until(next_ms + 4000).unwrap(), Mode::Running{seconds: 45, minutes: 0, power: 5});
+    }
+
+    #[test]
+    fn a_preset_cant_be_saved_as_0_00() {
+        let type_0_00 = |sim: &mut Simulator| {
+            let keys = [Key::Hash, Key::Hash, Key::Digit(6), Key::Digit(1)];
+            let next_ms = type_keys(sim, 1000, &keys);
+            let next_ms = type_keys(sim, next_ms + 1000, &[Key::Digit(0), Key::Digit(0)]);
+            sim.press(Input::Start, next_ms);
+            next_ms
+        };
+        let mut sim = Simulator::new();
+        let next_ms = type_0_00(&mut sim);
+        assert_eq!(sim.run_until(next_ms + 500).unwrap(), Mode::EditPreset{dish: Dish::Popcorn});
+        assert_eq!(render(sim.display()), "  :  ");
+
+        // Stop leaves it as it was.
+        let mut sim = Simulator::new();
+        let next_ms = type_0_00(&mut sim);
+        sim.press(Input::Stop, next_ms + 1000).hold(Input::Key(Key::Digit(1)), next_ms + 2000, 1500);
+        assert_eq!(sim.run_until(next_ms + 5000).unwrap(), Mode::Running{seconds: 30, minutes: 2, power: 10});
+    }
+
+    /// Sets the kitchen timer through `# # 5`, returning when start was pressed.
+    fn set_kitchen_timer(sim: &mut Simulator, at_ms: u64, time: &[u8]) -> u64 {
+        let next_ms = type_keys(sim, at_ms, &[Key::Hash, Key::Hash, Key::Digit(5)]);
+        let digits: Vec<Key> = time.iter().map(|digit| Key::Digit(*digit)).collect();
+        let next_ms = type_keys(sim, next_ms, &digits);
+        sim.press(Input::Start, next_ms);
+        next_ms
+    }
+
+    #[test]
+    fn the_kitchen_timer_counts_down_in_place_of_the_clock() {
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Hash, Key::Hash, Key::Digit(5), Key::Digit(5)]);
+        assert_eq!(sim.run_until(next_ms).unwrap(), Mode::KitchenTimer);
+
+        let mut sim = Simulator::new();
+        let set_at = set_kitchen_timer(&mut sim, 1000, &[5, 0, 0]);
+        assert_eq!(sim.run_until(set_at + 1500).unwrap(), Mode::Idle);
+        assert_eq!(sim.display(), font::text("t4:59", Align::Left));
+        assert!(!sim.light_on());
+    }
+
+    #[test]
+    fn the_kitchen_timer_shows_every_fifth_second_while_cooking() {
+        let mut sim = Simulator::new();
+        let set_at = set_kitchen_timer(&mut sim, 1000, &[5, 0, 0]);
+        let next_ms = type_keys(&mut sim, set_at + 1000, &[Key::Digit(2), Key::Digit(0)]);
+        sim.press(Input::Start, next_ms);
+        assert_eq!(sim.run_until(next_ms + 12000).unwrap(), Mode::Running{seconds: 20, minutes: 0, power: 10});
+        let [t, ..]: [u8; 4] = font::text("t", Align::Left);
+        let timer_shown: Vec<u64> = sim.events().iter()
+            .filter(|(at_ms, event)| *at_ms > next_ms && matches!(event, Event::Display([first, ..]) if *first == t))
+            .map(|(at_ms, _)| (at_ms - next_ms) / 1000)
+            .collect();
+        assert_eq!(timer_shown, [5, 10]);
+
+        // The light and running sound go with the cook, not the timer.
+        let mut sim = Simulator::new();
+        let set_at = set_kitchen_timer(&mut sim, 1000, &[5, 0, 0]);
+        let next_ms = type_keys(&mut sim, set_at + 1000, &[Key::Digit(2), Key::Digit(0)]);
+        sim.press(Input::Start, next_ms);
+        assert_eq!(sim.run_until(next_ms + 45500).unwrap(), Mode::Idle);
+        assert_eq!(light_switches(&sim), [(0, true), (20, false)]);
+        assert_eq!(sim.display(), font::text("t4:13", Align::Left));
+    }
+
+    #[test]
+    fn the_kitchen_timer_chimes_without_stopping_the_cook() {
+        let mut sim = Simulator::new();
+        let set_at = set_kitchen_timer(&mut sim, 1000, &[5]);
+        let next_ms = type_keys(&mut sim, set_at + 500, &[Key::Digit(2), Key::Digit(0)]);
+        sim.press(Input::Start, next_ms);
+        let mode = sim.run_until(next_ms + 10000).unwrap();
+        assert!(matches!(mode, Mode::Running{power: 10, ..}), "{:?}", mode);
+        assert!(sim.light_on());
+        let chimed = sim.events().iter()
+            .any(|(at_ms, event)| (set_at + 5000..set_at + 5200).contains(at_ms) && *event == Event::Sound);
+        assert!(chimed);
+        assert_eq!(count(&sim, Event::SoundCleared), 0);
+        assert_eq!(render(sim.display()), "  :10");
+    }
+
+    #[test]
+    fn stop_cancels_the_kitchen_timer_while_idle() {
+        let mut sim = Simulator::new();
+        let set_at = set_kitchen_timer(&mut sim, 1000, &[1, 0]);
+        sim.press(Input::Stop, set_at + 2000);
+        sim.run_until(set_at + 20500).unwrap();
+        assert_eq!(clock_shown(&sim), "12 00");
+        let after_stop = sim.events().iter()
+            .filter(|(at_ms, event)| *at_ms > set_at + 2000 && *event == Event::Sound)
+            .count();
+        // Just the beep for stop, and no chime.
+        assert_eq!(after_stop, 1);
+    }
+
+    #[test]
+    fn the_kitchen_timer_keeps_it_awake() {
+        let mut sim = Simulator::new();
+        let set_at = set_kitchen_timer(&mut sim, 1000, &[2, 0, 0]);
+        assert_eq!(sim.run_until(set_at + 110_000).unwrap(), Mode::Idle);
+        assert_eq!(count(&sim, Event::Sleep), 0);
+
+        // Locking doesn't cancel it either.
+        let mut sim = Simulator::new();
+        let set_at = set_kitchen_timer(&mut sim, 1000, &[2, 0, 0]);
+        sim.hold(Input::Stop, set_at + 1000, 3500);
+        sim.run_until(set_at + 110_000).unwrap();
+        assert_eq!(count(&sim, Event::Sleep), 0);
+        assert_eq!(locked_shown(&sim), 1);
+        assert_eq!(sim.display(), font::text("t0:10", Align::Left));
+    }
+
+    /// The last volume given to the speaker after `from`, the index of an event.
+    fn volume_after(sim: &Simulator, from: usize) -> Option<f32> {
+        sim.events()[from..].iter().rev().find_map(|(_, event)| match event {
+            Event::Volume(volume) => Some(*volume),
+            _ => None,
+        })
+    }
+
+    fn assert_volume(volume: Option<f32>, expected: f32) {
+        let volume = volume.expect("no volume was set");
+        assert!((volume - expected).abs() < 1e-6, "{} != {}", volume, expected);
+    }
+
+    #[test]
+    fn the_volume_is_set_from_the_menu_and_kept() {
+        let mut sim = Simulator::new();
+        sim.run_until(100).unwrap();
+        assert_volume(volume_after(&sim, 0), 1.0);
+
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Hash, Key::Hash, Key::Hash, Key::Digit(5)]);
+        assert_eq!(sim.run_until(next_ms + 500).unwrap(), Mode::Idle);
+        assert_volume(volume_after(&sim, 0), 25.0 / 81.0);
+
+        // Another `App`, as after a reboot, starts at the saved level.
+        let seen = sim.events().len();
+        sim.run_until(next_ms + 1000).unwrap();
+        assert_volume(volume_after(&sim, seen), 25.0 / 81.0);
+    }
+
+    #[test]
+    fn quiet_hours_turn_the_volume_down_overnight() {
+        let set_clock_and_quiet_hours = |sim: &mut Simulator, time: [u8; 4]| {
+            let next_ms = set_clock(sim, time);
+            let next_ms = type_keys(sim, next_ms + 500, &[Key::Hash, Key::Hash, Key::Hash, Key::Hash]);
+            sim.run_until(next_ms + 1500).unwrap();
+        };
+
+        let mut sim = Simulator::new();
+        set_clock_and_quiet_hours(&mut sim, [2, 1, 3, 0]);
+        assert_volume(volume_after(&sim, 0), 1.0);
+
+        let mut sim = Simulator::new();
+        set_clock_and_quiet_hours(&mut sim, [2, 2, 3, 0]);
+        assert_volume(volume_after(&sim, 0), 1.0 / 9.0);
+    }
+
+    /// When the light turned on and off, in whole seconds after it first came on.
+    fn light_switches(sim: &Simulator) -> Vec<(u64, bool)> {
+        let events = sim.events();
+        let (on_at, _) = events.iter().find(|(_, event)| matches!(event, Event::Light(LightCode::On, _))).unwrap();
+        events.iter().filter_map(|(at, event)| match event {
+            Event::Light(LightCode::On, _) => Some(((at - on_at) / 1000, true)),
+            Event::Light(LightCode::Off, _) => Some(((at - on_at) / 1000, false)),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn lower_power_cycles_the_light() {
+        // 20 seconds at power 3.
+        let mut sim = Simulator::new();
+        sim.press(Input::Key(Key::Digit(2)), 1000)
+            .press(Input::Key(Key::Digit(0)), 1500)
+            .press(Input::Key(Key::Hash), 2000)
+            .press(Input::Key(Key::Digit(3)), 2500)
+            .press(Input::Start, 3000);
+        assert_eq!(sim.run_until(4000).unwrap(), Mode::Running{seconds: 20, minutes: 0, power: 3});
+
+        let mut sim = Simulator::new();
+        sim.press(Input::Key(Key::Digit(2)), 1000)
+            .press(Input::Key(Key::Digit(0)), 1500)
+            .press(Input::Key(Key::Hash), 2000)
+            .press(Input::Key(Key::Digit(3)), 2500)
+            .press(Input::Start, 3000);
+        sim.run_until(30000).unwrap();
+        assert_eq!(light_switches(&sim), [(0, true), (3, false), (10, true), (13, false), (20, false)]);
+    }
+
+    #[test]
+    fn full_power_leaves_the_light_on() {
+        let mut sim = Simulator::new();
+        cook_ten_seconds(&mut sim);
+        sim.run_until(30000).unwrap();
+        assert_eq!(light_switches(&sim), [(0, true), (10, false)]);
+    }
+
+    #[test]
+    fn start_is_ignored_with_the_door_open() {
+        let mut sim = Simulator::new();
+        sim.hold(Input::DoorOpen, 0, 5000).press(Input::Start, 1000);
+        assert_eq!(sim.run_until(3000).unwrap(), Mode::Idle);
+        assert!(!sim.light_on());
+    }
+
+    fn locked_shown(sim: &Simulator) -> usize {
+        count(sim, Event::Display(font::text("LOC", Align::Left)))
+    }
+
+    #[test]
+    fn holding_stop_locks_the_panel() {
+        let mut sim = Simulator::new();
+        sim.hold(Input::Stop, 1000, 3500)
+            .press(Input::Key(Key::Digit(1)), 6000)
+            .press(Input::Start, 8000);
+        assert_eq!(sim.run_until(10000).unwrap(), Mode::Idle);
+        assert_eq!(locked_shown(&sim), 3);
+        assert!(!sim.light_on());
+
+        // Stop leaves the countdown and carries on into the hold.
+        let mut sim = Simulator::new();
+        cook_ten_seconds(&mut sim).hold(Input::Stop, 3000, 3500);
+        assert_eq!(sim.run_until(7000).unwrap(), Mode::Idle);
+        assert_eq!(locked_shown(&sim), 1);
+        assert!(!sim.light_on());
+    }
+
+    #[test]
+    fn loc_stays_up_for_a_second_without_blocking_stop() {
+        let mut sim = Simulator::new();
+        sim.hold(Input::Stop, 1000, 3500).press(Input::Key(Key::Digit(1)), 6000);
+        sim.run_until(6900).unwrap();
+        assert_eq!(sim.display(), font::text("LOC", Align::Left));
+
+        let mut sim = Simulator::new();
+        sim.hold(Input::Stop, 1000, 3500).press(Input::Key(Key::Digit(1)), 6000);
+        sim.run_until(7250).unwrap();
+        assert_eq!(render(sim.display()), "12:00");
+
+        // Stop is held to unlock while `LOC` is still up.
+        let mut sim = Simulator::new();
+        sim.hold(Input::Stop, 1000, 3500)
+            .press(Input::Key(Key::Digit(1)), 6000)
+            .hold(Input::Stop, 6200, 3500)
+            .press(Input::Key(Key::Digit(2)), 10000);
+        assert_eq!(sim.run_until(10500).unwrap(), Mode::UserInput{first_key: Some(Key::Digit(2))});
+    }
+
+    #[test]
+    fn the_lock_survives_sleep_and_power_loss() {
+        let mut sim = Simulator::new();
+        sim.hold(Input::Stop, 1000, 3500).press(Input::Key(Key::Digit(1)), 80000);
+        sim.run_until(81000).unwrap();
+        assert_eq!(count(&sim, Event::Sleep), 1);
+        assert_eq!(locked_shown(&sim), 2);
+
+        // A new `App` reads the lock back from the settings.
+        sim.press(Input::Key(Key::Digit(1)), 82000);
+        sim.run_until(83000).unwrap();
+        let locked = font::text("LOC", Align::Left);
+        assert!(sim.events().iter().any(|(at_ms, event)| *at_ms >= 82000 && *event == Event::Display(locked)));
+    }
+
+    #[test]
+    fn holding_stop_again_unlocks() {
+        let mut sim = Simulator::new();
+        sim.hold(Input::Stop, 1000, 3500)
+            .hold(Input::Stop, 6000, 3500)
+            .press(Input::Key(Key::Digit(1)), 10000);
+        assert_eq!(sim.run_until(10500).unwrap(), Mode::UserInput{first_key: Some(Key::Digit(1))});
+        assert_eq!(locked_shown(&sim), 1);
+
+        let mut sim = Simulator::new();
+        sim.hold(Input::Stop, 1000, 3500).hold(Input::Stop, 6000, 2000);
+        sim.press(Input::Key(Key::Digit(1)), 10000);
+        sim.run_until(10500).unwrap();
+        assert_eq!(locked_shown(&sim), 2);
+    }
+
+    #[test]
+    fn idle_times_out_to_sleep_and_a_key_wakes_it() {
+        let mut sim = Simulator::new();
+        assert_eq!(sim.run_until(70000).unwrap(), Mode::Sleep);
+        assert_eq!(count(&sim, Event::Sleep), 1);
+        let (slept_at, _) = sim.events().into_iter().find(|(_, event)| *event == Event::Sleep).unwrap();
+        assert!((60000..63000).contains(&slept_at), "slept at {} ms", slept_at);
+
+        let mut sim = Simulator::new();
+        sim.press(Input::Key(Key::Digit(2)), 80000);
+        assert_eq!(sim.run_until(80050).unwrap(), Mode::UserInput{first_key: Some(Key::Digit(2))});
+    }
+
+    #[test]
+    fn start_wakes_it_and_starts_cooking() {
+        let mut sim = Simulator::new();
+        sim.press(Input::Start, 80000);
+        assert_eq!(sim.run_until(81000).unwrap(), Mode::Running{seconds: 30, minutes: 0, power: 10});
+        assert_eq!(count(&sim, Event::Sleep), 1);
+        assert!(sim.light_on());
+
+        // Locked, it only shows `LOC`.
+        let mut sim = Simulator::new();
+        sim.hold(Input::Stop, 1000, 3500).press(Input::Start, 80000);
+        sim.run_until(81000).unwrap();
+        assert_eq!(count(&sim, Event::Sleep), 1);
+        assert_eq!(locked_shown(&sim), 2);
+        assert!(!sim.light_on());
+    }
+
+    #[test]
+    fn a_muted_panel_woken_by_start_starts_quietly() {
+        let mut sim = Simulator::new();
+        let next_ms = type_keys(&mut sim, 1000, &[Key::Hash, Key::Hash, Key::Hash, Key::Digit(0)]);
+        assert_eq!(sim.run_until(next_ms + 70000).unwrap(), Mode::Sleep);
+
+        // The board resets on waking, so this is a new `App` going through `App::run`.
+        sim.press(Input::Start, next_ms + 80000);
+        let woke = sim.events().len();
+        sim.wake_until(next_ms + 81000).unwrap();
+        let events = &sim.events()[woke..];
+        let first_sound = events.iter().position(|(_, event)| *event == Event::Sound).unwrap();
+        assert!(events[..first_sound].iter().any(|(_, event)| *event == Event::Volume(0.0)));
+        assert!(sim.light_on());
+    }
+
+    #[test]
+    fn waking_into_idle_fades_the_clock_in() {
+        let mut sim = Simulator::new();
+        sim.hold(Input::DoorOpen, 80000, 200);
+        assert_eq!(sim.run_until(81000).unwrap(), Mode::Idle);
+        let events = sim.events();
+        let slept = events.iter().position(|(_, event)| *event == Event::Sleep).unwrap();
+        let levels: Vec<u8> = events[slept..].iter().filter_map(|(_, event)| match event {
+            Event::Brightness(level) => Some(*level),
+            _ => None,
+        }).collect();
+        assert_eq!(levels, [0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn a_key_released_before_the_first_scan_wakes_to_idle() {
+        let mut sim = Simulator::new();
+        sim.hold(Input::Key(Key::Digit(2)), 80000, 0);
+        assert_eq!(sim.run_until(81000).unwrap(), Mode::Idle);
+        assert_eq!(count(&sim, Event::Sleep), 1);
+    }
+
+    #[test]
+    fn the_end_of_cooking_leaves_other_sounds_playing() {
+        // Clearing the speaker would also cut off a kitchen timer chime.
+        let mut sim = Simulator::new();
+        cook_ten_seconds(&mut sim).hold(Input::DoorOpen, 5000, 2000).press(Input::Start, 8000);
+        assert_eq!(sim.run_until(17000).unwrap(), Mode::Done);
+        assert_eq!(count(&sim, Event::SoundCleared), 0);
+    }
+
+    #[test]
+    fn stopping_leaves_other_sounds_playing() {
+        let mut sim = Simulator::new();
+        cook_ten_seconds(&mut sim).press(Input::Stop, 4000);
+        assert_eq!(sim.run_until(6000).unwrap(), Mode::Idle);
+        assert_eq!(count(&sim, Event::SoundCleared), 0);
+    }
+
+    #[test]
+    fn a_key_pressed_while_keys_arent_read_is_forgotten() {
+        // Done only reads keys once its beeps are over, and a tap during them mustn't cut the
+        // message short when it starts scrolling.
+        let mut sim = Simulator::new();
+        cook_ten_seconds(&mut sim).press(Input::Key(Key::Digit(4)), 13000);
+        assert_eq!(sim.run_until(18500).unwrap(), Mode::Done);
+
+        let mut sim = Simulator::new();
+        // The message is only polled between frames, so the key is held for a couple of them.
+        cook_ten_seconds(&mut sim).hold(Input::Key(Key::Digit(4)), 18000, 400);
+        assert_eq!(sim.run_until(19000).unwrap(), Mode::Idle);
+    }
+
+    #[test]
+    fn stop_cancels_cooking() {
+        let mut sim = Simulator::new();
+        cook_ten_seconds(&mut sim).press(Input::Stop, 4000);
+        assert_eq!(sim.run_until(4500).unwrap(), Mode::Idle);
+        assert!(!sim.light_on());
+    }
+}
@@ -23,9 +23,14 @@ use awedio::{
 };
 use awedio_esp32::Esp32Backend;
 use esp_idf_svc::sys::EspError;
+use crate::hal::AudioOut;
+use crate::manifest::{CHANNELS, SAMPLE_RATE};
+use crate::mixer::{Mixer, MixerControl};
 
 pub struct Speaker {
-    manager: Manager,
+    /// Kept so the output keeps running, everything is played through `mixer`.
+    _manager: Manager,
+    mixer: MixerControl,
 }
 
 impl Speaker {
@@ -39,7 +44,7 @@ impl Speaker {
 
         let std_config = StdConfig::new(
             Config::default(),
-            StdClkConfig::from_sample_rate_hz(16000),
+            StdClkConfig::from_sample_rate_hz(SAMPLE_RATE),
             StdSlotConfig::philips_slot_default(DataBitWidth::Bits16, SlotMode::Stereo),
             StdGpioConfig::default(),
         );
@@ -54,19 +59,41 @@ impl Speaker {
         let backend = Esp32Backend::with_defaults(
             i2s,
             2,
-            16000,
+            SAMPLE_RATE,
             128,
         );
-        let manager = backend.start();
-        Ok(Self {manager})
+        let mut manager = backend.start();
+        // The sounds are all mono, the manager copies the mix to both sides of the output.
+        let (mix, mixer) = Mixer::new(CHANNELS, SAMPLE_RATE);
+        manager.play(Box::new(mix));
+        Ok(Self {_manager: manager, mixer})
     }
 
     pub fn play(&mut self, sound: Box<dyn Sound>) -> Result<()> {
-        self.manager.play(sound);
+        self.mixer.play(sound);
         Ok(())
     }
 
     pub fn clear(&mut self) {
-        self.manager.clear();
+        self.mixer.clear();
+    }
+
+    /// From 0.0 for silence to 1.0 for the sounds' own level.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.mixer.set_volume(volume);
+    }
+}
+
+impl AudioOut for Speaker {
+    fn play(&mut self, sound: Box<dyn Sound>) -> Result<()> {
+        Speaker::play(self, sound)
+    }
+
+    fn clear(&mut self) {
+        Speaker::clear(self)
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        Speaker::set_volume(self, volume)
     }
 }
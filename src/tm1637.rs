@@ -0,0 +1,460 @@
+//! The TM1637 LED driver's two wire protocol.
+//!
+//! It looks like I2C without addresses: a start condition, bytes sent least significant bit
+//! first with the chip pulling DIO low on a ninth clock to acknowledge each one, and a stop
+//! condition. The driver only needs two open drain lines and a delay, so it's generic over
+//! `Bus` and can run against a mock.
+//!
+//! Every byte costs nine slow clocks, so the driver remembers what the chip is showing and
+//! only sends the digits and settings that changed.
+
+use anyhow::Result;
+use crate::hal::MAX_BRIGHTNESS;
+use std::fmt;
+
+const DATA_WRITE: u8 = 0x40;
+const DATA_READ_KEYS: u8 = 0x42;
+const ADDRESS: u8 = 0xC0;
+const CONTROL: u8 = 0x80;
+const CONTROL_ON: u8 = 0x08;
+
+/// How many bit delays to wait for an acknowledge before giving up.
+const ACK_TIMEOUT_DELAYS: u32 = 10;
+
+/// The order digit positions are wired on common modules, left to right.
+pub const FOUR_DIGITS: &[usize] = &[0, 1, 2, 3];
+/// Six digit modules wire each group of three digits right to left.
+pub const SIX_DIGITS: &[usize] = &[2, 1, 0, 5, 4, 3];
+
+/// The clock and data lines. Both are open drain, so setting a line high releases it.
+pub trait Bus {
+    fn set_clk(&mut self, high: bool) -> Result<()>;
+    fn set_dio(&mut self, high: bool) -> Result<()>;
+    fn dio_is_high(&self) -> bool;
+    /// Waits half a clock period.
+    fn delay(&mut self);
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Tm1637Error {
+    /// The chip didn't acknowledge this byte.
+    NoAck(u8),
+    TooManyDigits(usize),
+}
+
+impl fmt::Display for Tm1637Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tm1637Error::NoAck(byte) => write!(f, "TM1637 didn't acknowledge {:#04x}", byte),
+            Tm1637Error::TooManyDigits(digits) => write!(f, "{} digits won't fit on the display", digits),
+        }
+    }
+}
+
+impl std::error::Error for Tm1637Error {}
+
+/// A key on the TM1637's own scan inputs: `line` is K1 or K2 and `segment` the SG pin, 1-8.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScanKey {
+    pub line: u8,
+    pub segment: u8,
+}
+
+pub struct Tm1637<B> {
+    bus: B,
+    /// The chip's grid address for each digit, left to right.
+    digits: &'static [usize],
+    brightness: u8,
+    on: bool,
+    /// What the chip's display RAM and control register hold, or `None` if that isn't known,
+    /// like before the first write or after one failed.
+    shown: Option<[u8; 6]>,
+    control: Option<u8>,
+    bytes_written: u64,
+}
+
+impl<B: Bus> Tm1637<B> {
+    pub fn new(bus: B, digits: &'static [usize]) -> Self {
+        Self {
+            bus,
+            digits,
+            brightness: MAX_BRIGHTNESS,
+            on: true,
+            shown: None,
+            control: None,
+            bytes_written: 0,
+        }
+    }
+
+    pub fn digits(&self) -> usize {
+        self.digits.len()
+    }
+
+    /// How many bytes have gone out on the bus, for measuring transfers.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Sets one of the eight brightness levels, 0-7.
+    pub fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+        self.brightness = brightness.min(MAX_BRIGHTNESS);
+        self.write_control()
+    }
+
+    /// Turns the display off or on again, keeping what's on it.
+    pub fn set_on(&mut self, on: bool) -> Result<()> {
+        self.on = on;
+        self.write_control()
+    }
+
+    /// Writes segments to the digits from the left, blanking any past the end of `segments`.
+    /// Only the run of grid addresses that changed since the last write is sent.
+    pub fn write_segments(&mut self, segments: &[u8]) -> Result<()> {
+        if segments.len() > self.digits.len() {
+            return Err(Tm1637Error::TooManyDigits(segments.len()).into());
+        }
+        let mut grid = [0u8; 6];
+        for (segment, address) in segments.iter().zip(self.digits) {
+            grid[*address] = *segment;
+        }
+
+        let addresses = self.digits.len();
+        let changed = |address: &usize| self.shown.map_or(true, |shown| shown[*address] != grid[*address]);
+        let first = (0..addresses).find(changed);
+        let last = (0..addresses).rev().find(changed);
+        if let (Some(first), Some(last)) = (first, last) {
+            let result = self.write_grid(&grid[first..=last], first);
+            self.shown = result.is_ok().then_some(grid);
+            result?;
+        }
+        self.write_control()
+    }
+
+    /// Writes `segments` from grid address `first` on, with auto-increment.
+    fn write_grid(&mut self, segments: &[u8], first: usize) -> Result<()> {
+        self.command(&[DATA_WRITE])?;
+        self.start()?;
+        self.write_byte(ADDRESS | first as u8)?;
+        for segment in segments {
+            self.write_byte(*segment)?;
+        }
+        self.stop()
+    }
+
+    /// Reads the key held on the scan inputs, if any.
+    pub fn read_key(&mut self) -> Result<Option<ScanKey>> {
+        self.start()?;
+        self.write_byte(DATA_READ_KEYS)?;
+        let byte = self.read_byte()?;
+        self.stop()?;
+        Ok(decode_key(byte))
+    }
+
+    fn write_control(&mut self) -> Result<()> {
+        let on = if self.on { CONTROL_ON } else { 0 };
+        let control = CONTROL | on | self.brightness;
+        if self.control == Some(control) {
+            return Ok(());
+        }
+        let result = self.command(&[control]);
+        self.control = result.is_ok().then_some(control);
+        result
+    }
+
+    fn command(&mut self, bytes: &[u8]) -> Result<()> {
+        self.start()?;
+        for byte in bytes {
+            self.write_byte(*byte)?;
+        }
+        self.stop()
+    }
+
+    /// DIO falls while CLK is high.
+    fn start(&mut self) -> Result<()> {
+        self.bus.set_dio(false)?;
+        self.bus.delay();
+        Ok(())
+    }
+
+    /// DIO rises while CLK is high.
+    fn stop(&mut self) -> Result<()> {
+        self.bus.set_clk(false)?;
+        self.bus.delay();
+        self.bus.set_dio(false)?;
+        self.bus.delay();
+        self.bus.set_clk(true)?;
+        self.bus.delay();
+        self.bus.set_dio(true)?;
+        self.bus.delay();
+        Ok(())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<()> {
+        self.bytes_written += 1;
+        for i in 0..8 {
+            self.bus.set_clk(false)?;
+            self.bus.set_dio(byte & (1 << i) != 0)?;
+            self.bus.delay();
+            self.bus.set_clk(true)?;
+            self.bus.delay();
+        }
+
+        // Release DIO and clock the acknowledge, which the chip holds low until CLK falls.
+        self.bus.set_clk(false)?;
+        self.bus.set_dio(true)?;
+        self.bus.delay();
+        self.bus.set_clk(true)?;
+        let mut waited = 0;
+        while self.bus.dio_is_high() {
+            if waited == ACK_TIMEOUT_DELAYS {
+                self.stop()?;
+                return Err(Tm1637Error::NoAck(byte).into());
+            }
+            self.bus.delay();
+            waited += 1;
+        }
+        self.bus.delay();
+        self.bus.set_clk(false)?;
+        self.bus.delay();
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        self.bus.set_dio(true)?;
+        let mut byte = 0;
+        for i in 0..8 {
+            self.bus.set_clk(false)?;
+            self.bus.delay();
+            self.bus.set_clk(true)?;
+            self.bus.delay();
+            if self.bus.dio_is_high() {
+                byte |= 1 << i;
+            }
+        }
+        // Clock the chip through its acknowledge.
+        self.bus.set_clk(false)?;
+        self.bus.delay();
+        self.bus.set_clk(true)?;
+        self.bus.delay();
+        Ok(byte)
+    }
+}
+
+/// The scan byte is all ones with no key held. Otherwise the top five bits say which K line
+/// the key is on and the bottom three hold the inverted SG number.
+pub fn decode_key(byte: u8) -> Option<ScanKey> {
+    let line = match byte & 0xF8 {
+        0xF0 => 1,
+        0xE8 => 2,
+        _ => return None,
+    };
+    Some(ScanKey { line, segment: (!byte & 0x07) + 1 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays the chip's side of the wire: decodes what's clocked in, acknowledges each byte
+    /// and clocks `key_byte` out after a key read command.
+    struct MockBus {
+        clk: bool,
+        dio: bool,
+        ack: bool,
+        key_byte: u8,
+        /// The bytes of each start to stop transfer.
+        transfers: Vec<Vec<u8>>,
+        receiving: Option<Vec<u8>>,
+        /// DIO at the last rising edge, which is only a bit if it holds until CLK falls.
+        sampled: Option<bool>,
+        byte: u8,
+        bits: u8,
+        /// Whether the ninth clock, for the acknowledge, is under way.
+        acking: bool,
+        /// The bit of `key_byte` being clocked out, while reading.
+        reading: Option<u8>,
+    }
+
+    impl MockBus {
+        fn new() -> Self {
+            Self {
+                clk: true,
+                dio: true,
+                ack: true,
+                key_byte: 0xFF,
+                transfers: Vec::new(),
+                receiving: None,
+                sampled: None,
+                byte: 0,
+                bits: 0,
+                acking: false,
+                reading: None,
+            }
+        }
+
+        fn take(&mut self) -> Vec<Vec<u8>> {
+            std::mem::take(&mut self.transfers)
+        }
+
+        fn chip_pulls_low(&self) -> bool {
+            match self.reading {
+                Some(bit) => bit < 8 && self.key_byte & (1 << bit) == 0,
+                None => self.acking && self.ack,
+            }
+        }
+    }
+
+    impl Bus for MockBus {
+        fn set_clk(&mut self, high: bool) -> Result<()> {
+            let was_high = std::mem::replace(&mut self.clk, high);
+            if self.receiving.is_none() || was_high == high {
+                return Ok(());
+            }
+            if high {
+                if !self.acking && self.reading.is_none() {
+                    self.sampled = Some(self.dio);
+                }
+            } else if let Some(bit) = self.reading {
+                self.reading = (bit < 8).then_some(bit + 1);
+            } else if self.acking {
+                self.acking = false;
+                let received = self.receiving.as_ref().unwrap();
+                if received.last() == Some(&DATA_READ_KEYS) {
+                    self.reading = Some(0);
+                }
+            } else if let Some(bit) = self.sampled.take() {
+                self.byte |= (bit as u8) << self.bits;
+                self.bits += 1;
+                if self.bits == 8 {
+                    self.receiving.as_mut().unwrap().push(self.byte);
+                    self.byte = 0;
+                    self.bits = 0;
+                    self.acking = true;
+                }
+            }
+            Ok(())
+        }
+
+        fn set_dio(&mut self, high: bool) -> Result<()> {
+            let was_high = std::mem::replace(&mut self.dio, high);
+            if self.clk && was_high && !high {
+                self.receiving = Some(Vec::new());
+                self.sampled = None;
+                self.bits = 0;
+                self.reading = None;
+            } else if self.clk && !was_high && high {
+                assert_eq!(self.bits, 0, "stopped partway through a byte");
+                self.transfers.extend(self.receiving.take());
+            }
+            Ok(())
+        }
+
+        fn dio_is_high(&self) -> bool {
+            self.dio && !self.chip_pulls_low()
+        }
+
+        fn delay(&mut self) {}
+    }
+
+    fn released(bus: &MockBus) -> bool {
+        bus.clk && bus.dio && bus.receiving.is_none()
+    }
+
+    #[test]
+    fn commands_are_framed_and_sent_lsb_first() {
+        let mut tm = Tm1637::new(MockBus::new(), FOUR_DIGITS);
+        tm.set_brightness(3).unwrap();
+        assert_eq!(tm.bus.take(), [vec![0x8B]]);
+        assert!(released(&tm.bus));
+        assert_eq!(tm.bytes_written(), 1);
+
+        tm.set_on(false).unwrap();
+        assert_eq!(tm.bus.take(), [vec![0x83]]);
+        tm.set_brightness(20).unwrap();
+        assert_eq!(tm.bus.take(), [vec![0x87]]);
+    }
+
+    #[test]
+    fn the_first_write_sends_every_digit() {
+        let mut tm = Tm1637::new(MockBus::new(), FOUR_DIGITS);
+        tm.write_segments(&[0x3F, 0x06, 0x5B, 0x4F]).unwrap();
+        assert_eq!(tm.bus.take(), [vec![0x40], vec![0xC0, 0x3F, 0x06, 0x5B, 0x4F], vec![0x8F]]);
+        assert!(released(&tm.bus));
+
+        // Digits past the end of the segments are blanked.
+        let mut tm = Tm1637::new(MockBus::new(), SIX_DIGITS);
+        tm.write_segments(&[1, 2, 3, 4, 5]).unwrap();
+        assert_eq!(tm.bus.take(), [vec![0x40], vec![0xC0, 3, 2, 1, 0, 5, 4], vec![0x8F]]);
+    }
+
+    #[test]
+    fn later_writes_only_send_what_changed() {
+        let mut tm = Tm1637::new(MockBus::new(), FOUR_DIGITS);
+        tm.write_segments(&[1, 2, 3, 4]).unwrap();
+        tm.bus.take();
+
+        tm.write_segments(&[1, 2, 3, 4]).unwrap();
+        assert!(tm.bus.take().is_empty());
+        tm.write_segments(&[1, 2, 9, 4]).unwrap();
+        assert_eq!(tm.bus.take(), [vec![0x40], vec![0xC2, 9]]);
+        // Everything between the first and last change goes in one run.
+        tm.write_segments(&[1, 7, 9, 8]).unwrap();
+        assert_eq!(tm.bus.take(), [vec![0x40], vec![0xC1, 7, 9, 8]]);
+
+        let mut tm = Tm1637::new(MockBus::new(), SIX_DIGITS);
+        tm.write_segments(&[1, 2, 3, 4, 5, 6]).unwrap();
+        tm.bus.take();
+        tm.write_segments(&[1, 2, 3, 4, 5, 0]).unwrap();
+        assert_eq!(tm.bus.take(), [vec![0x40], vec![0xC3, 0]]);
+    }
+
+    #[test]
+    fn a_missing_acknowledge_times_out() {
+        let mut bus = MockBus::new();
+        bus.ack = false;
+        let mut tm = Tm1637::new(bus, FOUR_DIGITS);
+        let err = tm.write_segments(&[1, 2, 3, 4]).unwrap_err();
+        assert_eq!(err.downcast_ref::<Tm1637Error>(), Some(&Tm1637Error::NoAck(DATA_WRITE)));
+        // The transfer is ended rather than left hanging.
+        assert_eq!(tm.bus.take(), [vec![0x40]]);
+        assert!(released(&tm.bus));
+
+        // Nothing is assumed to have got through, so it's all sent again.
+        tm.bus.ack = true;
+        tm.write_segments(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(tm.bus.take(), [vec![0x40], vec![0xC0, 1, 2, 3, 4], vec![0x8F]]);
+    }
+
+    #[test]
+    fn too_many_digits_are_rejected() {
+        let mut tm = Tm1637::new(MockBus::new(), FOUR_DIGITS);
+        let err = tm.write_segments(&[0; 5]).unwrap_err();
+        assert_eq!(err.downcast_ref::<Tm1637Error>(), Some(&Tm1637Error::TooManyDigits(5)));
+        assert!(tm.bus.take().is_empty());
+    }
+
+    #[test]
+    fn keys_are_read_and_decoded() {
+        let mut tm = Tm1637::new(MockBus::new(), FOUR_DIGITS);
+        assert_eq!(tm.read_key().unwrap(), None);
+        assert_eq!(tm.bus.take(), [vec![DATA_READ_KEYS]]);
+        assert!(released(&tm.bus));
+
+        tm.bus.key_byte = 0xF7;
+        assert_eq!(tm.read_key().unwrap(), Some(ScanKey { line: 1, segment: 1 }));
+        tm.bus.key_byte = 0xEA;
+        assert_eq!(tm.read_key().unwrap(), Some(ScanKey { line: 2, segment: 6 }));
+    }
+
+    #[test]
+    fn scan_bytes_decode_to_keys() {
+        for segment in 1..=8 {
+            let low_bits = !(segment - 1) & 0x07;
+            assert_eq!(decode_key(0xF0 | low_bits), Some(ScanKey { line: 1, segment }));
+            assert_eq!(decode_key(0xE8 | low_bits), Some(ScanKey { line: 2, segment }));
+        }
+        assert_eq!(decode_key(0xFF), None);
+        assert_eq!(decode_key(0x00), None);
+        assert_eq!(decode_key(0xD8), None);
+    }
+}
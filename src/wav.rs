@@ -0,0 +1,334 @@
+use crate::adpcm::{self, Adpcm, Data};
+use std::fmt;
+use std::sync::Arc;
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IMA_ADPCM: u16 = 0x0011;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+#[derive(Debug, PartialEq)]
+pub enum WavError {
+    NotRiff,
+    NotWave,
+    Truncated,
+    MissingFormat,
+    MissingData,
+    UnsupportedFormat(u16),
+    UnsupportedChannels(u16),
+    UnsupportedBitsPerSample(u16),
+    BadBlockAlign,
+}
+
+impl fmt::Display for WavError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WavError::NotRiff => write!(f, "not a RIFF file"),
+            WavError::NotWave => write!(f, "RIFF file is not WAVE"),
+            WavError::Truncated => write!(f, "file ends in the middle of a chunk"),
+            WavError::MissingFormat => write!(f, "no fmt chunk before the data chunk"),
+            WavError::MissingData => write!(f, "no data chunk"),
+            WavError::UnsupportedFormat(format) => write!(f, "unsupported format tag {:#06x}", format),
+            WavError::UnsupportedChannels(channels) => write!(f, "unsupported channel count {}", channels),
+            WavError::UnsupportedBitsPerSample(bits) => write!(f, "unsupported sample size of {} bits", bits),
+            WavError::BadBlockAlign => write!(f, "block align doesn't match channels and sample size"),
+        }
+    }
+}
+
+impl std::error::Error for WavError {}
+
+/// Decoded PCM audio, as interleaved 16-bit samples.
+pub struct Wav {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub samples: Arc<Vec<i16>>,
+}
+
+/// The audio in a WAV file. PCM is converted to 16-bit samples, IMA ADPCM is kept compressed
+/// and decoded as it plays.
+pub enum Audio {
+    Pcm(Wav),
+    Adpcm(Adpcm),
+}
+
+struct Format {
+    tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    block_align: u16,
+    bits_per_sample: u16,
+}
+
+impl Audio {
+    /// Walks the RIFF chunks of `bytes`, skipping any it doesn't understand. 8, 16 and 24-bit
+    /// mono or stereo PCM and mono IMA ADPCM are supported.
+    pub fn parse(bytes: &[u8]) -> Result<Self, WavError> {
+        Self::parse_with(bytes, |data| Data::Shared(data.into()))
+    }
+
+    /// Like `parse`, but IMA ADPCM is played straight from `bytes` rather than copied, for
+    /// sounds built into the firmware.
+    pub fn parse_static(bytes: &'static [u8]) -> Result<Self, WavError> {
+        Self::parse_with(bytes, Data::Static)
+    }
+
+    fn parse_with<'a>(bytes: &'a [u8], adpcm_data: impl FnOnce(&'a [u8]) -> Data) -> Result<Self, WavError> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" {
+            return Err(WavError::NotRiff);
+        }
+        if &bytes[8..12] != b"WAVE" {
+            return Err(WavError::NotWave);
+        }
+
+        let mut format = None;
+        // ADPCM files give their length in samples, since the last block is rarely full.
+        let mut fact_samples = None;
+        let mut rest = &bytes[12..];
+        while rest.len() >= 8 {
+            let id = &rest[0..4];
+            let size = read_u32(&rest[4..8]) as usize;
+            // A corrupt size can be anything, so don't let it wrap on a 32-bit target.
+            let body = size.checked_add(8)
+                .and_then(|end| rest.get(8..end))
+                .ok_or(WavError::Truncated)?;
+            match id {
+                b"fmt " => format = Some(Self::parse_format(body)?),
+                b"fact" if body.len() >= 4 => fact_samples = Some(read_u32(&body[0..4]) as usize),
+                b"data" => {
+                    let format = format.ok_or(WavError::MissingFormat)?;
+                    if format.tag == WAVE_FORMAT_IMA_ADPCM {
+                        let block_align = format.block_align as usize;
+                        let capacity = adpcm::samples_in(body.len(), block_align);
+                        return Ok(Audio::Adpcm(Adpcm {
+                            sample_rate: format.sample_rate,
+                            block_align,
+                            samples: fact_samples.map_or(capacity, |samples| samples.min(capacity)),
+                            data: adpcm_data(body),
+                        }));
+                    }
+                    return Ok(Audio::Pcm(Wav {
+                        channels: format.channels,
+                        sample_rate: format.sample_rate,
+                        samples: Arc::new(Self::convert_samples(body, format.bits_per_sample)),
+                    }));
+                },
+                _ => {},
+            }
+            // Chunks are padded to an even length.
+            let next = size.checked_add(8 + (size & 1));
+            rest = next.and_then(|next| rest.get(next..)).unwrap_or(&[]);
+        }
+        Err(WavError::MissingData)
+    }
+
+    fn parse_format(body: &[u8]) -> Result<Format, WavError> {
+        if body.len() < 16 {
+            return Err(WavError::Truncated);
+        }
+        let mut tag = read_u16(&body[0..2]);
+        let channels = read_u16(&body[2..4]);
+        let sample_rate = read_u32(&body[4..8]);
+        let block_align = read_u16(&body[12..14]);
+        let bits_per_sample = read_u16(&body[14..16]);
+
+        // Extensible files keep the real format tag at the start of the sub-format GUID.
+        if tag == WAVE_FORMAT_EXTENSIBLE {
+            let sub_format = body.get(24..26).ok_or(WavError::Truncated)?;
+            tag = read_u16(sub_format);
+        }
+        if tag == WAVE_FORMAT_IMA_ADPCM {
+            if channels != 1 {
+                return Err(WavError::UnsupportedChannels(channels));
+            }
+            if bits_per_sample != 4 {
+                return Err(WavError::UnsupportedBitsPerSample(bits_per_sample));
+            }
+            // The extra format bytes repeat how many samples fit in a block.
+            let samples_per_block = body.get(18..20).map(read_u16);
+            if block_align <= 4
+                || samples_per_block.is_some_and(|samples| samples as usize != adpcm::samples_per_block(block_align as usize))
+            {
+                return Err(WavError::BadBlockAlign);
+            }
+            return Ok(Format { tag, channels, sample_rate, block_align, bits_per_sample });
+        }
+        if tag != WAVE_FORMAT_PCM {
+            return Err(WavError::UnsupportedFormat(tag));
+        }
+        if channels != 1 && channels != 2 {
+            return Err(WavError::UnsupportedChannels(channels));
+        }
+        if !matches!(bits_per_sample, 8 | 16 | 24) {
+            return Err(WavError::UnsupportedBitsPerSample(bits_per_sample));
+        }
+        if block_align != channels * bits_per_sample / 8 {
+            return Err(WavError::BadBlockAlign);
+        }
+        Ok(Format { tag, channels, sample_rate, block_align, bits_per_sample })
+    }
+
+    fn convert_samples(data: &[u8], bits_per_sample: u16) -> Vec<i16> {
+        match bits_per_sample {
+            // 8-bit PCM is unsigned, centred on 128.
+            8 => data.iter().map(|sample| ((*sample as i16) - 128) << 8).collect(),
+            16 => data.chunks_exact(2).map(|sample| i16::from_le_bytes([sample[0], sample[1]])).collect(),
+            // Keep the top 16 bits of each 24-bit sample.
+            24 => data.chunks_exact(3).map(|sample| i16::from_le_bytes([sample[1], sample[2]])).collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn channels(&self) -> u16 {
+        match self {
+            Audio::Pcm(wav) => wav.channels,
+            Audio::Adpcm(_) => 1,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        match self {
+            Audio::Pcm(wav) => wav.sample_rate,
+            Audio::Adpcm(adpcm) => adpcm.sample_rate,
+        }
+    }
+
+    /// The whole thing as 16-bit samples, decoding it if it's compressed.
+    pub fn decode(&self) -> Vec<i16> {
+        match self {
+            Audio::Pcm(wav) => wav.samples.to_vec(),
+            Audio::Adpcm(adpcm) => adpcm.decode(),
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn riff(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+        for (id, chunk) in chunks {
+            body.extend_from_slice(*id);
+            body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            body.extend_from_slice(chunk);
+            if chunk.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        file.extend_from_slice(&body);
+        file
+    }
+
+    fn pcm_format(channels: u16, bits_per_sample: u16) -> Vec<u8> {
+        let block_align = channels * bits_per_sample / 8;
+        let mut format = Vec::new();
+        format.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+        format.extend_from_slice(&channels.to_le_bytes());
+        format.extend_from_slice(&16000u32.to_le_bytes());
+        format.extend_from_slice(&(16000 * block_align as u32).to_le_bytes());
+        format.extend_from_slice(&block_align.to_le_bytes());
+        format.extend_from_slice(&bits_per_sample.to_le_bytes());
+        format
+    }
+
+    fn pcm(bytes: &[u8]) -> Wav {
+        match Audio::parse(bytes) {
+            Ok(Audio::Pcm(wav)) => wav,
+            Ok(Audio::Adpcm(_)) => panic!("parsed as ADPCM"),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn parses_the_bundled_assets() {
+        let assets: [&[u8]; 3] = [
+            include_bytes!("assets/beep.wav"),
+            include_bytes!("assets/start.wav"),
+            include_bytes!("assets/microwave.wav"),
+        ];
+        for asset in assets {
+            let audio = Audio::parse(asset).unwrap();
+            assert_eq!((audio.channels(), audio.sample_rate()), (1, 16000));
+            assert!(audio.decode().len() > 4000);
+        }
+    }
+
+    #[test]
+    fn built_in_sounds_play_from_flash() {
+        static ASSET: &[u8] = include_bytes!("assets/beep.wav");
+        let Audio::Adpcm(clip) = Audio::parse_static(ASSET).unwrap() else {
+            panic!("beep.wav isn't ADPCM");
+        };
+        assert!(matches!(clip.data, Data::Static(data) if ASSET.as_ptr_range().contains(&data.as_ptr())));
+        assert!(matches!(Audio::parse(ASSET), Ok(Audio::Adpcm(Adpcm { data: Data::Shared(_), .. }))));
+    }
+
+    #[test]
+    fn skips_chunks_it_doesnt_know() {
+        let samples = [1i16, -2, 3].iter().flat_map(|sample| sample.to_le_bytes()).collect::<Vec<_>>();
+        let bytes = riff(&[
+            (b"LIST", b"INFOsome text"),
+            (b"fmt ", &pcm_format(1, 16)),
+            (b"junk", &[0; 10]),
+            (b"data", &samples),
+        ]);
+        assert_eq!(*pcm(&bytes).samples, vec![1, -2, 3]);
+    }
+
+    #[test]
+    fn steps_over_odd_sized_chunks_and_their_padding() {
+        let bytes = riff(&[(b"odd ", &[7; 3]), (b"fmt ", &pcm_format(1, 16)), (b"data", &[0x34, 0x12])]);
+        assert_eq!(*pcm(&bytes).samples, vec![0x1234]);
+    }
+
+    #[test]
+    fn converts_8_and_24_bit_samples() {
+        let bytes = riff(&[(b"fmt ", &pcm_format(1, 8)), (b"data", &[0, 128, 255])]);
+        assert_eq!(*pcm(&bytes).samples, vec![-32768, 0, 32512]);
+
+        let bytes = riff(&[(b"fmt ", &pcm_format(1, 24)), (b"data", &[0xFF, 0x34, 0x12, 0x00, 0x00, 0x80])]);
+        assert_eq!(*pcm(&bytes).samples, vec![0x1234, i16::MIN]);
+    }
+
+    #[test]
+    fn keeps_stereo_interleaved() {
+        let bytes = riff(&[(b"fmt ", &pcm_format(2, 16)), (b"data", &[1, 0, 2, 0, 3, 0, 4, 0])]);
+        let wav = pcm(&bytes);
+        assert_eq!(wav.channels, 2);
+        assert_eq!(*wav.samples, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let format = pcm_format(1, 16);
+        assert_eq!(Audio::parse(b"RIFX\0\0\0\0WAVE").err(), Some(WavError::NotRiff));
+        assert_eq!(Audio::parse(b"RIFF\0\0\0\0AVI ").err(), Some(WavError::NotWave));
+        assert_eq!(Audio::parse(&riff(&[(b"data", &[0, 0])])).err(), Some(WavError::MissingFormat));
+        assert_eq!(Audio::parse(&riff(&[(b"fmt ", &format)])).err(), Some(WavError::MissingData));
+        assert_eq!(Audio::parse(&riff(&[(b"fmt ", &format[..12])])).err(), Some(WavError::Truncated));
+
+        let mut cut = riff(&[(b"fmt ", &format), (b"data", &[0; 8])]);
+        cut.truncate(cut.len() - 2);
+        assert_eq!(Audio::parse(&cut).err(), Some(WavError::Truncated));
+    }
+
+    #[test]
+    fn survives_chunk_sizes_near_the_limit() {
+        for size in [u32::MAX, u32::MAX - 7, u32::MAX - 8] {
+            let mut bytes = riff(&[(b"fmt ", &pcm_format(1, 16))]);
+            bytes.extend_from_slice(b"data");
+            bytes.extend_from_slice(&size.to_le_bytes());
+            assert_eq!(Audio::parse(&bytes).err(), Some(WavError::Truncated));
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Reading logic analyzer CSV exports and turning them into mark/space pulses.
+
+use anyhow::{bail, Result};
+use crate::nec::Pulse;
+
+/// Marks shorter than this are individual carrier cycles rather than demodulated marks.
+const MAX_CARRIER_MARK_MICROS: f64 = 50.0;
+/// Gaps shorter than this between carrier cycles belong to the same mark.
+const MAX_CARRIER_GAP_MICROS: f64 = 100.0;
+
+struct Edge {
+    seconds: f64,
+    level: bool,
+}
+
+/// A capture converted to pulses. `carrier_hz` is set when the capture was taken on the LED
+/// side and still contained the carrier, rather than from a demodulating receiver.
+pub struct Capture {
+    pub pulses: Vec<Pulse>,
+    pub carrier_hz: Option<u32>,
+}
+
+impl Capture {
+    /// Parses a Saleae Logic or sigrok CSV export of a single channel.
+    ///
+    /// Saleae exports a row per transition with a time column. sigrok exports a row per sample
+    /// and only includes a time column when asked to, so the sample rate is read from its
+    /// comment header instead.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut sample_rate = None;
+        let mut has_time_column = false;
+        let mut edges: Vec<Edge> = Vec::new();
+        let mut sample = 0u64;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(comment) = line.strip_prefix(';') {
+                if let Some(rate) = comment.trim().strip_prefix("Samplerate:") {
+                    sample_rate = Some(parse_rate(rate.trim())?);
+                }
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            if fields[0].parse::<f64>().is_err() {
+                has_time_column = fields[0].to_ascii_lowercase().starts_with("time");
+                continue;
+            }
+
+            let (seconds, value) = match sample_rate {
+                Some(rate) if !has_time_column => (sample as f64 / rate, fields[0]),
+                _ => {
+                    let Some(value) = fields.get(1) else {
+                        bail!("row without a channel column: {}", line);
+                    };
+                    (fields[0].parse::<f64>()?, *value)
+                },
+            };
+            sample += 1;
+            let level = value != "0";
+            if edges.last().map_or(true, |edge| edge.level != level) {
+                edges.push(Edge { seconds, level });
+            }
+        }
+        if edges.len() < 2 {
+            bail!("capture has no transitions");
+        }
+
+        // Whatever the line sits at before the first transition is idle, the other level is a mark.
+        let idle = edges[0].level;
+        let spans: Vec<(bool, f64)> = edges.windows(2)
+            .map(|pair| (pair[0].level != idle, (pair[1].seconds - pair[0].seconds) * 1e6))
+            .skip_while(|(mark, _)| !mark)
+            .collect();
+        Ok(Self::demodulate(&spans))
+    }
+
+    fn demodulate(spans: &[(bool, f64)]) -> Self {
+        let carrier_marks = spans.iter()
+            .filter(|(mark, micros)| *mark && *micros < MAX_CARRIER_MARK_MICROS)
+            .count();
+        let marks = spans.iter().filter(|(mark, _)| *mark).count();
+        if carrier_marks * 2 < marks {
+            return Self {
+                pulses: spans.iter().map(|(mark, micros)| to_pulse(*mark, *micros)).collect(),
+                carrier_hz: None,
+            };
+        }
+
+        // Merge each burst of carrier cycles into a single mark, and measure the cycle period
+        // from the start of the first cycle to the start of the last one in each burst.
+        let mut pulses = Vec::new();
+        let mut cycles = 0;
+        let mut cycle_time = 0.0;
+        let mut burst = 0.0;
+        let mut burst_cycles = 0;
+        let mut last_mark = 0.0;
+        for (mark, micros) in spans {
+            if *mark {
+                burst += micros;
+                burst_cycles += 1;
+                last_mark = *micros;
+            } else if *micros < MAX_CARRIER_GAP_MICROS {
+                burst += micros;
+            } else {
+                if burst_cycles > 1 {
+                    cycles += burst_cycles - 1;
+                    cycle_time += burst - last_mark;
+                }
+                pulses.push(to_pulse(true, burst));
+                pulses.push(to_pulse(false, *micros));
+                burst = 0.0;
+                burst_cycles = 0;
+            }
+        }
+        if burst > 0.0 {
+            pulses.push(to_pulse(true, burst));
+        }
+        let carrier_hz = if cycles > 0 && cycle_time > 0.0 {
+            Some((cycles as f64 * 1e6 / cycle_time).round() as u32)
+        } else {
+            None
+        };
+        Self { pulses, carrier_hz }
+    }
+}
+
+fn to_pulse(mark: bool, micros: f64) -> Pulse {
+    Pulse {
+        mark,
+        micros: micros.round().min(u16::MAX as f64) as u16,
+    }
+}
+
+fn parse_rate(text: &str) -> Result<f64> {
+    let (number, unit) = text.split_once(' ').unwrap_or((text, "Hz"));
+    let scale = match unit {
+        "Hz" => 1.0,
+        "kHz" => 1e3,
+        "MHz" => 1e6,
+        "GHz" => 1e9,
+        _ => bail!("unknown sample rate unit {}", unit),
+    };
+    Ok(number.parse::<f64>()? * scale)
+}
@@ -0,0 +1,377 @@
+//! Converts a logic analyzer capture of an IR remote into codes the firmware can send.
+//!
+//! Reads Saleae Logic or sigrok CSV exports of either the IR LED or a demodulating receiver,
+//! splits the capture into frames, decodes each as NEC, Sony SIRC or RC5, and prints the
+//! result as text, Rust source for `Remote`, a JSON code table or a Flipper Zero `.ir` file.
+
+mod capture;
+// Shared with the firmware, which uses the parts of it this tool doesn't.
+#[path = "../../../src/nec.rs"]
+#[allow(dead_code)]
+mod nec;
+mod rc5;
+mod sony;
+
+use anyhow::{bail, Context, Result};
+use capture::Capture;
+use nec::{within_tolerance, Address, NecError, Pulse};
+use std::fmt::Write;
+
+/// Spaces at least this long separate frames. Every protocol's longest in-frame space is
+/// well under this, and every protocol's frame gap is well over it.
+const FRAME_GAP_MICROS: u16 = 8000;
+
+const USAGE: &str = "usage: ir-convert [--format text|rust|json|flipper] [--name NAME] <capture.csv>";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Rust,
+    Json,
+    Flipper,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Code {
+    Nec(nec::Frame),
+    Sony(sony::Frame),
+    Rc5(rc5::Frame),
+}
+
+enum Decoded {
+    Code(Code),
+    Repeat,
+    Malformed {
+        protocol: &'static str,
+        error: String,
+        pulses: Vec<Pulse>,
+    },
+}
+
+fn main() -> Result<()> {
+    let mut format = Format::Text;
+    let mut name = String::from("code");
+    let mut input = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("text") => Format::Text,
+                    Some("rust") => Format::Rust,
+                    Some("json") => Format::Json,
+                    Some("flipper") => Format::Flipper,
+                    _ => bail!(USAGE),
+                };
+            },
+            "--name" => name = args.next().context(USAGE)?,
+            _ if input.is_none() && !arg.starts_with("--") => input = Some(arg),
+            _ => bail!(USAGE),
+        }
+    }
+    let input = input.context(USAGE)?;
+
+    let text = std::fs::read_to_string(&input).with_context(|| format!("reading {}", input))?;
+    let capture = Capture::parse(&text).with_context(|| format!("parsing {}", input))?;
+    let raw = split_frames(&capture.pulses);
+    let frames: Vec<Decoded> = raw.iter().map(|pulses| decode(pulses)).collect();
+
+    let output = match format {
+        Format::Text => text_output(&capture, &frames),
+        Format::Rust => rust_output(&name, &raw, &frames),
+        Format::Json => json_output(&name, &frames),
+        Format::Flipper => flipper_output(&name, &capture, &frames),
+    };
+    print!("{}", output);
+
+    if frames.iter().any(|frame| matches!(frame, Decoded::Malformed { .. })) && format != Format::Text {
+        eprintln!("warning: capture contains malformed frames, run with --format text for details");
+    }
+    Ok(())
+}
+
+fn split_frames(pulses: &[Pulse]) -> Vec<&[Pulse]> {
+    pulses.split(|pulse| !pulse.mark && pulse.micros >= FRAME_GAP_MICROS)
+        .filter(|frame| !frame.is_empty())
+        .collect()
+}
+
+/// Picks the protocol from the length of the first mark, then decodes the frame with it.
+fn decode(pulses: &[Pulse]) -> Decoded {
+    let first = pulses[0].micros;
+    let (protocol, result) = if within_tolerance(first, nec::HEADER_MARK) {
+        match nec::Frame::decode(pulses) {
+            Err(NecError::Repeat) => return Decoded::Repeat,
+            result => ("NEC", result.map(Code::Nec).map_err(|err| err.to_string())),
+        }
+    } else if within_tolerance(first, sony::HEADER_MARK) {
+        ("Sony", sony::Frame::decode(pulses).map(Code::Sony).map_err(|err| err.to_string()))
+    } else if within_tolerance(first, rc5::HALF_BIT) || within_tolerance(first, rc5::HALF_BIT * 2) {
+        ("RC5", rc5::Frame::decode(pulses).map(Code::Rc5).map_err(|err| err.to_string()))
+    } else {
+        ("unknown", Err(format!("unrecognised {} µs leading mark", first)))
+    };
+    match result {
+        Ok(code) => Decoded::Code(code),
+        Err(error) => Decoded::Malformed { protocol, error, pulses: pulses.to_vec() },
+    }
+}
+
+/// The distinct codes in the capture, in the order they first appear. Holding a button sends
+/// the same code several times, so duplicates are dropped.
+fn distinct_codes(frames: &[Decoded]) -> Vec<Code> {
+    let mut codes = Vec::new();
+    for frame in frames {
+        if let Decoded::Code(code) = frame {
+            if !codes.contains(code) {
+                codes.push(*code);
+            }
+        }
+    }
+    codes
+}
+
+/// Appends `_2`, `_3`, ... when a capture holds more than one code.
+fn numbered(name: &str, index: usize) -> String {
+    if index == 0 {
+        name.to_string()
+    } else {
+        format!("{}_{}", name, index + 1)
+    }
+}
+
+fn describe(code: &Code) -> String {
+    match code {
+        Code::Nec(frame) => match frame.address {
+            Address::Standard(address) => format!("NEC address {:#04x} command {:#04x}", address, frame.command),
+            Address::Extended(address) => format!("extended NEC address {:#06x} command {:#04x}", address, frame.command),
+        },
+        Code::Sony(frame) => format!("Sony {}-bit address {:#04x} command {:#04x}", frame.bits, frame.address, frame.command),
+        Code::Rc5(frame) => format!("RC5 address {:#04x} command {:#04x} toggle {}", frame.address, frame.command, frame.toggle as u8),
+    }
+}
+
+fn text_output(capture: &Capture, frames: &[Decoded]) -> String {
+    let mut out = String::new();
+    match capture.carrier_hz {
+        Some(hz) => writeln!(out, "carrier: {:.1} kHz", hz as f32 / 1000.0).unwrap(),
+        None => writeln!(out, "carrier: none, capture is already demodulated").unwrap(),
+    }
+    for (i, frame) in frames.iter().enumerate() {
+        match frame {
+            Decoded::Code(code) => writeln!(out, "frame {}: {}", i + 1, describe(code)).unwrap(),
+            Decoded::Repeat => writeln!(out, "frame {}: NEC repeat", i + 1).unwrap(),
+            Decoded::Malformed { protocol, error, pulses } => {
+                writeln!(out, "frame {}: malformed {} frame of {} pulses: {}", i + 1, protocol, pulses.len(), error).unwrap()
+            },
+        }
+    }
+    out
+}
+
+/// Rust source for `src/remote.rs`. `Remote` only speaks NEC, so anything else is written out
+/// as the raw pulse list it used to be built from.
+fn rust_output(name: &str, raw: &[&[Pulse]], frames: &[Decoded]) -> String {
+    let name = name.to_ascii_uppercase();
+    let mut out = String::new();
+    let mut codes = Vec::new();
+    for (pulses, frame) in raw.iter().zip(frames) {
+        match frame {
+            Decoded::Code(code) if !codes.contains(code) => {
+                let name = numbered(&name, codes.len());
+                codes.push(*code);
+                match code {
+                    Code::Nec(frame) => match frame.address {
+                        Address::Standard(address) => writeln!(
+                            out, "const {}: nec::Frame = nec::Frame::new({:#04x}, {:#04x});", name, address, frame.command,
+                        ).unwrap(),
+                        Address::Extended(address) => writeln!(
+                            out, "const {}: nec::Frame = nec::Frame::extended({:#06x}, {:#04x});", name, address, frame.command,
+                        ).unwrap(),
+                    },
+                    code => {
+                        writeln!(out, "// {}: {}", name, describe(code)).unwrap();
+                        write_pulses(&mut out, pulses);
+                    },
+                }
+            },
+            Decoded::Malformed { protocol, .. } => {
+                writeln!(out, "// Undecoded {} frame", protocol).unwrap();
+                write_pulses(&mut out, pulses);
+            },
+            _ => {},
+        }
+    }
+    out
+}
+
+fn write_pulses(out: &mut String, pulses: &[Pulse]) {
+    for pulse in pulses {
+        let level = if pulse.mark { "high" } else { "low" };
+        writeln!(out, "&{}_pulse({})?,", level, pulse.micros).unwrap();
+    }
+}
+
+fn json_output(name: &str, frames: &[Decoded]) -> String {
+    let entries: Vec<String> = distinct_codes(frames).iter().enumerate().map(|(i, code)| {
+        let (protocol, address, command) = match code {
+            Code::Nec(frame) => match frame.address {
+                Address::Standard(address) => ("NEC", address as u16, frame.command),
+                Address::Extended(address) => ("NECext", address, frame.command),
+            },
+            Code::Sony(frame) => ("SIRC", frame.address, frame.command),
+            Code::Rc5(frame) => ("RC5", frame.address as u16, frame.command),
+        };
+        format!(
+            "  {{\"name\": \"{}\", \"protocol\": \"{}\", \"address\": {}, \"command\": {}}}",
+            numbered(name, i), protocol, address, command,
+        )
+    }).collect();
+    if entries.is_empty() {
+        return "[]\n".to_string();
+    }
+    format!("[\n{}\n]\n", entries.join(",\n"))
+}
+
+/// A Flipper Zero infrared signals file. Decoded codes are written as parsed signals and
+/// malformed frames as raw ones, so they can still be replayed.
+fn flipper_output(name: &str, capture: &Capture, frames: &[Decoded]) -> String {
+    let mut out = String::from("Filetype: IR signals file\nVersion: 1\n");
+    for (i, code) in distinct_codes(frames).iter().enumerate() {
+        let (protocol, address, command): (String, u32, u32) = match code {
+            Code::Nec(frame) => match frame.address {
+                Address::Standard(address) => ("NEC".into(), address as u32, frame.command as u32),
+                Address::Extended(address) => ("NECext".into(), address as u32, frame.command as u32),
+            },
+            Code::Sony(frame) => {
+                let protocol = if frame.bits == 12 { "SIRC".into() } else { format!("SIRC{}", frame.bits) };
+                (protocol, frame.address as u32, frame.command as u32)
+            },
+            Code::Rc5(frame) => ("RC5".into(), frame.address as u32, frame.command as u32),
+        };
+        writeln!(out, "#\nname: {}\ntype: parsed\nprotocol: {}", numbered(name, i), protocol).unwrap();
+        writeln!(out, "address: {}\ncommand: {}", flipper_bytes(address), flipper_bytes(command)).unwrap();
+    }
+    let frequency = capture.carrier_hz.unwrap_or(38000);
+    let mut raw = 0;
+    for frame in frames {
+        if let Decoded::Malformed { pulses, .. } = frame {
+            let data: Vec<String> = pulses.iter().map(|pulse| pulse.micros.to_string()).collect();
+            writeln!(out, "#\nname: {}_raw{}\ntype: raw", name, raw + 1).unwrap();
+            writeln!(out, "frequency: {}\nduty_cycle: 0.330000\ndata: {}", frequency, data.join(" ")).unwrap();
+            raw += 1;
+        }
+    }
+    out
+}
+
+/// Flipper writes values as four little endian hex bytes.
+fn flipper_bytes(value: u32) -> String {
+    value.to_le_bytes().iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_capture(text: &str) -> (Capture, Vec<Decoded>) {
+        let capture = Capture::parse(text).unwrap();
+        let frames = split_frames(&capture.pulses).into_iter().map(decode).collect();
+        (capture, frames)
+    }
+
+    fn rust_capture(name: &str, text: &str) -> String {
+        let capture = Capture::parse(text).unwrap();
+        let raw = split_frames(&capture.pulses);
+        let frames: Vec<Decoded> = raw.iter().map(|pulses| decode(pulses)).collect();
+        rust_output(name, &raw, &frames)
+    }
+
+    fn codes(frames: &[Decoded]) -> Vec<Option<Code>> {
+        frames.iter().map(|frame| match frame {
+            Decoded::Code(code) => Some(*code),
+            Decoded::Repeat => None,
+            Decoded::Malformed { error, .. } => panic!("malformed frame: {}", error),
+        }).collect()
+    }
+
+    #[test]
+    fn decodes_nec_from_a_receiver() {
+        let (capture, frames) = decode_capture(include_str!("../fixtures/nec_light_on.csv"));
+        assert_eq!(capture.carrier_hz, None);
+        assert_eq!(codes(&frames), [Some(Code::Nec(nec::LIGHT_ON)), None]);
+    }
+
+    #[test]
+    fn decodes_nec_and_measures_the_carrier_from_the_led() {
+        let (capture, frames) = decode_capture(include_str!("../fixtures/nec_light_off_led.csv"));
+        let carrier_hz = capture.carrier_hz.unwrap();
+        assert!((37500..38500).contains(&carrier_hz), "{} Hz", carrier_hz);
+        assert_eq!(codes(&frames), [Some(Code::Nec(nec::LIGHT_OFF))]);
+    }
+
+    #[test]
+    fn decodes_rc5() {
+        let (_, frames) = decode_capture(include_str!("../fixtures/rc5.csv"));
+        let held = Code::Rc5(rc5::Frame { toggle: true, address: 0x05, command: 0x35 });
+        let next = Code::Rc5(rc5::Frame { toggle: false, address: 0x05, command: 0x10 });
+        assert_eq!(codes(&frames), [Some(held), Some(held), Some(next)]);
+    }
+
+    #[test]
+    fn decodes_sirc() {
+        let (_, frames) = decode_capture(include_str!("../fixtures/sirc.csv"));
+        let held = Code::Sony(sony::Frame { bits: 12, address: 0x01, command: 0x15 });
+        let long = Code::Sony(sony::Frame { bits: 20, address: 0x1A3, command: 0x2C });
+        assert_eq!(codes(&frames), [Some(held), Some(held), Some(held), Some(long)]);
+    }
+
+    #[test]
+    fn reads_sigrok_exports_by_sample() {
+        // A 2 kHz export of a long mark, a space and a short mark.
+        let text = "; Samplerate: 2 kHz\nlogic\n1\n0\n0\n0\n1\n1\n0\n1\n";
+        let capture = Capture::parse(text).unwrap();
+        assert_eq!(capture.pulses, [Pulse::mark(1500), Pulse::space(1000), Pulse::mark(500)]);
+    }
+
+    #[test]
+    fn rust_output_writes_nec_constants_once() {
+        assert_eq!(
+            rust_capture("light_on", include_str!("../fixtures/nec_light_on.csv")),
+            "const LIGHT_ON: nec::Frame = nec::Frame::new(0x00, 0x45);\n",
+        );
+
+        let pulses = nec::Frame::extended(0x1234, 0x07).encode();
+        let frames = [decode(&pulses)];
+        assert_eq!(
+            rust_output("dim", &[&pulses], &frames),
+            "const DIM: nec::Frame = nec::Frame::extended(0x1234, 0x07);\n",
+        );
+    }
+
+    #[test]
+    fn rust_output_lists_other_protocols_as_pulses() {
+        let output = rust_capture("tv", include_str!("../fixtures/sirc.csv"));
+        let lines: Vec<&str> = output.lines().collect();
+        // Each distinct code gets a comment and its frame's pulses: the header and two per bit,
+        // less the last space, which is the gap to the next frame.
+        assert_eq!(lines[0], "// TV: Sony 12-bit address 0x01 command 0x15");
+        assert_eq!(lines[26], "// TV_2: Sony 20-bit address 0x1a3 command 0x2c");
+        assert_eq!(lines.len(), 27 + 1 + 2 * 20);
+        for (i, line) in lines.iter().enumerate().filter(|(i, _)| *i != 0 && *i != 26) {
+            let level = if i % 2 == 1 { "high" } else { "low" };
+            assert!(line.starts_with(&format!("&{}_pulse(", level)), "line {}: {}", i, line);
+        }
+    }
+
+    #[test]
+    fn rust_output_lists_malformed_frames_as_pulses() {
+        let pulses = vec![Pulse::mark(9000), Pulse::space(3000), Pulse::mark(560)];
+        let frames = [decode(&pulses)];
+        assert!(matches!(frames[0], Decoded::Malformed { protocol: "NEC", .. }));
+        assert_eq!(
+            rust_output("light_on", &[&pulses], &frames),
+            "// Undecoded NEC frame\n&high_pulse(9000)?,\n&low_pulse(3000)?,\n&high_pulse(560)?,\n",
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! Philips RC5: 14 Manchester coded bits of 1778 µs, most significant bit first. A one is a
+//! space followed by a mark, a zero is a mark followed by a space. The bits are two start bits,
+//! a toggle bit, a 5-bit address and a 6-bit command. The second start bit doubles as an
+//! inverted seventh command bit in extended RC5.
+
+use anyhow::{bail, Result};
+use crate::nec::Pulse;
+
+pub const HALF_BIT: u16 = 889;
+const BITS: usize = 14;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    pub toggle: bool,
+    pub address: u8,
+    pub command: u8,
+}
+
+impl Frame {
+    pub fn decode(pulses: &[Pulse]) -> Result<Self> {
+        // The first half of the first start bit is a space, so it never shows up in a capture.
+        let mut halves = vec![false];
+        for pulse in pulses {
+            let count = (pulse.micros as f32 / HALF_BIT as f32).round() as usize;
+            let expected = count as i32 * HALF_BIT as i32;
+            if !matches!(count, 1 | 2) || (pulse.micros as i32 - expected).abs() > HALF_BIT as i32 * 3 / 10 {
+                bail!("{} µs is not one or two RC5 half bits", pulse.micros);
+            }
+            halves.extend(std::iter::repeat(pulse.mark).take(count));
+        }
+        // Likewise a trailing space after a zero.
+        if halves.len() % 2 == 1 {
+            halves.push(false);
+        }
+        if halves.len() != BITS * 2 {
+            bail!("{} half bits is not an RC5 frame", halves.len());
+        }
+
+        let mut value = 0u16;
+        for (i, half) in halves.chunks(2).enumerate() {
+            let bit = match half {
+                [false, true] => 1,
+                [true, false] => 0,
+                _ => bail!("bit {} has no transition in its middle", i),
+            };
+            value = (value << 1) | bit;
+        }
+        if value >> 13 != 1 {
+            bail!("first start bit is not set");
+        }
+        let field = (value >> 12) & 1;
+        Ok(Self {
+            toggle: (value >> 11) & 1 == 1,
+            address: ((value >> 6) & 0x1F) as u8,
+            command: ((value & 0x3F) | ((field ^ 1) << 6)) as u8,
+        })
+    }
+}
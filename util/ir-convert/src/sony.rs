@@ -0,0 +1,55 @@
+//! Sony SIRC: a 2.4 ms header mark, then 12, 15 or 20 bits sent least significant bit first.
+//! Every bit starts with a 600 µs space, followed by a 1200 µs mark for a one or a 600 µs mark
+//! for a zero. The first 7 bits are the command and the rest are the address.
+
+use anyhow::{bail, Result};
+use crate::nec::Pulse;
+use crate::nec::within_tolerance;
+
+pub const HEADER_MARK: u16 = 2400;
+const SPACE: u16 = 600;
+const ONE_MARK: u16 = 1200;
+const ZERO_MARK: u16 = 600;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    pub bits: u8,
+    pub address: u16,
+    pub command: u8,
+}
+
+impl Frame {
+    pub fn decode(pulses: &[Pulse]) -> Result<Self> {
+        let Some((header, rest)) = pulses.split_first() else {
+            bail!("empty frame");
+        };
+        if !header.mark || !within_tolerance(header.micros, HEADER_MARK) {
+            bail!("missing Sony header");
+        }
+
+        let mut value = 0u32;
+        let mut bits = 0u8;
+        for (i, pair) in rest.chunks(2).enumerate() {
+            let [space, mark] = pair else {
+                bail!("frame ends after the space of bit {}", i);
+            };
+            if space.mark || !within_tolerance(space.micros, SPACE) || !mark.mark {
+                bail!("bit {} has invalid timing", i);
+            }
+            if within_tolerance(mark.micros, ONE_MARK) {
+                value |= 1 << i;
+            } else if !within_tolerance(mark.micros, ZERO_MARK) {
+                bail!("bit {} has invalid timing", i);
+            }
+            bits += 1;
+        }
+        if !matches!(bits, 12 | 15 | 20) {
+            bail!("{} bits is not a Sony frame length", bits);
+        }
+        Ok(Self {
+            bits,
+            address: (value >> 7) as u16,
+            command: (value & 0x7F) as u8,
+        })
+    }
+}
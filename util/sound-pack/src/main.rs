@@ -0,0 +1,253 @@
+//! Builds the sound pack image the firmware loads its sounds from at boot.
+//!
+//! Takes a WAV file for each event to replace, checks the firmware can play it, compresses it
+//! to IMA ADPCM and writes them with their manifest to a FAT image the size of the `sounds`
+//! partition, ready to flash at that partition's offset. `sound-pack encode` compresses a
+//! single file, which is how the built-in sounds in `src/assets` are made from the originals in
+//! `assets`.
+
+// Shared with the firmware, which uses the parts of them this tool doesn't.
+#[path = "../../../src/adpcm.rs"]
+#[allow(dead_code)]
+mod adpcm;
+#[path = "../../../src/manifest.rs"]
+#[allow(dead_code)]
+mod manifest;
+#[path = "../../../src/wav.rs"]
+#[allow(dead_code)]
+mod wav;
+
+use adpcm::Adpcm;
+use anyhow::{bail, Context, Result};
+use fatfs::{FileSystem, FormatVolumeOptions, FsOptions};
+use manifest::{Entry, Manifest, SoundEvent, MANIFEST_FILE};
+use std::io::{Cursor, Write};
+use wav::Audio;
+
+/// The size of the `sounds` partition in `partitions.csv`.
+const DEFAULT_SIZE: u32 = 0x100000;
+/// ESP-IDF mounts a read-only FAT partition without wear levelling, straight onto flash
+/// sectors, so the volume's sectors have to match them.
+const SECTOR_SIZE: u16 = 4096;
+
+const USAGE: &str = "usage: sound-pack [--size BYTES] [--output FILE] [--pcm] <event>=<file.wav>[@gain]...
+       sound-pack encode <in.wav> <out.wav>
+events: beep, start, running, done, door
+--pcm keeps PCM files as they are instead of compressing them";
+
+struct Sound {
+    entry: Entry,
+    bytes: Vec<u8>,
+    seconds: f32,
+}
+
+fn main() -> Result<()> {
+    let mut size = DEFAULT_SIZE;
+    let mut output = String::from("sounds.bin");
+    let mut compress = true;
+    let mut sounds: Vec<Sound> = Vec::new();
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("encode") {
+        args.next();
+        let (Some(input), Some(output), None) = (args.next(), args.next(), args.next()) else {
+            bail!(USAGE);
+        };
+        return encode(&input, &output);
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => size = parse_size(&args.next().context(USAGE)?)?,
+            "--output" => output = args.next().context(USAGE)?,
+            "--pcm" => compress = false,
+            _ if arg.contains('=') => {
+                let sound = load(&arg, compress)?;
+                if sounds.iter().any(|other| other.entry.event == sound.entry.event) {
+                    bail!("{} is given more than once", sound.entry.event.name());
+                }
+                sounds.push(sound);
+            },
+            _ => bail!(USAGE),
+        }
+    }
+    if sounds.is_empty() {
+        bail!(USAGE);
+    }
+
+    let image = build_image(&sounds, size)?;
+    std::fs::write(&output, image).with_context(|| format!("writing {}", output))?;
+    for sound in &sounds {
+        println!(
+            "{:<8} {:<12} {:>5.1} s {:>8} bytes  gain {}",
+            sound.entry.event.name(),
+            sound.entry.file,
+            sound.seconds,
+            sound.bytes.len(),
+            sound.entry.gain,
+        );
+    }
+    println!("wrote {} ({} bytes)", output, size);
+    Ok(())
+}
+
+/// Reads and checks the sound in an `event=file.wav[@gain]` argument, compressing it if it's
+/// PCM and `compress` is set.
+fn load(arg: &str, compress: bool) -> Result<Sound> {
+    let (name, path) = arg.split_once('=').context(USAGE)?;
+    let event = SoundEvent::from_name(name).with_context(|| format!("unknown event {:?}\n{}", name, USAGE))?;
+    let (path, gain) = match path.rsplit_once('@') {
+        Some((path, gain)) => (path, gain.parse::<f32>().with_context(|| format!("bad gain {:?}", gain))?),
+        None => (path, 1.0),
+    };
+    if !(0.0..=manifest::MAX_GAIN).contains(&gain) {
+        bail!("{}: gain has to be from 0 to {}", path, manifest::MAX_GAIN);
+    }
+
+    let bytes = std::fs::read(path).with_context(|| format!("reading {}", path))?;
+    let audio = Audio::parse(&bytes).with_context(|| format!("parsing {}", path))?;
+    if !manifest::is_playable(audio.channels(), audio.sample_rate()) {
+        bail!(
+            "{} is {} channel {} Hz, the firmware only plays mono {} Hz",
+            path,
+            audio.channels(),
+            audio.sample_rate(),
+            manifest::SAMPLE_RATE,
+        );
+    }
+    let (bytes, seconds) = match audio {
+        Audio::Pcm(wav) if compress => {
+            let clip = Adpcm::encode(&wav.samples, wav.sample_rate, adpcm::BLOCK_ALIGN);
+            (adpcm_wav(&clip), wav.samples.len() as f32 / wav.sample_rate as f32)
+        },
+        Audio::Pcm(wav) => (bytes, wav.samples.len() as f32 / wav.sample_rate as f32),
+        Audio::Adpcm(clip) => (bytes, clip.samples as f32 / clip.sample_rate as f32),
+    };
+    // Named after the event, which always makes an 8.3 name.
+    let file = format!("{}.WAV", event.name().to_uppercase());
+    Ok(Sound { entry: Entry { event, file, gain }, bytes, seconds })
+}
+
+/// Compresses a mono PCM WAV file to IMA ADPCM.
+fn encode(input: &str, output: &str) -> Result<()> {
+    let bytes = std::fs::read(input).with_context(|| format!("reading {}", input))?;
+    let wav = match Audio::parse(&bytes).with_context(|| format!("parsing {}", input))? {
+        Audio::Pcm(wav) if wav.channels == 1 => wav,
+        Audio::Pcm(wav) => bail!("{} is {} channel, only mono can be compressed", input, wav.channels),
+        Audio::Adpcm(_) => bail!("{} is already IMA ADPCM", input),
+    };
+    let clip = Adpcm::encode(&wav.samples, wav.sample_rate, adpcm::BLOCK_ALIGN);
+    let encoded = adpcm_wav(&clip);
+    std::fs::write(output, &encoded).with_context(|| format!("writing {}", output))?;
+    println!(
+        "{}: {} samples, {} bytes to {} ({:.1} dB SNR)",
+        output,
+        clip.samples,
+        bytes.len(),
+        encoded.len(),
+        snr(&wav.samples, &clip.decode()),
+    );
+    Ok(())
+}
+
+/// A WAV file holding `clip`, with the `fact` chunk giving its exact length.
+fn adpcm_wav(clip: &Adpcm) -> Vec<u8> {
+    let samples_per_block = clip.samples_per_block() as u32;
+    let block_align = clip.block_align as u32;
+    let mut format = Vec::new();
+    format.extend_from_slice(&0x0011u16.to_le_bytes());
+    format.extend_from_slice(&1u16.to_le_bytes());
+    format.extend_from_slice(&clip.sample_rate.to_le_bytes());
+    format.extend_from_slice(&(clip.sample_rate * block_align / samples_per_block).to_le_bytes());
+    format.extend_from_slice(&(block_align as u16).to_le_bytes());
+    format.extend_from_slice(&4u16.to_le_bytes());
+    // The extra bytes, which for IMA ADPCM are just the samples per block.
+    format.extend_from_slice(&2u16.to_le_bytes());
+    format.extend_from_slice(&(samples_per_block as u16).to_le_bytes());
+
+    let mut chunks = Vec::new();
+    for (id, body) in [(b"fmt ", &format[..]), (b"fact", &(clip.samples as u32).to_le_bytes()), (b"data", &clip.data[..])] {
+        chunks.extend_from_slice(id);
+        chunks.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        chunks.extend_from_slice(body);
+        // Chunks start on even offsets.
+        if body.len() % 2 == 1 {
+            chunks.push(0);
+        }
+    }
+    let mut file = Vec::with_capacity(chunks.len() + 12);
+    file.extend_from_slice(b"RIFF");
+    file.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+    file.extend_from_slice(b"WAVE");
+    file.extend_from_slice(&chunks);
+    file
+}
+
+/// How far above the compression noise `original` is, in decibels.
+fn snr(original: &[i16], decoded: &[i16]) -> f64 {
+    let (signal, noise) = original.iter().zip(decoded).fold((0.0, 0.0), |(signal, noise), (a, b)| {
+        let (a, b) = (*a as f64, *b as f64);
+        (signal + a * a, noise + (a - b) * (a - b))
+    });
+    10.0 * (signal / noise.max(1.0)).log10()
+}
+
+fn parse_size(size: &str) -> Result<u32> {
+    let parsed = match size.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => size.parse(),
+    };
+    let size = parsed.with_context(|| format!("bad size {:?}", size))?;
+    if size == 0 || size % SECTOR_SIZE as u32 != 0 {
+        bail!("the size has to be a whole number of {} byte flash sectors", SECTOR_SIZE);
+    }
+    Ok(size)
+}
+
+fn build_image(sounds: &[Sound], size: u32) -> Result<Vec<u8>> {
+    let manifest = Manifest { entries: sounds.iter().map(|sound| sound.entry.clone()).collect() };
+    let text = manifest.to_text();
+    // Whatever goes in has to come back out the same on the board.
+    if Manifest::parse(&text).as_ref() != Ok(&manifest) {
+        bail!("the manifest doesn't read back as written");
+    }
+
+    let mut image = Cursor::new(vec![0u8; size as usize]);
+    let options = FormatVolumeOptions::new()
+        .bytes_per_sector(SECTOR_SIZE)
+        .bytes_per_cluster(SECTOR_SIZE as u32)
+        .volume_label(*b"SOUNDS     ");
+    fatfs::format_volume(&mut image, options).with_context(|| format!("formatting a {} byte image", size))?;
+    {
+        let fs = FileSystem::new(&mut image, FsOptions::new())?;
+        let mut files = vec![(MANIFEST_FILE, text.as_bytes())];
+        files.extend(sounds.iter().map(|sound| (sound.entry.file.as_str(), sound.bytes.as_slice())));
+        for (name, bytes) in files {
+            fs.root_dir().create_file(name)
+                .and_then(|mut file| file.write_all(bytes))
+                .with_context(|| format!("adding {}, the sounds don't fit in {} bytes", name, size))?;
+        }
+        fs.unmount()?;
+    }
+    Ok(image.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_sounds_are_encoded_from_their_sources() {
+        let sounds: [(&[u8], &[u8]); 3] = [
+            (include_bytes!("../assets/beep.wav"), include_bytes!("../../../src/assets/beep.wav")),
+            (include_bytes!("../assets/start.wav"), include_bytes!("../../../src/assets/start.wav")),
+            (include_bytes!("../assets/microwave.wav"), include_bytes!("../../../src/assets/microwave.wav")),
+        ];
+        for (source, built_in) in sounds {
+            let Ok(Audio::Pcm(wav)) = Audio::parse(source) else {
+                panic!("the sources should be PCM");
+            };
+            let clip = Adpcm::encode(&wav.samples, wav.sample_rate, adpcm::BLOCK_ALIGN);
+            assert!(adpcm_wav(&clip) == built_in, "rerun `sound-pack encode` on the sources");
+            assert!(snr(&wav.samples, &clip.decode()) > 25.0);
+        }
+    }
+}